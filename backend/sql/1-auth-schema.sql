@@ -20,7 +20,8 @@ create table user_data_t(
   creator_user_id bigint not null references user_t(user_id),
   dateofbirth bigint not null,
   username text not null,
-  realname text not null
+  realname text not null,
+  country text -- ISO 3166 country or region code, determines the age of parental consent
 );
 
 create view recent_user_data_v as
@@ -49,16 +50,38 @@ create table email_t(
   verification_challenge_key_hash text not null references verification_challenge_t(verification_challenge_key_hash)
 );
 
-create view recent_own_email_v as
+drop table if exists email_removal_t cascade;
+create table email_removal_t(
+  email_id bigint not null primary key references email_t(email_id),
+  creation_time bigint not null default extract(epoch from now()) * 1000
+);
+
+drop table if exists email_primary_t cascade;
+create table email_primary_t(
+  email_primary_id bigserial primary key,
+  creation_time bigint not null default extract(epoch from now()) * 1000,
+  creator_user_id bigint not null references user_t(user_id),
+  email_id bigint not null references email_t(email_id)
+);
+
+-- every own email the user still holds
+create view own_email_v as
+  select e.* from email_t e
+  join verification_challenge_t vc using(verification_challenge_key_hash)
+  where vc.to_parent = false
+  and e.email_id not in (select email_id from email_removal_t);
+
+-- the own email notifications are sent to
+-- a primary email can't be removed, so this always points at a held email
+create view primary_email_v as
   with maxids as (
-    select max(email_id) email_id 
-    from email_t e
-    join verification_challenge_t vc using(verification_challenge_key_hash)
-    where vc.to_parent = false
-    group by vc.creator_user_id
+    select max(email_primary_id) email_primary_id
+    from email_primary_t
+    group by creator_user_id
   )
-  select e.* from email_t e
-  inner join maxids using(email_id);
+  select e.* from own_email_v e
+  join email_primary_t ep using(email_id)
+  inner join maxids using(email_primary_id);
 
 
 create view recent_parent_email_v as
@@ -84,7 +107,7 @@ create table password_t(
   password_id bigserial primary key,
   creation_time bigint not null default extract(epoch from now()) * 1000,
   creator_user_id bigint not null references user_t(user_id),
-  password_hash text not null,
+  password_hash text not null, -- argon2, or bcrypt, PBKDF2 or scrypt for an imported user until they next log in
   password_reset_key_hash text references password_reset_t(password_reset_key_hash)  -- only valid if change was made by RESET
 );
 
@@ -117,3 +140,236 @@ create view recent_api_key_v as
   ) maxids
   on maxids.id = ak.api_key_id;
 
+
+drop table if exists notification_preference_t cascade;
+create table notification_preference_t(
+  notification_preference_id bigserial primary key,
+  creation_time bigint not null default extract(epoch from now()) * 1000,
+  creator_user_id bigint not null references user_t(user_id),
+  security_notifications bool not null,
+  locale text -- language emails are sent in, null for the server default
+);
+
+create view recent_notification_preference_v as
+  select np.* from notification_preference_t np
+  inner join (
+    select max(notification_preference_id) id 
+    from notification_preference_t 
+    group by creator_user_id
+  ) maxids
+  on maxids.id = np.notification_preference_id;
+
+drop table if exists device_t cascade;
+create table device_t(
+  device_id bigserial primary key,
+  creation_time bigint not null default extract(epoch from now()) * 1000,
+  creator_user_id bigint not null references user_t(user_id),
+  device_hash text not null
+);
+
+drop table if exists security_notification_t cascade;
+create table security_notification_t(
+  security_notification_key_hash text not null primary key,
+  creation_time bigint not null default extract(epoch from now()) * 1000,
+  creator_user_id bigint not null references user_t(user_id),
+  security_notification_kind bigint not null, -- NEW_LOGIN, PASSWORD_CHANGE, PASSWORD_RESET, EMAIL_CHANGE
+  email text not null
+);
+
+drop table if exists account_deletion_t cascade;
+create table account_deletion_t(
+  account_deletion_id bigserial primary key,
+  creation_time bigint not null default extract(epoch from now()) * 1000,
+  creator_user_id bigint not null references user_t(user_id),
+  account_deletion_kind bigint not null, -- PENDING, CANCEL, COMPLETE
+  scheduled_time bigint not null -- only valid if account_deletion_kind == PENDING
+);
+
+create view recent_account_deletion_v as
+  select ad.* from account_deletion_t ad
+  inner join (
+    select max(account_deletion_id) id 
+    from account_deletion_t 
+    group by creator_user_id
+  ) maxids
+  on maxids.id = ad.account_deletion_id;
+
+drop table if exists parent_permission_t cascade;
+create table parent_permission_t(
+  parent_permission_id bigserial primary key,
+  creation_time bigint not null default extract(epoch from now()) * 1000,
+  creator_user_id bigint not null references user_t(user_id), -- the child
+  verification_challenge_key_hash text not null references verification_challenge_t(verification_challenge_key_hash), -- the parent's email
+  parent_permission_kind bigint not null, -- GRANT, REVOKE
+  terms_version text -- only valid if parent_permission_kind == GRANT, null if granted before terms were recorded
+);
+
+create view recent_parent_permission_v as
+  select pp.* from parent_permission_t pp
+  inner join (
+    select max(parent_permission_id) id 
+    from parent_permission_t 
+    group by creator_user_id
+  ) maxids
+  on maxids.id = pp.parent_permission_id;
+
+drop table if exists parent_access_t cascade;
+create table parent_access_t(
+  parent_access_key_hash text not null primary key,
+  creation_time bigint not null default extract(epoch from now()) * 1000,
+  -- an hour after creation, or when the permission lapses for links in reminder emails
+  expiry_time bigint not null,
+  email text not null
+);
+
+drop table if exists parent_permission_reminder_t cascade;
+create table parent_permission_reminder_t(
+  parent_permission_id bigint not null primary key references parent_permission_t(parent_permission_id),
+  creation_time bigint not null default extract(epoch from now()) * 1000
+);
+
+drop table if exists email_change_t cascade;
+create table email_change_t(
+  email_change_key_hash text not null primary key, -- the key in the revert link sent to the old address
+  creation_time bigint not null default extract(epoch from now()) * 1000,
+  creator_user_id bigint not null references user_t(user_id),
+  old_verification_challenge_key_hash text not null references verification_challenge_t(verification_challenge_key_hash),
+  new_verification_challenge_key_hash text not null references verification_challenge_t(verification_challenge_key_hash)
+);
+
+drop table if exists email_change_revert_t cascade;
+create table email_change_revert_t(
+  email_change_key_hash text not null primary key references email_change_t(email_change_key_hash),
+  creation_time bigint not null default extract(epoch from now()) * 1000
+);
+
+-- emails waiting to be delivered, written in the same transaction as the row they are about
+-- a row is deleted once the email is delivered, or moved to email_dead_letter_t once it is given up on
+-- the contents hold the keys in links and codes, so they are never kept past expiry_time
+drop table if exists email_outbox_t cascade;
+create table email_outbox_t(
+  email_outbox_id bigserial primary key,
+  creation_time bigint not null default extract(epoch from now()) * 1000,
+  expiry_time bigint not null, -- when the links in the email stop working, so it isn't worth sending
+  destination text not null,
+  topic text not null,
+  title text not null,
+  content text not null,
+  content_plaintext text not null,
+  attempts bigint not null default 0,
+  next_attempt_time bigint not null default extract(epoch from now()) * 1000,
+  last_error text -- null until an attempt fails
+);
+
+-- only says which email was given up on and why, the contents are dropped along with the keys in them
+drop table if exists email_dead_letter_t cascade;
+create table email_dead_letter_t(
+  email_outbox_id bigint not null primary key,
+  creation_time bigint not null default extract(epoch from now()) * 1000, -- when it was given up on
+  outbox_creation_time bigint not null,
+  destination text not null,
+  topic text not null,
+  title text not null,
+  attempts bigint not null,
+  error text not null
+);
+
+-- addresses that bounced or complained, and emails are no longer sent to
+drop table if exists email_suppression_t cascade;
+create table email_suppression_t(
+  email_suppression_id bigserial primary key,
+  creation_time bigint not null default extract(epoch from now()) * 1000,
+  email text not null,
+  email_suppression_kind bigint not null -- BOUNCE, COMPLAINT, CLEAR
+);
+
+create view recent_email_suppression_v as
+  select es.* from email_suppression_t es
+  inner join (
+    select max(email_suppression_id) id 
+    from email_suppression_t 
+    group by lower(email)
+  ) maxids
+  on maxids.id = es.email_suppression_id;
+
+-- a short code sent along with a verification challenge's link, for devices that can't open the link
+drop table if exists verification_code_t cascade;
+create table verification_code_t(
+  verification_challenge_key_hash text not null primary key references verification_challenge_t(verification_challenge_key_hash),
+  creation_time bigint not null default extract(epoch from now()) * 1000,
+  api_key_hash text not null, -- only the api key that asked for the code can use it
+  verification_code_hash text not null
+);
+
+drop table if exists verification_code_attempt_t cascade;
+create table verification_code_attempt_t(
+  verification_code_attempt_id bigserial primary key,
+  creation_time bigint not null default extract(epoch from now()) * 1000,
+  verification_challenge_key_hash text not null references verification_code_t(verification_challenge_key_hash),
+  success bool not null
+);
+
+-- a code texted to a phone, either to verify the number or as a second factor when logging in
+drop table if exists phone_challenge_t cascade;
+create table phone_challenge_t(
+  phone_challenge_id bigserial primary key,
+  creation_time bigint not null default extract(epoch from now()) * 1000,
+  creator_user_id bigint not null references user_t(user_id),
+  phone text not null, -- E.164, eg +15555550123
+  phone_challenge_kind bigint not null, -- VERIFY, SECOND_FACTOR
+  phone_code_hash text not null
+);
+
+drop table if exists phone_challenge_attempt_t cascade;
+create table phone_challenge_attempt_t(
+  phone_challenge_attempt_id bigserial primary key,
+  creation_time bigint not null default extract(epoch from now()) * 1000,
+  phone_challenge_id bigint not null references phone_challenge_t(phone_challenge_id),
+  success bool not null
+);
+
+-- a user has at most one verified phone
+drop table if exists phone_t cascade;
+create table phone_t(
+  phone_id bigserial primary key,
+  creation_time bigint not null default extract(epoch from now()) * 1000,
+  creator_user_id bigint not null references user_t(user_id),
+  phone_challenge_id bigint references phone_challenge_t(phone_challenge_id) -- null if the phone was removed
+);
+
+create view recent_phone_v as
+  select p.* from phone_t p
+  inner join (
+    select max(phone_id) id 
+    from phone_t 
+    group by creator_user_id
+  ) maxids
+  on maxids.id = p.phone_id;
+
+drop table if exists second_factor_t cascade;
+create table second_factor_t(
+  second_factor_id bigserial primary key,
+  creation_time bigint not null default extract(epoch from now()) * 1000,
+  creator_user_id bigint not null references user_t(user_id),
+  enabled bool not null -- if true, logging in also needs a code texted to the user's phone
+);
+
+create view recent_second_factor_v as
+  select sf.* from second_factor_t sf
+  inner join (
+    select max(second_factor_id) id 
+    from second_factor_t 
+    group by creator_user_id
+  ) maxids
+  on maxids.id = sf.second_factor_id;
+
+-- each time the holder of an api key proved who they are, by logging in or by entering their password or a second factor again
+-- changing the password or email, or deleting the account, needs a recent one
+drop table if exists reauthentication_t cascade;
+create table reauthentication_t(
+  reauthentication_id bigserial primary key,
+  creation_time bigint not null default extract(epoch from now()) * 1000,
+  creator_user_id bigint not null references user_t(user_id),
+  api_key_hash text not null,
+  reauthentication_kind bigint not null -- PASSWORD, SECOND_FACTOR
+);
@@ -0,0 +1,24 @@
+-- Parent permission used to be implied by the existence of a parent email.
+-- Run this once on databases created before parent_permission_t existed,
+-- so that children who already have a parent's permission keep it.
+
+\c authenticator
+
+INSERT INTO parent_permission_t(
+  creation_time,
+  creator_user_id,
+  verification_challenge_key_hash,
+  parent_permission_kind,
+  terms_version
+)
+SELECT
+  e.creation_time,
+  vc.creator_user_id,
+  e.verification_challenge_key_hash,
+  0, -- GRANT
+  NULL
+FROM recent_parent_email_v e
+JOIN verification_challenge_t vc USING(verification_challenge_key_hash)
+WHERE NOT EXISTS (
+  SELECT 1 FROM parent_permission_t pp WHERE pp.creator_user_id = vc.creator_user_id
+);
@@ -0,0 +1,37 @@
+-- Users used to have a single own email, the most recently verified one.
+-- Run this once on databases created before email_primary_t existed,
+-- so that each user's current email becomes their primary, and the ones it replaced are removed.
+
+\c authenticator
+
+WITH maxids AS (
+  SELECT max(e.email_id) email_id, vc.creator_user_id
+  FROM email_t e
+  JOIN verification_challenge_t vc USING(verification_challenge_key_hash)
+  WHERE vc.to_parent = false
+  GROUP BY vc.creator_user_id
+)
+INSERT INTO email_primary_t(
+  creation_time,
+  creator_user_id,
+  email_id
+)
+SELECT
+  e.creation_time,
+  maxids.creator_user_id,
+  e.email_id
+FROM email_t e
+INNER JOIN maxids USING(email_id)
+WHERE NOT EXISTS (
+  SELECT 1 FROM email_primary_t ep WHERE ep.creator_user_id = maxids.creator_user_id
+);
+
+INSERT INTO email_removal_t(
+  email_id
+)
+SELECT e.email_id
+FROM email_t e
+JOIN verification_challenge_t vc USING(verification_challenge_key_hash)
+WHERE vc.to_parent = false
+AND e.email_id NOT IN (SELECT email_id FROM email_primary_t)
+AND e.email_id NOT IN (SELECT email_id FROM email_removal_t);
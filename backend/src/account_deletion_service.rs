@@ -0,0 +1,210 @@
+use super::db_types::*;
+use auth_service_api::request::AccountDeletionKind;
+use std::convert::TryInto;
+use tokio_postgres::GenericClient;
+
+impl From<tokio_postgres::row::Row> for AccountDeletion {
+  // select * from account_deletion order only, otherwise it will fail
+  fn from(row: tokio_postgres::row::Row) -> AccountDeletion {
+    AccountDeletion {
+      account_deletion_id: row.get("account_deletion_id"),
+      creation_time: row.get("creation_time"),
+      creator_user_id: row.get("creator_user_id"),
+      // means that there's a mismatch between the values of the enum and the value stored in the column
+      account_deletion_kind: (row.get::<&str, i64>("account_deletion_kind") as u8)
+        .try_into()
+        .unwrap(),
+      scheduled_time: row.get("scheduled_time"),
+    }
+  }
+}
+
+pub async fn add(
+  con: &mut impl GenericClient,
+  creator_user_id: i64,
+  account_deletion_kind: AccountDeletionKind,
+  scheduled_time: i64,
+) -> Result<AccountDeletion, tokio_postgres::Error> {
+  let row = con
+    .query_one(
+      "INSERT INTO
+       account_deletion_t(
+         creator_user_id,
+         account_deletion_kind,
+         scheduled_time
+       )
+       VALUES ($1, $2, $3)
+       RETURNING account_deletion_id, creation_time
+      ",
+      &[
+        &creator_user_id,
+        &(account_deletion_kind.clone() as i64),
+        &scheduled_time,
+      ],
+    )
+    .await?;
+
+  Ok(AccountDeletion {
+    account_deletion_id: row.get(0),
+    creation_time: row.get(1),
+    creator_user_id,
+    account_deletion_kind,
+    scheduled_time,
+  })
+}
+
+// gets most recent account deletion by user_id
+pub async fn get_by_user_id(
+  con: &mut impl GenericClient,
+  user_id: i64,
+) -> Result<Option<AccountDeletion>, tokio_postgres::Error> {
+  let result = con
+    .query_opt(
+      "SELECT ad.* FROM recent_account_deletion_v ad
+       WHERE ad.creator_user_id = $1
+      ",
+      &[&user_id],
+    )
+    .await?
+    .map(|x| x.into());
+
+  Ok(result)
+}
+
+// gets all pending deletions whose grace period has run out
+pub async fn get_pending_due(
+  con: &mut impl GenericClient,
+  current_time: i64,
+) -> Result<Vec<AccountDeletion>, tokio_postgres::Error> {
+  let results = con
+    .query(
+      "SELECT ad.* FROM recent_account_deletion_v ad
+       WHERE ad.account_deletion_kind = $1
+       AND ad.scheduled_time <= $2
+       ORDER BY ad.account_deletion_id
+      ",
+      &[&(AccountDeletionKind::Pending as i64), &current_time],
+    )
+    .await?
+    .into_iter()
+    .map(|x| x.into())
+    .collect();
+
+  Ok(results)
+}
+
+// gets the full history of the user's account deletions
+pub async fn get_all_by_user_id(
+  con: &mut impl GenericClient,
+  user_id: i64,
+) -> Result<Vec<AccountDeletion>, tokio_postgres::Error> {
+  let results = con
+    .query(
+      "SELECT ad.* FROM account_deletion_t ad
+       WHERE ad.creator_user_id = $1
+       ORDER BY ad.account_deletion_id
+      ",
+      &[&user_id],
+    )
+    .await?
+    .into_iter()
+    .map(|x| x.into())
+    .collect();
+
+  Ok(results)
+}
+
+// deletes everything we know about the user, in an order that respects foreign keys
+// the user_t row and the account deletion history are kept so that ids are never reused
+pub async fn purge_user(
+  con: &mut impl GenericClient,
+  user_id: i64,
+) -> Result<(), tokio_postgres::Error> {
+  let statements = [
+    "DELETE FROM reauthentication_t WHERE creator_user_id = $1",
+    "DELETE FROM api_key_t WHERE creator_user_id = $1",
+    "DELETE FROM password_t WHERE creator_user_id = $1",
+    "DELETE FROM password_reset_t WHERE creator_user_id = $1",
+    "DELETE FROM parent_permission_reminder_t ppr USING parent_permission_t pp
+     WHERE ppr.parent_permission_id = pp.parent_permission_id
+     AND pp.creator_user_id = $1",
+    "DELETE FROM parent_permission_t WHERE creator_user_id = $1",
+    "DELETE FROM email_change_revert_t ecr USING email_change_t ec
+     WHERE ecr.email_change_key_hash = ec.email_change_key_hash
+     AND ec.creator_user_id = $1",
+    "DELETE FROM email_change_t WHERE creator_user_id = $1",
+    "DELETE FROM email_primary_t WHERE creator_user_id = $1",
+    "DELETE FROM email_removal_t er USING email_t e, verification_challenge_t vc
+     WHERE er.email_id = e.email_id
+     AND e.verification_challenge_key_hash = vc.verification_challenge_key_hash
+     AND vc.creator_user_id = $1",
+    "DELETE FROM email_t e USING verification_challenge_t vc
+     WHERE e.verification_challenge_key_hash = vc.verification_challenge_key_hash
+     AND vc.creator_user_id = $1",
+    "DELETE FROM verification_code_attempt_t vca USING verification_challenge_t vc
+     WHERE vca.verification_challenge_key_hash = vc.verification_challenge_key_hash
+     AND vc.creator_user_id = $1",
+    "DELETE FROM verification_code_t vco USING verification_challenge_t vc
+     WHERE vco.verification_challenge_key_hash = vc.verification_challenge_key_hash
+     AND vc.creator_user_id = $1",
+    "DELETE FROM verification_challenge_t WHERE creator_user_id = $1",
+    "DELETE FROM security_notification_t WHERE creator_user_id = $1",
+    "DELETE FROM second_factor_t WHERE creator_user_id = $1",
+    "DELETE FROM phone_t WHERE creator_user_id = $1",
+    "DELETE FROM phone_challenge_attempt_t pca USING phone_challenge_t pc
+     WHERE pca.phone_challenge_id = pc.phone_challenge_id
+     AND pc.creator_user_id = $1",
+    "DELETE FROM phone_challenge_t WHERE creator_user_id = $1",
+    "DELETE FROM device_t WHERE creator_user_id = $1",
+    "DELETE FROM notification_preference_t WHERE creator_user_id = $1",
+    "DELETE FROM user_data_t WHERE creator_user_id = $1",
+  ];
+
+  for statement in statements {
+    con.execute(statement, &[&user_id]).await?;
+  }
+
+  Ok(())
+}
+
+pub async fn query(
+  con: &mut impl GenericClient,
+  props: auth_service_api::request::AccountDeletionViewProps,
+) -> Result<Vec<AccountDeletion>, tokio_postgres::Error> {
+  let sql = [
+    if props.only_recent {
+      "SELECT ad.* FROM recent_account_deletion_v ad"
+    } else {
+      "SELECT ad.* FROM account_deletion_t ad"
+    },
+    " WHERE 1 = 1",
+    " AND ($1::bigint[] IS NULL OR ad.account_deletion_id = ANY($1))",
+    " AND ($2::bigint   IS NULL OR ad.creation_time >= $2)",
+    " AND ($3::bigint   IS NULL OR ad.creation_time <= $3)",
+    " AND ($4::bigint[] IS NULL OR ad.creator_user_id = ANY($4))",
+    " AND ($5::bigint[] IS NULL OR ad.account_deletion_kind = ANY($5))",
+    " ORDER BY ad.account_deletion_id",
+  ]
+  .join("\n");
+
+  let stmnt = con.prepare(&sql).await?;
+
+  let results = con
+    .query(
+      &stmnt,
+      &[
+        &props.account_deletion_id,
+        &props.min_creation_time,
+        &props.max_creation_time,
+        &props.creator_user_id,
+        &props
+          .account_deletion_kind
+          .map(|x| x.into_iter().map(|e| e as i64).collect::<Vec<i64>>()),
+      ],
+    )
+    .await?
+    .into_iter()
+    .map(|row| row.into())
+    .collect();
+  Ok(results)
+}
@@ -58,14 +58,15 @@ pub async fn add(
   })
 }
 
-pub async fn get_by_api_key_hash(
+// the hashes are every hash the key may have been stored under, see token_hasher.rs
+pub async fn get_by_api_key_hashes(
   con: &mut impl GenericClient,
-  api_key_hash: &str,
+  api_key_hashes: &[String],
 ) -> Result<Option<ApiKey>, tokio_postgres::Error> {
   let result = con
     .query_opt(
-      "SELECT * FROM recent_api_key_v WHERE api_key_hash=$1",
-      &[&api_key_hash],
+      "SELECT * FROM recent_api_key_v WHERE api_key_hash=ANY($1)",
+      &[&api_key_hashes],
     )
     .await?
     .map(|x| x.into());
@@ -73,6 +74,87 @@ pub async fn get_by_api_key_hash(
   Ok(result)
 }
 
+// gets all keys of the user that are neither cancelled nor expired
+pub async fn get_current_by_creator_user_id(
+  con: &mut impl GenericClient,
+  creator_user_id: i64,
+  current_time: i64,
+) -> Result<Vec<ApiKey>, tokio_postgres::Error> {
+  let results = con
+    .query(
+      "SELECT ak.* FROM recent_api_key_v ak
+       WHERE ak.creator_user_id = $1
+       AND ak.api_key_kind != $2
+       AND ak.creation_time + ak.duration > $3
+       ORDER BY ak.api_key_id
+      ",
+      &[
+        &creator_user_id,
+        &(auth_service_api::request::ApiKeyKind::Cancel as i64),
+        &current_time,
+      ],
+    )
+    .await?
+    .into_iter()
+    .map(|x| x.into())
+    .collect();
+
+  Ok(results)
+}
+
+// gets the full history of the user's api keys, including cancellations
+pub async fn get_all_by_user_id(
+  con: &mut impl GenericClient,
+  user_id: i64,
+) -> Result<Vec<ApiKey>, tokio_postgres::Error> {
+  let results = con
+    .query(
+      "SELECT ak.* FROM api_key_t ak
+       WHERE ak.creator_user_id = $1
+       ORDER BY ak.api_key_id
+      ",
+      &[&user_id],
+    )
+    .await?
+    .into_iter()
+    .map(|x| x.into())
+    .collect();
+
+  Ok(results)
+}
+
+// replaces each of the user's current keys of one kind with a key of another kind
+// the replacement keeps the hash and expires at the same time as the original
+pub async fn change_current_kind(
+  con: &mut impl GenericClient,
+  creator_user_id: i64,
+  from_kind: auth_service_api::request::ApiKeyKind,
+  to_kind: auth_service_api::request::ApiKeyKind,
+  current_time: i64,
+) -> Result<Vec<ApiKey>, tokio_postgres::Error> {
+  let api_keys = get_current_by_creator_user_id(con, creator_user_id, current_time).await?;
+
+  let mut changed = vec![];
+  for api_key in api_keys.into_iter() {
+    if api_key.api_key_kind.clone() as i64 != from_kind.clone() as i64 {
+      continue;
+    }
+
+    changed.push(
+      add(
+        con,
+        creator_user_id,
+        api_key.api_key_hash,
+        to_kind.clone(),
+        api_key.creation_time + api_key.duration - current_time,
+      )
+      .await?,
+    );
+  }
+
+  Ok(changed)
+}
+
 pub async fn query(
   con: &mut impl GenericClient,
   props: auth_service_api::request::ApiKeyViewProps,
@@ -0,0 +1,348 @@
+use sha1::{Digest, Sha1};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+// identifies the file, and its layout in case it ever changes
+static MAGIC: &[u8; 8] = b"BPFILT01";
+
+// A bloom filter of the SHA-1 hashes of passwords known from public breaches, so they can be refused without
+// sending anything over the network. It never misses a breached password, but a small fraction of other
+// passwords (the false positive rate it was built with) are refused too.
+//
+// The file is the magic bytes, the number of bits and of hash functions as little endian u64s, then the bits.
+// It is built from a downloaded hash list with the build-breached-password-filter subcommand.
+#[derive(Clone)]
+pub struct BreachedPasswordFilter {
+  num_bits: u64,
+  num_hashes: u64,
+  bits: Vec<u8>,
+}
+
+impl std::fmt::Debug for BreachedPasswordFilter {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("BreachedPasswordFilter")
+      .field("num_bits", &self.num_bits)
+      .field("num_hashes", &self.num_hashes)
+      .finish()
+  }
+}
+
+#[derive(Clone, Debug)]
+pub struct BuildReport {
+  pub num_files: usize,
+  pub num_lines: u64,
+  pub num_added: u64,
+  pub num_bits: u64,
+  pub num_hashes: u64,
+}
+
+impl BreachedPasswordFilter {
+  // sized so that holding num_entries hashes gives about the false positive rate
+  fn with_capacity(num_entries: u64, false_positive_rate: f64) -> BreachedPasswordFilter {
+    let num_entries = num_entries.max(1) as f64;
+    let ln2 = std::f64::consts::LN_2;
+
+    let num_bits = (-num_entries * false_positive_rate.ln() / (ln2 * ln2)).ceil() as u64;
+    let num_bits = num_bits.max(64).div_ceil(8) * 8;
+    let num_hashes = ((num_bits as f64 / num_entries) * ln2)
+      .round()
+      .clamp(1.0, 30.0) as u64;
+
+    BreachedPasswordFilter {
+      num_bits,
+      num_hashes,
+      bits: vec![0; (num_bits / 8) as usize],
+    }
+  }
+
+  pub fn load(path: &Path) -> Result<BreachedPasswordFilter, Box<dyn std::error::Error + 'static>> {
+    let contents = std::fs::read(path)?;
+
+    if contents.len() < 24 || &contents[0..8] != MAGIC {
+      return Err(format!("{} is not a breached password filter", path.display()).into());
+    }
+
+    let num_bits = u64::from_le_bytes(contents[8..16].try_into()?);
+    let num_hashes = u64::from_le_bytes(contents[16..24].try_into()?);
+
+    if num_bits == 0 || num_hashes == 0 || (contents.len() - 24) as u64 != num_bits.div_ceil(8) {
+      return Err(format!("{} is truncated or corrupt", path.display()).into());
+    }
+
+    Ok(BreachedPasswordFilter {
+      num_bits,
+      num_hashes,
+      bits: contents[24..].to_vec(),
+    })
+  }
+
+  fn save(&self, path: &Path) -> Result<(), std::io::Error> {
+    let mut writer = BufWriter::new(std::fs::File::create(path)?);
+    writer.write_all(MAGIC)?;
+    writer.write_all(&self.num_bits.to_le_bytes())?;
+    writer.write_all(&self.num_hashes.to_le_bytes())?;
+    writer.write_all(&self.bits)?;
+    writer.flush()
+  }
+
+  pub fn contains(&self, password: &str) -> bool {
+    let hash: [u8; 20] = Sha1::digest(password.as_bytes()).into();
+    self
+      .bit_indexes(&hash)
+      .all(|x| self.bits[(x / 8) as usize] & (1 << (x % 8)) != 0)
+  }
+
+  fn insert(&mut self, hash: &[u8; 20]) {
+    for x in self.bit_indexes(hash) {
+      self.bits[(x / 8) as usize] |= 1 << (x % 8);
+    }
+  }
+
+  // the hash is already uniformly distributed, so its bytes are used directly for double hashing
+  fn bit_indexes(&self, hash: &[u8; 20]) -> impl Iterator<Item = u64> {
+    let h1 = u64::from_le_bytes(hash[0..8].try_into().unwrap());
+    let h2 = u64::from_le_bytes(hash[8..16].try_into().unwrap()) | 1;
+    let num_bits = self.num_bits;
+    (0..self.num_hashes).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % num_bits)
+  }
+}
+
+// Builds a filter file from a downloaded list of SHA-1 password hashes, in either form the lists come in:
+//   a single file with a line of <40 hex digit hash>:<count> for each password
+//   a directory of k-anonymity range files, named by the first 5 hex digits of the hash (eg 5BAA6.txt),
+//   each with a line of <remaining 35 hex digits>:<count> for each password
+// The count is optional. Passwords seen fewer than min_count times are left out, which shrinks the filter.
+pub fn build(
+  input: &Path,
+  output: &Path,
+  false_positive_rate: f64,
+  min_count: u64,
+) -> Result<BuildReport, Box<dyn std::error::Error + 'static>> {
+  if !(false_positive_rate > 0.0 && false_positive_rate < 1.0) {
+    return Err("the false positive rate must be between 0 and 1".into());
+  }
+
+  let sources = list_sources(input)?;
+
+  // read everything twice, once to size the filter and once to fill it, rather than holding it all in memory
+  let mut num_lines = 0;
+  let mut num_added = 0;
+  for_each_hash(&sources, min_count, |_| num_added += 1, &mut num_lines)?;
+
+  let mut filter = BreachedPasswordFilter::with_capacity(num_added, false_positive_rate);
+  for_each_hash(&sources, min_count, |x| filter.insert(&x), &mut 0)?;
+
+  filter.save(output)?;
+
+  Ok(BuildReport {
+    num_files: sources.len(),
+    num_lines,
+    num_added,
+    num_bits: filter.num_bits,
+    num_hashes: filter.num_hashes,
+  })
+}
+
+// each file to read, and the hash prefix its lines leave out
+fn list_sources(input: &Path) -> Result<Vec<(PathBuf, String)>, std::io::Error> {
+  if !input.is_dir() {
+    return Ok(vec![(input.to_owned(), String::new())]);
+  }
+
+  let mut sources = vec![];
+  for entry in std::fs::read_dir(input)? {
+    let path = entry?.path();
+    let prefix = match path.file_stem().and_then(|x| x.to_str()) {
+      Some(x) if x.len() == 5 && x.chars().all(|c| c.is_ascii_hexdigit()) => x.to_owned(),
+      // not a range file
+      _ => continue,
+    };
+    sources.push((path, prefix));
+  }
+  sources.sort();
+
+  Ok(sources)
+}
+
+fn for_each_hash(
+  sources: &[(PathBuf, String)],
+  min_count: u64,
+  mut f: impl FnMut([u8; 20]),
+  num_lines: &mut u64,
+) -> Result<(), Box<dyn std::error::Error + 'static>> {
+  for (path, prefix) in sources {
+    let reader = BufReader::new(std::fs::File::open(path)?);
+    for (i, line) in reader.lines().enumerate() {
+      let line = line?;
+      let line = line.trim();
+      if line.is_empty() {
+        continue;
+      }
+      *num_lines += 1;
+
+      let (hash, count) = parse_line(prefix, line)
+        .ok_or_else(|| format!("{}:{}: invalid line", path.display(), i + 1))?;
+
+      if count >= min_count {
+        f(hash);
+      }
+    }
+  }
+
+  Ok(())
+}
+
+fn parse_line(prefix: &str, line: &str) -> Option<([u8; 20], u64)> {
+  let (hex, count) = match line.split_once(':') {
+    Some((hex, count)) => (hex, count.trim().parse().ok()?),
+    None => (line, 1),
+  };
+
+  let hex = format!("{}{}", prefix, hex.trim());
+  if hex.len() != 40 || !hex.is_ascii() {
+    return None;
+  }
+
+  let mut hash = [0; 20];
+  for (i, byte) in hash.iter_mut().enumerate() {
+    *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+  }
+
+  Some((hash, count))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn sha1_hex(password: &str) -> String {
+    Sha1::digest(password.as_bytes())
+      .iter()
+      .map(|x| format!("{:02X}", x))
+      .collect()
+  }
+
+  // a fresh scratch directory per test, since they run in parallel
+  fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+      "breached-password-filter-{}-{}",
+      name,
+      std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+  }
+
+  #[test]
+  fn parses_lines() {
+    let (hash, count) = parse_line("", "5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:9545824").unwrap();
+    assert_eq!(hash[0..3], [0x5b, 0xaa, 0x61]);
+    assert_eq!(count, 9545824);
+
+    // a range file leaves off the prefix, and the count is optional
+    let (ranged, count) = parse_line("5BAA6", "1e4c9b93f3f0682250b6cf8331b7ee68fd8").unwrap();
+    assert_eq!(ranged, hash);
+    assert_eq!(count, 1);
+
+    assert!(parse_line("", "5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD").is_none());
+    assert!(parse_line("", "ZBAA61E4C9B93F3F0682250B6CF8331B7EE68FD8").is_none());
+    assert!(parse_line("", "5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:many").is_none());
+  }
+
+  #[test]
+  fn never_misses_and_rarely_false_positive() {
+    let mut filter = BreachedPasswordFilter::with_capacity(1000, 0.01);
+    for i in 0..1000 {
+      filter.insert(&Sha1::digest(format!("breached{}", i).as_bytes()).into());
+    }
+
+    assert!((0..1000).all(|i| filter.contains(&format!("breached{}", i))));
+
+    let false_positives = (0..10000)
+      .filter(|i| filter.contains(&format!("unbreached{}", i)))
+      .count();
+    assert!(false_positives < 300, "{} false positives", false_positives);
+  }
+
+  #[test]
+  fn builds_from_a_single_file() {
+    let dir = scratch_dir("single");
+    let input = dir.join("hashes.txt");
+    let output = dir.join("filter.bin");
+    std::fs::write(
+      &input,
+      format!(
+        "{}:100\n\n{}:1\n",
+        sha1_hex("password"),
+        sha1_hex("rarely used")
+      ),
+    )
+    .unwrap();
+
+    let report = build(&input, &output, 0.001, 2).unwrap();
+    assert_eq!(report.num_files, 1);
+    assert_eq!(report.num_lines, 2);
+    assert_eq!(report.num_added, 1);
+
+    let filter = BreachedPasswordFilter::load(&output).unwrap();
+    assert_eq!(filter.num_bits, report.num_bits);
+    assert_eq!(filter.num_hashes, report.num_hashes);
+    assert!(filter.contains("password"));
+    assert!(!filter.contains("rarely used"));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn builds_from_range_files() {
+    let dir = scratch_dir("ranges");
+    let input = dir.join("ranges");
+    let output = dir.join("filter.bin");
+    std::fs::create_dir(&input).unwrap();
+    for password in ["password", "letmein"] {
+      let hex = sha1_hex(password);
+      std::fs::write(
+        input.join(format!("{}.txt", &hex[..5])),
+        format!("{}:5\n", &hex[5..]),
+      )
+      .unwrap();
+    }
+    std::fs::write(input.join("README.txt"), "not a range file").unwrap();
+
+    let report = build(&input, &output, 0.001, 1).unwrap();
+    assert_eq!(report.num_files, 2);
+    assert_eq!(report.num_added, 2);
+
+    let filter = BreachedPasswordFilter::load(&output).unwrap();
+    assert!(filter.contains("password"));
+    assert!(filter.contains("letmein"));
+    assert!(!filter.contains("correct horse battery staple"));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn rejects_bad_input() {
+    let dir = scratch_dir("bad");
+    let input = dir.join("hashes.txt");
+    let output = dir.join("filter.bin");
+
+    std::fs::write(&input, "not a hash\n").unwrap();
+    assert!(build(&input, &output, 0.001, 1).is_err());
+    assert!(build(&input, &output, 1.0, 1).is_err());
+
+    std::fs::write(&output, b"BPFILT00").unwrap();
+    assert!(BreachedPasswordFilter::load(&output).is_err());
+
+    // claims more bits than it has
+    let mut truncated = MAGIC.to_vec();
+    truncated.extend_from_slice(&128u64.to_le_bytes());
+    truncated.extend_from_slice(&3u64.to_le_bytes());
+    truncated.extend_from_slice(&[0; 8]);
+    std::fs::write(&output, truncated).unwrap();
+    assert!(BreachedPasswordFilter::load(&output).is_err());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+}
@@ -0,0 +1,250 @@
+use super::db_types::ParentPermission;
+use super::db_types::UserData;
+use auth_service_api::request::ParentPermissionKind;
+use std::collections::HashMap;
+
+static MILLIS_PER_DAY: i64 = 24 * 60 * 60 * 1000;
+
+// The age below which a parent must consent, for jurisdictions whose age differs from COPPA's
+// or where we want to be explicit. Operators can change these, see ParentalConsentAges.
+// Keyed by ISO 3166-1 alpha-2 country code.
+static PARENTAL_CONSENT_AGES: &[(&str, i64)] = &[
+  ("AT", 14),
+  ("BE", 13),
+  ("BG", 14),
+  ("CY", 14),
+  ("CZ", 15),
+  ("DE", 16),
+  ("DK", 13),
+  ("EE", 13),
+  ("ES", 14),
+  ("FI", 13),
+  ("FR", 15),
+  ("GB", 13),
+  ("GR", 15),
+  ("HR", 16),
+  ("HU", 16),
+  ("IE", 16),
+  ("IS", 13),
+  ("IT", 14),
+  ("LI", 16),
+  ("LT", 14),
+  ("LU", 16),
+  ("LV", 13),
+  ("MT", 13),
+  ("NL", 16),
+  ("NO", 13),
+  ("PL", 16),
+  ("PT", 13),
+  ("RO", 16),
+  ("SE", 13),
+  ("SI", 15),
+  ("SK", 16),
+  ("US", 13),
+];
+
+// converts days since the unix epoch to a (year, month, day) civil date in UTC
+// see: http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+  let z = days + 719468;
+  let era = z.div_euclid(146097);
+  let doe = z.rem_euclid(146097);
+  let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+  let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+  let mp = (5 * doy + 2) / 153;
+  let day = doy - (153 * mp + 2) / 5 + 1;
+  let month = if mp < 10 { mp + 3 } else { mp - 9 };
+  let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+  (year, month, day)
+}
+
+// the number of birthdays a person born at `dateofbirth` has had by `time`
+// someone born on Feb 29 has their birthday on Mar 1 in non leap years
+pub fn age_in_years(dateofbirth: i64, time: i64) -> i64 {
+  let (birth_year, birth_month, birth_day) =
+    civil_from_days(dateofbirth.div_euclid(MILLIS_PER_DAY));
+  let (year, month, day) = civil_from_days(time.div_euclid(MILLIS_PER_DAY));
+
+  let age = year - birth_year;
+  if (month, day) < (birth_month, birth_day) {
+    age - 1
+  } else {
+    age
+  }
+}
+
+// The ages of parental consent in use, built at startup from the table above, then
+// --parental-consent-ages, which replaces the table's age for any country it lists.
+// Codes can also name a region within a country (eg "ES-CT"), which is looked up before its country.
+// Anywhere not listed falls back to --parental-consent-age.
+#[derive(Clone, Debug)]
+pub struct ParentalConsentAges {
+  ages: HashMap<String, i64>,
+  default_age: i64,
+}
+
+impl ParentalConsentAges {
+  // parses overrides like "DE=14;ES-CT=16"
+  pub fn new(overrides: &str, default_age: i64) -> Result<ParentalConsentAges, String> {
+    let mut ages: HashMap<String, i64> = PARENTAL_CONSENT_AGES
+      .iter()
+      .map(|(code, age)| (code.to_string(), *age))
+      .collect();
+
+    for entry in overrides
+      .split(';')
+      .map(|x| x.trim())
+      .filter(|x| !x.is_empty())
+    {
+      let (code, age) = entry
+        .split_once('=')
+        .ok_or_else(|| format!("parental consent age `{}` is missing an `=`", entry))?;
+      let age = age
+        .trim()
+        .parse()
+        .map_err(|_| format!("parental consent age `{}` isn't a number", entry))?;
+      ages.insert(code.trim().to_uppercase(), age);
+    }
+
+    Ok(ParentalConsentAges { ages, default_age })
+  }
+
+  // the age of parental consent for a country, or region within a country (eg "ES" or "ES-CT")
+  pub fn for_country(&self, country: Option<&str>) -> i64 {
+    let country = match country {
+      Some(country) => country.to_uppercase(),
+      None => return self.default_age,
+    };
+
+    let country_code = country.split('-').next().unwrap_or(&country);
+
+    self
+      .ages
+      .get(&country)
+      .or_else(|| self.ages.get(country_code))
+      .copied()
+      .unwrap_or(self.default_age)
+  }
+}
+
+// whether the user is too young to use the service without a parent's permission
+pub fn needs_parent_permission(
+  user_data: &UserData,
+  parental_consent_ages: &ParentalConsentAges,
+  time: i64,
+) -> bool {
+  age_in_years(user_data.dateofbirth, time)
+    < parental_consent_ages.for_country(user_data.country.as_deref())
+}
+
+// whether the parent permission is a grant that hasn't lapsed yet
+pub fn is_parent_permission_current(
+  parent_permission: &ParentPermission,
+  parental_consent_validity: i64,
+  time: i64,
+) -> bool {
+  matches!(
+    parent_permission.parent_permission_kind,
+    ParentPermissionKind::Grant
+  ) && parent_permission.creation_time + parental_consent_validity > time
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // the inverse of civil_from_days, midnight UTC on a date
+  // see: http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+  fn millis(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    (era * 146097 + doe - 719468) * MILLIS_PER_DAY
+  }
+
+  #[test]
+  fn civil_from_days_matches_known_dates() {
+    assert_eq!(civil_from_days(0), (1970, 1, 1));
+    assert_eq!(civil_from_days(-1), (1969, 12, 31));
+    assert_eq!(
+      civil_from_days(951782400000 / MILLIS_PER_DAY),
+      (2000, 2, 29)
+    );
+    assert_eq!(millis(2000, 2, 29), 951782400000);
+    for days in -100_000..100_000 {
+      let (year, month, day) = civil_from_days(days);
+      assert_eq!(millis(year, month, day), days * MILLIS_PER_DAY);
+    }
+  }
+
+  #[test]
+  fn age_counts_whole_years() {
+    let birth = millis(2010, 6, 15);
+    assert_eq!(age_in_years(birth, millis(2023, 6, 14)), 12);
+    assert_eq!(age_in_years(birth, millis(2023, 6, 15)), 13);
+    // the time of day doesn't matter
+    assert_eq!(age_in_years(birth, millis(2023, 6, 15) - 1), 12);
+    assert_eq!(age_in_years(birth + 1000, millis(2023, 6, 15)), 13);
+  }
+
+  #[test]
+  fn age_before_the_epoch() {
+    assert_eq!(age_in_years(millis(1960, 1, 1), millis(1969, 12, 31)), 9);
+    assert_eq!(age_in_years(millis(1960, 1, 1), millis(1970, 1, 1)), 10);
+  }
+
+  #[test]
+  fn feb_29_birthday_in_non_leap_years() {
+    let birth = millis(2000, 2, 29);
+    assert_eq!(age_in_years(birth, millis(2013, 2, 28)), 12);
+    assert_eq!(age_in_years(birth, millis(2013, 3, 1)), 13);
+  }
+
+  #[test]
+  fn feb_29_birthday_in_leap_years() {
+    let birth = millis(2000, 2, 29);
+    assert_eq!(age_in_years(birth, millis(2012, 2, 28)), 11);
+    assert_eq!(age_in_years(birth, millis(2012, 2, 29)), 12);
+    assert_eq!(age_in_years(birth, millis(2016, 2, 29)), 16);
+  }
+
+  #[test]
+  fn table_ages_and_default() {
+    let ages = ParentalConsentAges::new("", 13).unwrap();
+    assert_eq!(ages.for_country(Some("DE")), 16);
+    assert_eq!(ages.for_country(Some("de")), 16);
+    assert_eq!(ages.for_country(Some("ES-CT")), 14);
+    assert_eq!(ages.for_country(Some("BR")), 13);
+    assert_eq!(ages.for_country(None), 13);
+
+    // the default only applies outside the table
+    let ages = ParentalConsentAges::new("", 18).unwrap();
+    assert_eq!(ages.for_country(Some("DE")), 16);
+    assert_eq!(ages.for_country(Some("BR")), 18);
+  }
+
+  #[test]
+  fn overrides_replace_the_table() {
+    let ages = ParentalConsentAges::new("de=14; ES-CT=16;BR=12", 13).unwrap();
+    assert_eq!(ages.for_country(Some("DE")), 14);
+    assert_eq!(ages.for_country(Some("BR")), 12);
+    assert_eq!(ages.for_country(Some("FR")), 15);
+  }
+
+  #[test]
+  fn regions_are_looked_up_before_countries() {
+    let ages = ParentalConsentAges::new("ES-CT=16", 13).unwrap();
+    assert_eq!(ages.for_country(Some("ES-CT")), 16);
+    assert_eq!(ages.for_country(Some("es-ct")), 16);
+    assert_eq!(ages.for_country(Some("ES-MD")), 14);
+    assert_eq!(ages.for_country(Some("ES")), 14);
+  }
+
+  #[test]
+  fn rejects_malformed_overrides() {
+    assert!(ParentalConsentAges::new("DE", 13).is_err());
+    assert!(ParentalConsentAges::new("DE=sixteen", 13).is_err());
+  }
+}
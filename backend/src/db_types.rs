@@ -1,4 +1,6 @@
+use auth_service_api::request::AccountDeletionKind;
 use auth_service_api::request::ApiKeyKind;
+use auth_service_api::request::ParentPermissionKind;
 
 #[derive(Clone, Debug)]
 pub struct User {
@@ -14,6 +16,7 @@ pub struct UserData {
   pub dateofbirth: i64,
   pub username: String,
   pub realname: String,
+  pub country: Option<String>,
 }
 
 #[derive(Clone, Debug)]
@@ -57,3 +60,225 @@ pub struct ApiKey {
   pub api_key_kind: ApiKeyKind,
   pub duration: i64,
 }
+
+#[derive(Clone, Debug)]
+pub struct NotificationPreference {
+  pub notification_preference_id: i64,
+  pub creation_time: i64,
+  pub creator_user_id: i64,
+  pub security_notifications: bool,
+  pub locale: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Device {
+  pub device_id: i64,
+  pub creation_time: i64,
+  // devices are only ever looked up by these, never read back
+  #[allow(dead_code)]
+  pub creator_user_id: i64,
+  #[allow(dead_code)]
+  pub device_hash: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SecurityNotificationKind {
+  NewLogin = 0,
+  PasswordChange = 1,
+  PasswordReset = 2,
+  EmailChange = 3,
+}
+
+impl TryFrom<u8> for SecurityNotificationKind {
+  type Error = u8;
+  fn try_from(value: u8) -> Result<Self, Self::Error> {
+    match value {
+      0 => Ok(SecurityNotificationKind::NewLogin),
+      1 => Ok(SecurityNotificationKind::PasswordChange),
+      2 => Ok(SecurityNotificationKind::PasswordReset),
+      3 => Ok(SecurityNotificationKind::EmailChange),
+      _ => Err(value),
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EmailSuppressionKind {
+  Bounce = 0,
+  Complaint = 1,
+  // the address was verified again, so it can be sent to
+  Clear = 2,
+}
+
+impl TryFrom<u8> for EmailSuppressionKind {
+  type Error = u8;
+  fn try_from(value: u8) -> Result<Self, Self::Error> {
+    match value {
+      0 => Ok(EmailSuppressionKind::Bounce),
+      1 => Ok(EmailSuppressionKind::Complaint),
+      2 => Ok(EmailSuppressionKind::Clear),
+      _ => Err(value),
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PhoneChallengeKind {
+  Verify = 0,
+  SecondFactor = 1,
+}
+
+impl TryFrom<u8> for PhoneChallengeKind {
+  type Error = u8;
+  fn try_from(value: u8) -> Result<Self, Self::Error> {
+    match value {
+      0 => Ok(PhoneChallengeKind::Verify),
+      1 => Ok(PhoneChallengeKind::SecondFactor),
+      _ => Err(value),
+    }
+  }
+}
+
+#[derive(Clone, Debug)]
+pub struct SecurityNotification {
+  // only used to look the notification up
+  #[allow(dead_code)]
+  pub security_notification_key_hash: String,
+  pub creation_time: i64,
+  pub creator_user_id: i64,
+  pub security_notification_kind: SecurityNotificationKind,
+  pub email: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct AccountDeletion {
+  pub account_deletion_id: i64,
+  pub creation_time: i64,
+  pub creator_user_id: i64,
+  pub account_deletion_kind: AccountDeletionKind,
+  pub scheduled_time: i64,
+}
+
+#[derive(Clone, Debug)]
+pub struct ParentPermission {
+  pub parent_permission_id: i64,
+  pub creation_time: i64,
+  pub creator_user_id: i64,
+  pub verification_challenge_key_hash: String,
+  pub parent_permission_kind: ParentPermissionKind,
+  pub terms_version: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ParentAccess {
+  // only used to look the access up
+  #[allow(dead_code)]
+  pub parent_access_key_hash: String,
+  pub creation_time: i64,
+  pub expiry_time: i64,
+  pub email: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct EmailChange {
+  pub email_change_key_hash: String,
+  pub creation_time: i64,
+  pub creator_user_id: i64,
+  pub old_verification_challenge_key_hash: String,
+  pub new_verification_challenge_key_hash: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct EmailOutbox {
+  pub email_outbox_id: i64,
+  // the dispatcher filters on these in sql, they are kept for looking at the table by hand
+  #[allow(dead_code)]
+  pub creation_time: i64,
+  #[allow(dead_code)]
+  pub expiry_time: i64,
+  pub destination: String,
+  pub topic: String,
+  pub title: String,
+  pub content: String,
+  pub content_plaintext: String,
+  pub attempts: i64,
+  #[allow(dead_code)]
+  pub next_attempt_time: i64,
+  #[allow(dead_code)]
+  pub last_error: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct EmailSuppression {
+  // suppressions are looked up by address
+  #[allow(dead_code)]
+  pub email_suppression_id: i64,
+  pub creation_time: i64,
+  pub email: String,
+  pub email_suppression_kind: EmailSuppressionKind,
+}
+
+#[derive(Clone, Debug)]
+pub struct VerificationCode {
+  pub verification_challenge_key_hash: String,
+  // the code is found by its key, and times out with its challenge
+  #[allow(dead_code)]
+  pub creation_time: i64,
+  #[allow(dead_code)]
+  pub api_key_hash: String,
+  pub verification_code_hash: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct PhoneChallenge {
+  pub phone_challenge_id: i64,
+  pub creation_time: i64,
+  pub creator_user_id: i64,
+  pub phone: String,
+  pub phone_challenge_kind: PhoneChallengeKind,
+  pub phone_code_hash: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct Phone {
+  pub phone_id: i64,
+  pub creation_time: i64,
+  pub creator_user_id: i64,
+  pub phone_challenge_id: Option<i64>,
+}
+
+#[derive(Clone, Debug)]
+pub struct SecondFactor {
+  pub second_factor_id: i64,
+  pub creation_time: i64,
+  pub creator_user_id: i64,
+  pub enabled: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReauthenticationKind {
+  Password = 0,
+  SecondFactor = 1,
+}
+
+impl TryFrom<u8> for ReauthenticationKind {
+  type Error = u8;
+  fn try_from(value: u8) -> Result<Self, Self::Error> {
+    match value {
+      0 => Ok(ReauthenticationKind::Password),
+      1 => Ok(ReauthenticationKind::SecondFactor),
+      _ => Err(value),
+    }
+  }
+}
+
+#[derive(Clone, Debug)]
+pub struct Reauthentication {
+  pub reauthentication_id: i64,
+  pub creation_time: i64,
+  pub creator_user_id: i64,
+  // the key is matched in the query that finds the reauthentication
+  #[allow(dead_code)]
+  pub api_key_hash: String,
+  pub reauthentication_kind: ReauthenticationKind,
+}
@@ -0,0 +1,77 @@
+use super::db_types::*;
+use tokio_postgres::GenericClient;
+
+impl From<tokio_postgres::row::Row> for Device {
+  // select * from device order only, otherwise it will fail
+  fn from(row: tokio_postgres::row::Row) -> Device {
+    Device {
+      device_id: row.get("device_id"),
+      creation_time: row.get("creation_time"),
+      creator_user_id: row.get("creator_user_id"),
+      device_hash: row.get("device_hash"),
+    }
+  }
+}
+
+pub async fn add(
+  con: &mut impl GenericClient,
+  creator_user_id: i64,
+  device_hash: String,
+) -> Result<Device, tokio_postgres::Error> {
+  let row = con
+    .query_one(
+      "INSERT INTO
+       device_t(
+         creator_user_id,
+         device_hash
+       )
+       VALUES ($1, $2)
+       RETURNING device_id, creation_time
+      ",
+      &[&creator_user_id, &device_hash],
+    )
+    .await?;
+
+  Ok(Device {
+    device_id: row.get(0),
+    creation_time: row.get(1),
+    creator_user_id,
+    device_hash,
+  })
+}
+
+pub async fn exists_by_user_id_and_device_hash(
+  con: &mut impl GenericClient,
+  user_id: i64,
+  device_hash: &str,
+) -> Result<bool, tokio_postgres::Error> {
+  let count: i64 = con
+    .query_one(
+      "SELECT count(*) FROM device_t WHERE creator_user_id=$1 AND device_hash=$2",
+      &[&user_id, &device_hash],
+    )
+    .await?
+    .get(0);
+  Ok(count != 0)
+}
+
+// gets every device the user has logged in from
+pub async fn get_all_by_user_id(
+  con: &mut impl GenericClient,
+  user_id: i64,
+) -> Result<Vec<Device>, tokio_postgres::Error> {
+  let results = con
+    .query(
+      "SELECT d.* FROM device_t d
+       WHERE d.creator_user_id = $1
+       ORDER BY d.device_id
+      ",
+      &[&user_id],
+    )
+    .await?
+    .into_iter()
+    .map(|x| x.into())
+    .collect();
+
+  Ok(results)
+}
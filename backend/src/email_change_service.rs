@@ -0,0 +1,133 @@
+use super::db_types::*;
+use tokio_postgres::GenericClient;
+
+impl From<tokio_postgres::row::Row> for EmailChange {
+  // select * from email_change order only, otherwise it will fail
+  fn from(row: tokio_postgres::row::Row) -> EmailChange {
+    EmailChange {
+      email_change_key_hash: row.get("email_change_key_hash"),
+      creation_time: row.get("creation_time"),
+      creator_user_id: row.get("creator_user_id"),
+      old_verification_challenge_key_hash: row.get("old_verification_challenge_key_hash"),
+      new_verification_challenge_key_hash: row.get("new_verification_challenge_key_hash"),
+    }
+  }
+}
+
+pub async fn add(
+  con: &mut impl GenericClient,
+  email_change_key_hash: String,
+  creator_user_id: i64,
+  old_verification_challenge_key_hash: String,
+  new_verification_challenge_key_hash: String,
+) -> Result<EmailChange, tokio_postgres::Error> {
+  let row = con
+    .query_one(
+      "INSERT INTO
+       email_change_t(
+         email_change_key_hash,
+         creator_user_id,
+         old_verification_challenge_key_hash,
+         new_verification_challenge_key_hash
+       )
+       VALUES ($1, $2, $3, $4)
+       RETURNING creation_time
+      ",
+      &[
+        &email_change_key_hash,
+        &creator_user_id,
+        &old_verification_challenge_key_hash,
+        &new_verification_challenge_key_hash,
+      ],
+    )
+    .await?;
+
+  Ok(EmailChange {
+    email_change_key_hash,
+    creation_time: row.get(0),
+    creator_user_id,
+    old_verification_challenge_key_hash,
+    new_verification_challenge_key_hash,
+  })
+}
+
+// the hashes are every hash the key may have been stored under, see token_hasher.rs
+pub async fn get_by_email_change_key_hashes(
+  con: &mut impl GenericClient,
+  email_change_key_hashes: &[String],
+) -> Result<Option<EmailChange>, tokio_postgres::Error> {
+  let result = con
+    .query_opt(
+      "SELECT * FROM email_change_t WHERE email_change_key_hash=ANY($1)",
+      &[&email_change_key_hashes],
+    )
+    .await?
+    .map(|row| row.into());
+
+  Ok(result)
+}
+
+// gets the change that made this verification challenge's email the user's own email
+pub async fn get_by_new_verification_challenge_key_hash(
+  con: &mut impl GenericClient,
+  new_verification_challenge_key_hash: &str,
+) -> Result<Option<EmailChange>, tokio_postgres::Error> {
+  let result = con
+    .query_opt(
+      "SELECT * FROM email_change_t WHERE new_verification_challenge_key_hash=$1",
+      &[&new_verification_challenge_key_hash],
+    )
+    .await?
+    .map(|row| row.into());
+
+  Ok(result)
+}
+
+pub async fn add_revert(
+  con: &mut impl GenericClient,
+  email_change_key_hash: &str,
+) -> Result<(), tokio_postgres::Error> {
+  con
+    .execute(
+      "INSERT INTO email_change_revert_t(email_change_key_hash) VALUES ($1)",
+      &[&email_change_key_hash],
+    )
+    .await?;
+
+  Ok(())
+}
+
+pub async fn exists_revert_by_email_change_key_hash(
+  con: &mut impl GenericClient,
+  email_change_key_hash: &str,
+) -> Result<bool, tokio_postgres::Error> {
+  let count: i64 = con
+    .query_one(
+      "SELECT count(*) FROM email_change_revert_t WHERE email_change_key_hash=$1",
+      &[&email_change_key_hash],
+    )
+    .await?
+    .get(0);
+  Ok(count != 0)
+}
+
+// gets every change the user made to their own email
+pub async fn get_all_by_user_id(
+  con: &mut impl GenericClient,
+  user_id: i64,
+) -> Result<Vec<EmailChange>, tokio_postgres::Error> {
+  let results = con
+    .query(
+      "SELECT ec.* FROM email_change_t ec
+       WHERE ec.creator_user_id = $1
+       ORDER BY ec.creation_time
+      ",
+      &[&user_id],
+    )
+    .await?
+    .into_iter()
+    .map(|x| x.into())
+    .collect();
+
+  Ok(results)
+}
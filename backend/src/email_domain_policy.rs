@@ -0,0 +1,176 @@
+use serde::Deserialize;
+use std::path::Path;
+
+// well known throwaway mail providers, blocked unless a policy sets blockDisposable to false
+// more can be added with disposableDomains in the policy file
+static DISPOSABLE_DOMAINS: [&str; 16] = [
+  "10minutemail.com",
+  "discard.email",
+  "dispostable.com",
+  "emailondeck.com",
+  "fakeinbox.com",
+  "getnada.com",
+  "guerrillamail.com",
+  "maildrop.cc",
+  "mailinator.com",
+  "mailnesia.com",
+  "mintemail.com",
+  "mohmal.com",
+  "sharklasers.com",
+  "temp-mail.org",
+  "throwawaymail.com",
+  "yopmail.com",
+];
+
+// which domains an address may be at, for one purpose (own or parent emails)
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PurposePolicy {
+  // if not empty, only these domains (and their subdomains) are allowed
+  pub allowed_domains: Vec<String>,
+  // these domains (and their subdomains) are never allowed
+  pub denied_domains: Vec<String>,
+  pub block_disposable: bool,
+}
+
+impl Default for PurposePolicy {
+  fn default() -> PurposePolicy {
+    PurposePolicy {
+      allowed_domains: vec![],
+      denied_domains: vec![],
+      block_disposable: true,
+    }
+  }
+}
+
+// the contents of the file given by --email-domain-policy-file, eg:
+// {
+//   "own": { "allowedDomains": ["district.org"] },
+//   "parent": { "deniedDomains": ["example.com"] },
+//   "disposableDomains": ["spam.example"]
+// }
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct DomainPolicy {
+  pub own: PurposePolicy,
+  pub parent: PurposePolicy,
+  pub disposable_domains: Vec<String>,
+}
+
+// true if the domain is the listed domain or one of its subdomains
+fn domain_matches(domain: &str, listed: &str) -> bool {
+  let listed = listed.trim().to_lowercase();
+  domain == listed || domain.ends_with(&format!(".{}", listed))
+}
+
+impl DomainPolicy {
+  pub fn load(path: &Path) -> Result<DomainPolicy, Box<dyn std::error::Error + 'static>> {
+    let contents = std::fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&contents)?)
+  }
+
+  // returns why the address isn't allowed, if it isn't
+  // the address should already be normalized
+  pub fn check(&self, email: &str, to_parent: bool) -> Result<(), String> {
+    let policy = if to_parent { &self.parent } else { &self.own };
+
+    let domain = match email.rsplit_once('@') {
+      Some((_, domain)) => domain.to_lowercase(),
+      None => return Err("address has no domain".to_owned()),
+    };
+
+    if !policy.allowed_domains.is_empty()
+      && !policy
+        .allowed_domains
+        .iter()
+        .any(|x| domain_matches(&domain, x))
+    {
+      return Err(format!("{} is not an allowed domain", domain));
+    }
+
+    if policy
+      .denied_domains
+      .iter()
+      .any(|x| domain_matches(&domain, x))
+    {
+      return Err(format!("{} is a denied domain", domain));
+    }
+
+    if policy.block_disposable
+      && (DISPOSABLE_DOMAINS
+        .iter()
+        .any(|x| domain_matches(&domain, x))
+        || self
+          .disposable_domains
+          .iter()
+          .any(|x| domain_matches(&domain, x)))
+    {
+      return Err(format!("{} is a disposable domain", domain));
+    }
+
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn policy(json: &str) -> DomainPolicy {
+    serde_json::from_str(json).unwrap()
+  }
+
+  #[test]
+  fn default_blocks_only_disposable_domains() {
+    let policy = DomainPolicy::default();
+    assert!(policy.check("jane@example.com", false).is_ok());
+    assert!(policy.check("jane@example.com", true).is_ok());
+    assert!(policy.check("jane@mailinator.com", false).is_err());
+    assert!(policy.check("jane@eu.mailinator.com", true).is_err());
+    assert!(policy.check("jane", false).is_err());
+  }
+
+  #[test]
+  fn matches_subdomains_but_not_lookalikes() {
+    assert!(domain_matches("district.org", "district.org"));
+    assert!(domain_matches("mail.district.org", " District.org "));
+    assert!(!domain_matches("notdistrict.org", "district.org"));
+    assert!(!domain_matches("district.org.example", "district.org"));
+  }
+
+  #[test]
+  fn allowed_domains_apply_to_their_purpose_only() {
+    let policy = policy(r#"{ "own": { "allowedDomains": ["district.org"] } }"#);
+    assert!(policy.check("jane@district.org", false).is_ok());
+    assert!(policy.check("jane@students.district.org", false).is_ok());
+    assert!(policy.check("jane@example.com", false).is_err());
+    assert!(policy.check("parent@example.com", true).is_ok());
+  }
+
+  #[test]
+  fn denied_domains_win_over_allowed_ones() {
+    let policy = policy(
+      r#"{ "parent": { "allowedDomains": ["example.com"], "deniedDomains": ["old.example.com"] } }"#,
+    );
+    assert!(policy.check("parent@example.com", true).is_ok());
+    assert!(policy.check("parent@old.example.com", true).is_err());
+  }
+
+  #[test]
+  fn extra_disposable_domains_can_be_turned_off() {
+    let policy = policy(
+      r#"{ "parent": { "blockDisposable": false }, "disposableDomains": ["spam.example"] }"#,
+    );
+    assert!(policy.check("jane@spam.example", false).is_err());
+    assert!(policy.check("jane@spam.example", true).is_ok());
+    assert!(policy.check("jane@yopmail.com", true).is_ok());
+  }
+
+  #[test]
+  fn rejects_malformed_json() {
+    assert!(serde_json::from_str::<DomainPolicy>(
+      r#"{ "own": { "allowedDomains": "district.org" } }"#
+    )
+    .is_err());
+  }
+}
@@ -0,0 +1,255 @@
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+
+use super::db_types::*;
+use super::verification_challenge_service;
+
+// how the local part (before the @) of addresses at a domain is folded
+// most providers treat the local part case insensitively, some also ignore dots or anything after a +
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LocalPartFolding {
+  pub lowercase: bool,
+  pub strip_dots: bool,
+  pub strip_plus: bool,
+}
+
+pub type FoldingRules = HashMap<String, LocalPartFolding>;
+
+// parses rules like "gmail.com=lowercase,nodots,noplus;outlook.com=lowercase,noplus"
+pub fn parse_folding_rules(rules: &str) -> Result<FoldingRules, String> {
+  let mut folding_rules = FoldingRules::new();
+
+  for rule in rules.split(';').map(|x| x.trim()).filter(|x| !x.is_empty()) {
+    let (domain, options) = rule
+      .split_once('=')
+      .ok_or_else(|| format!("email folding rule `{}` is missing an `=`", rule))?;
+
+    let mut folding = LocalPartFolding::default();
+    for option in options
+      .split(',')
+      .map(|x| x.trim())
+      .filter(|x| !x.is_empty())
+    {
+      match option {
+        "lowercase" => folding.lowercase = true,
+        "nodots" => folding.strip_dots = true,
+        "noplus" => folding.strip_plus = true,
+        _ => return Err(format!("unknown email folding option `{}`", option)),
+      }
+    }
+
+    folding_rules.insert(domain.trim().to_lowercase(), folding);
+  }
+
+  Ok(folding_rules)
+}
+
+// returns the canonical form of an address
+// the domain is always lower cased, the local part is only folded if there's a rule for the domain
+// the local part of other domains keeps its case, but addresses are still compared case insensitively
+pub fn normalize_email(email: &str, folding_rules: &FoldingRules) -> String {
+  let email = email.trim();
+
+  let (local_part, domain) = match email.rsplit_once('@') {
+    Some(parts) => parts,
+    // not an address, leave it for the mail service to reject
+    None => return email.to_owned(),
+  };
+
+  let domain = domain.trim_end_matches('.').to_lowercase();
+
+  let mut local_part = local_part.to_owned();
+  if let Some(folding) = folding_rules.get(&domain) {
+    if folding.strip_plus {
+      if let Some((before_plus, _)) = local_part.split_once('+') {
+        local_part = before_plus.to_owned();
+      }
+    }
+    if folding.strip_dots {
+      local_part = local_part.replace('.', "");
+    }
+    if folding.lowercase {
+      local_part = local_part.to_lowercase();
+    }
+  }
+
+  format!("{}@{}", local_part, domain)
+}
+
+// picks which of the held emails matching an address case insensitively was meant, given each one's stored address
+// there is normally at most one, but users may hold addresses differing only in case from before addresses were normalized,
+// in which case only an exact match will do, as guessing could hand one user's account to another
+pub fn choose_held<T>(email: &str, mut held: Vec<(String, T)>) -> Option<T> {
+  if held.len() <= 1 {
+    return held.pop().map(|(_, x)| x);
+  }
+
+  let mut exact: Vec<T> = held
+    .into_iter()
+    .filter(|(address, _)| address == email)
+    .map(|(_, x)| x)
+    .collect();
+
+  if exact.len() == 1 {
+    exact.pop()
+  } else {
+    None
+  }
+}
+
+// own emails that normalize to the same address but are held by different users
+#[derive(Clone, Debug)]
+pub struct Collision {
+  pub normalized_email: String,
+  pub verification_challenges: Vec<VerificationChallenge>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct NormalizationReport {
+  // (old address, new address) of each held own email that was rewritten
+  pub normalized: Vec<(String, String)>,
+  // these have to be resolved by hand, none of the addresses involved are rewritten
+  pub collisions: Vec<Collision>,
+}
+
+// rewrites every held own email into its canonical form, unless doing so would make two users share an address
+// if dry_run is set, nothing is written and the report only says what would be done
+pub async fn normalize_existing(
+  con: &mut tokio_postgres::Client,
+  folding_rules: &FoldingRules,
+  dry_run: bool,
+) -> Result<NormalizationReport, tokio_postgres::Error> {
+  let held = verification_challenge_service::get_all_held_own(con).await?;
+
+  // group by canonical form, comparing case insensitively like the database does
+  let mut groups: BTreeMap<String, Vec<VerificationChallenge>> = BTreeMap::new();
+  for vc in held.into_iter() {
+    groups
+      .entry(normalize_email(&vc.email, folding_rules).to_lowercase())
+      .or_default()
+      .push(vc);
+  }
+
+  let mut report = NormalizationReport::default();
+
+  let mut sp = con.transaction().await?;
+
+  for (normalized_email, vcs) in groups.into_iter() {
+    let mut user_ids: Vec<i64> = vcs.iter().map(|x| x.creator_user_id).collect();
+    user_ids.sort();
+    user_ids.dedup();
+
+    if user_ids.len() > 1 {
+      report.collisions.push(Collision {
+        normalized_email,
+        verification_challenges: vcs,
+      });
+      continue;
+    }
+
+    for vc in vcs.into_iter() {
+      let new_email = normalize_email(&vc.email, folding_rules);
+      if new_email == vc.email {
+        continue;
+      }
+
+      if !dry_run {
+        verification_challenge_service::update_email(
+          &mut sp,
+          &vc.verification_challenge_key_hash,
+          &new_email,
+        )
+        .await?;
+      }
+
+      report.normalized.push((vc.email, new_email));
+    }
+  }
+
+  sp.commit().await?;
+
+  Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn gmail_rules() -> FoldingRules {
+    parse_folding_rules("gmail.com=lowercase,nodots,noplus;outlook.com=lowercase,noplus").unwrap()
+  }
+
+  #[test]
+  fn parses_folding_rules() {
+    let rules = parse_folding_rules(" Gmail.com = lowercase, nodots ;; example.org=").unwrap();
+    assert_eq!(
+      rules.get("gmail.com"),
+      Some(&LocalPartFolding {
+        lowercase: true,
+        strip_dots: true,
+        strip_plus: false,
+      })
+    );
+    assert_eq!(rules.get("example.org"), Some(&LocalPartFolding::default()));
+    assert_eq!(rules.len(), 2);
+  }
+
+  #[test]
+  fn rejects_malformed_folding_rules() {
+    assert!(parse_folding_rules("gmail.com").is_err());
+    assert!(parse_folding_rules("gmail.com=lowercase,nospaces").is_err());
+    assert!(parse_folding_rules("").unwrap().is_empty());
+  }
+
+  #[test]
+  fn folds_local_parts_by_domain() {
+    let rules = gmail_rules();
+    assert_eq!(
+      normalize_email("  Jane.Doe+news@GMail.com. ", &rules),
+      "janedoe@gmail.com"
+    );
+    assert_eq!(
+      normalize_email("Jane.Doe+news@outlook.com", &rules),
+      "jane.doe@outlook.com"
+    );
+  }
+
+  #[test]
+  fn keeps_local_parts_of_other_domains() {
+    let rules = gmail_rules();
+    assert_eq!(
+      normalize_email("Jane.Doe+news@Example.COM", &rules),
+      "Jane.Doe+news@example.com"
+    );
+    assert_eq!(normalize_email("not an address", &rules), "not an address");
+  }
+
+  #[test]
+  fn splits_at_the_last_at() {
+    assert_eq!(
+      normalize_email("\"a@b\"@Example.com", &FoldingRules::new()),
+      "\"a@b\"@example.com"
+    );
+  }
+
+  #[test]
+  fn chooses_the_only_held_email() {
+    assert_eq!(choose_held::<i64>("a@example.com", vec![]), None);
+    assert_eq!(
+      choose_held("a@example.com", vec![("A@example.com".to_owned(), 1)]),
+      Some(1)
+    );
+  }
+
+  #[test]
+  fn chooses_an_exact_match_among_case_variants() {
+    let held = vec![
+      ("Bob@example.com".to_owned(), 1),
+      ("bob@example.com".to_owned(), 2),
+    ];
+    assert_eq!(choose_held("bob@example.com", held.clone()), Some(2));
+    assert_eq!(choose_held("Bob@example.com", held.clone()), Some(1));
+    // guessing could log into the wrong account
+    assert_eq!(choose_held("BOB@example.com", held), None);
+  }
+}
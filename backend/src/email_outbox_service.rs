@@ -0,0 +1,195 @@
+use super::db_types::*;
+use tokio_postgres::GenericClient;
+
+impl From<tokio_postgres::row::Row> for EmailOutbox {
+  // select * from email_outbox order only, otherwise it will fail
+  fn from(row: tokio_postgres::row::Row) -> EmailOutbox {
+    EmailOutbox {
+      email_outbox_id: row.get("email_outbox_id"),
+      creation_time: row.get("creation_time"),
+      expiry_time: row.get("expiry_time"),
+      destination: row.get("destination"),
+      topic: row.get("topic"),
+      title: row.get("title"),
+      content: row.get("content"),
+      content_plaintext: row.get("content_plaintext"),
+      attempts: row.get("attempts"),
+      next_attempt_time: row.get("next_attempt_time"),
+      last_error: row.get("last_error"),
+    }
+  }
+}
+
+pub async fn add(
+  con: &mut impl GenericClient,
+  expiry_time: i64,
+  destination: String,
+  topic: String,
+  title: String,
+  content: String,
+  content_plaintext: String,
+) -> Result<EmailOutbox, tokio_postgres::Error> {
+  let row = con
+    .query_one(
+      "INSERT INTO
+       email_outbox_t(
+         expiry_time,
+         destination,
+         topic,
+         title,
+         content,
+         content_plaintext
+       )
+       VALUES ($1, $2, $3, $4, $5, $6)
+       RETURNING email_outbox_id, creation_time, next_attempt_time
+      ",
+      &[
+        &expiry_time,
+        &destination,
+        &topic,
+        &title,
+        &content,
+        &content_plaintext,
+      ],
+    )
+    .await?;
+
+  Ok(EmailOutbox {
+    email_outbox_id: row.get(0),
+    creation_time: row.get(1),
+    expiry_time,
+    destination,
+    topic,
+    title,
+    content,
+    content_plaintext,
+    attempts: 0,
+    next_attempt_time: row.get(2),
+    last_error: None,
+  })
+}
+
+// gets the oldest emails that are due to be attempted
+pub async fn get_due(
+  con: &mut impl GenericClient,
+  current_time: i64,
+  limit: i64,
+) -> Result<Vec<EmailOutbox>, tokio_postgres::Error> {
+  let results = con
+    .query(
+      "SELECT eo.* FROM email_outbox_t eo
+       WHERE eo.next_attempt_time <= $1
+       ORDER BY eo.next_attempt_time, eo.email_outbox_id
+       LIMIT $2
+      ",
+      &[&current_time, &limit],
+    )
+    .await?
+    .into_iter()
+    .map(|x| x.into())
+    .collect();
+
+  Ok(results)
+}
+
+// the email was delivered, so there's no need to hold on to its contents
+pub async fn delete(
+  con: &mut impl GenericClient,
+  email_outbox_id: i64,
+) -> Result<(), tokio_postgres::Error> {
+  con
+    .execute(
+      "DELETE FROM email_outbox_t WHERE email_outbox_id=$1",
+      &[&email_outbox_id],
+    )
+    .await?;
+
+  Ok(())
+}
+
+pub async fn add_failure(
+  con: &mut impl GenericClient,
+  email_outbox_id: i64,
+  next_attempt_time: i64,
+  error: &str,
+) -> Result<(), tokio_postgres::Error> {
+  con
+    .execute(
+      "UPDATE email_outbox_t
+       SET attempts=attempts+1, next_attempt_time=$2, last_error=$3
+       WHERE email_outbox_id=$1
+      ",
+      &[&email_outbox_id, &next_attempt_time, &error],
+    )
+    .await?;
+
+  Ok(())
+}
+
+// moves the email out of the outbox, so that it is never attempted again
+// its contents aren't kept, since they hold keys that may still work
+pub async fn add_dead_letter(
+  con: &mut impl GenericClient,
+  email_outbox_id: i64,
+  error: &str,
+) -> Result<(), tokio_postgres::Error> {
+  con
+    .execute(
+      "WITH dead AS (
+         DELETE FROM email_outbox_t WHERE email_outbox_id=$1 RETURNING *
+       )
+       INSERT INTO
+       email_dead_letter_t(
+         email_outbox_id,
+         outbox_creation_time,
+         destination,
+         topic,
+         title,
+         attempts,
+         error
+       )
+       SELECT email_outbox_id, creation_time, destination, topic, title, attempts+1, $2
+       FROM dead
+      ",
+      &[&email_outbox_id, &error],
+    )
+    .await?;
+
+  Ok(())
+}
+
+// gives up on every email whose links have stopped working, whether or not it is due
+// returns how many there were
+pub async fn add_expired_dead_letters(
+  con: &mut impl GenericClient,
+  current_time: i64,
+) -> Result<u64, tokio_postgres::Error> {
+  con
+    .execute(
+      "WITH dead AS (
+         DELETE FROM email_outbox_t WHERE expiry_time <= $1 RETURNING *
+       )
+       INSERT INTO
+       email_dead_letter_t(
+         email_outbox_id,
+         outbox_creation_time,
+         destination,
+         topic,
+         title,
+         attempts,
+         error
+       )
+       SELECT
+         email_outbox_id,
+         creation_time,
+         destination,
+         topic,
+         title,
+         attempts,
+         coalesce(last_error, 'not sent') || ', and expired before it could be delivered'
+       FROM dead
+      ",
+      &[&current_time],
+    )
+    .await
+}
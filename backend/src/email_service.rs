@@ -1,4 +1,5 @@
 use super::db_types::*;
+use super::email_normalization;
 use super::utils::current_time_millis;
 use tokio_postgres::GenericClient;
 
@@ -70,31 +71,78 @@ pub async fn get_by_verification_challenge_key_hash(
   Ok(result)
 }
 
-// gets most recent email
+// gets the held own email with this address, whichever user holds it
+// addresses are compared case insensitively, but addresses held from before that may differ only in case,
+// so if several users hold the address, it has to be an exact match, see email_normalization::choose_held
 pub async fn get_by_own_email(
   con: &mut impl GenericClient,
   email: &str,
 ) -> Result<Option<Email>, tokio_postgres::Error> {
-  let result = con
-    .query_opt(
-      "SELECT e.* FROM recent_own_email_v e
+  let held = con
+    .query(
+      "SELECT e.*, vc.email FROM own_email_v e
        INNER JOIN verification_challenge_t vc ON vc.verification_challenge_key_hash = e.verification_challenge_key_hash
-       WHERE vc.email = $1
+       WHERE lower(vc.email) = lower($1)
       ",
       &[&email],
-    ).await?
-    .map(|x| x.into());
+    )
+    .await?
+    .into_iter()
+    .map(|x| (x.get("email"), x.into()))
+    .collect();
 
-  Ok(result)
+  Ok(email_normalization::choose_held(email, held))
+}
+
+// gets every held own email with this address, compared case insensitively
+// more than one means that users held addresses differing only in case before addresses were normalized
+pub async fn get_all_by_own_email(
+  con: &mut impl GenericClient,
+  email: &str,
+) -> Result<Vec<Email>, tokio_postgres::Error> {
+  let results = con
+    .query(
+      "SELECT e.* FROM own_email_v e
+       INNER JOIN verification_challenge_t vc ON vc.verification_challenge_key_hash = e.verification_challenge_key_hash
+       WHERE lower(vc.email) = lower($1)
+       ORDER BY e.email_id
+      ",
+      &[&email],
+    )
+    .await?
+    .into_iter()
+    .map(|x| x.into())
+    .collect();
+
+  Ok(results)
+}
+
+// whether any user holds the address, compared case insensitively
+pub async fn exists_by_own_email(
+  con: &mut impl GenericClient,
+  email: &str,
+) -> Result<bool, tokio_postgres::Error> {
+  let count: i64 = con
+    .query_one(
+      "SELECT count(*) FROM own_email_v e
+       INNER JOIN verification_challenge_t vc ON vc.verification_challenge_key_hash = e.verification_challenge_key_hash
+       WHERE lower(vc.email) = lower($1)
+      ",
+      &[&email],
+    )
+    .await?
+    .get(0);
+
+  Ok(count > 0)
 }
 
-pub async fn get_own_by_user_id(
+pub async fn get_primary_by_user_id(
   con: &mut impl GenericClient,
   user_id: i64,
 ) -> Result<Option<Email>, tokio_postgres::Error> {
   let result = con
     .query_opt(
-      "SELECT e.* FROM recent_own_email_v e
+      "SELECT e.* FROM primary_email_v e
        JOIN verification_challenge_t vc USING(verification_challenge_key_hash)
        WHERE vc.creator_user_id = $1
       ",
@@ -105,23 +153,128 @@ pub async fn get_own_by_user_id(
   Ok(result)
 }
 
-pub async fn get_parent_by_user_id(
+// gets the own email if the user still holds it
+pub async fn get_own_by_user_id_and_email_id(
   con: &mut impl GenericClient,
   user_id: i64,
+  email_id: i64,
 ) -> Result<Option<Email>, tokio_postgres::Error> {
   let result = con
     .query_opt(
-      "SELECT e.* FROM recent_parent_email_v e
+      "SELECT e.* FROM own_email_v e
        JOIN verification_challenge_t vc USING(verification_challenge_key_hash)
        WHERE vc.creator_user_id = $1
+       AND e.email_id = $2
       ",
-      &[&user_id],
+      &[&user_id, &email_id],
     )
     .await?
     .map(|x| x.into());
   Ok(result)
 }
 
+// gets the own email made from this verification challenge, if it is still held
+pub async fn get_own_by_verification_challenge_key_hash(
+  con: &mut impl GenericClient,
+  verification_challenge_key_hash: &str,
+) -> Result<Option<Email>, tokio_postgres::Error> {
+  let result = con
+    .query_opt(
+      "SELECT * FROM own_email_v WHERE verification_challenge_key_hash=$1",
+      &[&verification_challenge_key_hash],
+    )
+    .await?
+    .map(|x| x.into());
+  Ok(result)
+}
+
+pub async fn add_primary(
+  con: &mut impl GenericClient,
+  creator_user_id: i64,
+  email_id: i64,
+) -> Result<(), tokio_postgres::Error> {
+  con
+    .execute(
+      "INSERT INTO email_primary_t(creator_user_id, email_id) VALUES ($1, $2)",
+      &[&creator_user_id, &email_id],
+    )
+    .await?;
+
+  Ok(())
+}
+
+pub async fn add_removal(
+  con: &mut impl GenericClient,
+  email_id: i64,
+) -> Result<(), tokio_postgres::Error> {
+  con
+    .execute(
+      "INSERT INTO email_removal_t(email_id) VALUES ($1)",
+      &[&email_id],
+    )
+    .await?;
+
+  Ok(())
+}
+
+pub async fn exists_removal_by_email_id(
+  con: &mut impl GenericClient,
+  email_id: i64,
+) -> Result<bool, tokio_postgres::Error> {
+  let count: i64 = con
+    .query_one(
+      "SELECT count(*) FROM email_removal_t WHERE email_id=$1",
+      &[&email_id],
+    )
+    .await?
+    .get(0);
+  Ok(count != 0)
+}
+
+// gets every email, own and parent, ever verified by the user
+pub async fn get_all_by_user_id(
+  con: &mut impl GenericClient,
+  user_id: i64,
+) -> Result<Vec<Email>, tokio_postgres::Error> {
+  let results = con
+    .query(
+      "SELECT e.* FROM email_t e
+       JOIN verification_challenge_t vc USING(verification_challenge_key_hash)
+       WHERE vc.creator_user_id = $1
+       ORDER BY e.email_id
+      ",
+      &[&user_id],
+    )
+    .await?
+    .into_iter()
+    .map(|x| x.into())
+    .collect();
+
+  Ok(results)
+}
+
+// gets the ids of every user whose current parent email is this one
+pub async fn get_child_user_ids_by_parent_email(
+  con: &mut impl GenericClient,
+  email: &str,
+) -> Result<Vec<i64>, tokio_postgres::Error> {
+  let results = con
+    .query(
+      "SELECT vc.creator_user_id FROM recent_parent_email_v e
+       JOIN verification_challenge_t vc USING(verification_challenge_key_hash)
+       WHERE lower(vc.email) = lower($1)
+       ORDER BY vc.creator_user_id
+      ",
+      &[&email],
+    )
+    .await?
+    .into_iter()
+    .map(|row| row.get(0))
+    .collect();
+
+  Ok(results)
+}
+
 pub async fn query(
   con: &mut impl GenericClient,
   props: auth_service_api::request::EmailViewProps,
@@ -131,7 +284,7 @@ pub async fn query(
       if props.to_parent {
         "SELECT e.* FROM recent_parent_email_v e"
       } else {
-        "SELECT e.* FROM recent_own_email_v e"
+        "SELECT e.* FROM own_email_v e"
       }
     } else {
       "SELECT e.* FROM email_t e"
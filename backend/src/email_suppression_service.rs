@@ -0,0 +1,76 @@
+use super::db_types::*;
+use std::convert::TryInto;
+use tokio_postgres::GenericClient;
+
+impl From<tokio_postgres::row::Row> for EmailSuppression {
+  // select * from email_suppression order only, otherwise it will fail
+  fn from(row: tokio_postgres::row::Row) -> EmailSuppression {
+    EmailSuppression {
+      email_suppression_id: row.get("email_suppression_id"),
+      creation_time: row.get("creation_time"),
+      email: row.get("email"),
+      // means that there's a mismatch between the values of the enum and the value stored in the column
+      email_suppression_kind: (row.get::<&str, i64>("email_suppression_kind") as u8)
+        .try_into()
+        .unwrap(),
+    }
+  }
+}
+
+pub async fn add(
+  con: &mut impl GenericClient,
+  email: String,
+  email_suppression_kind: EmailSuppressionKind,
+) -> Result<EmailSuppression, tokio_postgres::Error> {
+  let row = con
+    .query_one(
+      "INSERT INTO
+       email_suppression_t(
+         email,
+         email_suppression_kind
+       )
+       VALUES ($1, $2)
+       RETURNING email_suppression_id, creation_time
+      ",
+      &[&email, &(email_suppression_kind as i64)],
+    )
+    .await?;
+
+  Ok(EmailSuppression {
+    email_suppression_id: row.get(0),
+    creation_time: row.get(1),
+    email,
+    email_suppression_kind,
+  })
+}
+
+// gets the latest suppression event for the address, compared case insensitively
+pub async fn get_by_email(
+  con: &mut impl GenericClient,
+  email: &str,
+) -> Result<Option<EmailSuppression>, tokio_postgres::Error> {
+  let result = con
+    .query_opt(
+      "SELECT * FROM recent_email_suppression_v WHERE lower(email)=lower($1)",
+      &[&email],
+    )
+    .await?
+    .map(|row| row.into());
+
+  Ok(result)
+}
+
+// true if the address bounced or complained and hasn't been verified since
+pub async fn is_suppressed(
+  con: &mut impl GenericClient,
+  email: &str,
+) -> Result<bool, tokio_postgres::Error> {
+  let suppressed = match get_by_email(con, email).await? {
+    Some(email_suppression) => {
+      email_suppression.email_suppression_kind != EmailSuppressionKind::Clear
+    }
+    None => false,
+  };
+
+  Ok(suppressed)
+}
@@ -0,0 +1,194 @@
+use std::path::PathBuf;
+
+// Each email is made from three files in the template directory:
+//   <locale>/<template>.subject  the title, on a single line
+//   <locale>/<template>.html     the html part
+//   <locale>/<template>.txt      the plaintext part
+// Text messages are a single file, <locale>/<template>.sms
+// Each file is looked up in the requested locale (eg "es-MX"), then its language ("es"), then the default locale,
+// so a translation can leave out files it hasn't got to yet.
+// Variables are written as {{name}}, and are html escaped in the html part.
+// Files are read every time an email is sent, so they can be edited without restarting the server.
+#[derive(Clone, Debug)]
+pub struct EmailTemplates {
+  dir: PathBuf,
+  default_locale: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct RenderedEmail {
+  pub title: String,
+  pub content: String,
+  pub content_plaintext: String,
+}
+
+impl EmailTemplates {
+  pub fn new(dir: PathBuf, default_locale: String) -> EmailTemplates {
+    EmailTemplates {
+      dir,
+      default_locale,
+    }
+  }
+
+  pub fn render(
+    &self,
+    template: &str,
+    locale: Option<&str>,
+    vars: &[(&str, &str)],
+  ) -> Result<RenderedEmail, std::io::Error> {
+    let locales = self.candidate_locales(locale);
+
+    let title = self.read_template(&locales, &format!("{}.subject", template))?;
+    let content = self.read_template(&locales, &format!("{}.html", template))?;
+    let content_plaintext = self.read_template(&locales, &format!("{}.txt", template))?;
+
+    Ok(RenderedEmail {
+      title: substitute(title.trim(), vars, false),
+      content: substitute(&content, vars, true),
+      content_plaintext: substitute(&content_plaintext, vars, false),
+    })
+  }
+
+  // renders a text message, which has no html part to escape
+  pub fn render_sms(
+    &self,
+    template: &str,
+    locale: Option<&str>,
+    vars: &[(&str, &str)],
+  ) -> Result<String, std::io::Error> {
+    let locales = self.candidate_locales(locale);
+    let message = self.read_template(&locales, &format!("{}.sms", template))?;
+    Ok(substitute(message.trim(), vars, false))
+  }
+
+  // most specific first
+  fn candidate_locales(&self, locale: Option<&str>) -> Vec<String> {
+    let mut locales = vec![];
+
+    // the locale ends up in a path, so ignore anything that isn't shaped like one
+    if let Some(locale) =
+      locale.filter(|x| !x.is_empty() && x.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'))
+    {
+      locales.push(locale.to_owned());
+      if let Some((language, _)) = locale.split_once('-') {
+        locales.push(language.to_owned());
+      }
+    }
+
+    locales.push(self.default_locale.clone());
+    locales
+  }
+
+  fn read_template(&self, locales: &[String], file_name: &str) -> Result<String, std::io::Error> {
+    for locale in locales {
+      match std::fs::read_to_string(self.dir.join(locale).join(file_name)) {
+        Ok(contents) => return Ok(contents),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+        Err(e) => return Err(e),
+      }
+    }
+
+    Err(std::io::Error::new(
+      std::io::ErrorKind::NotFound,
+      format!("no template {} in {}", file_name, self.dir.display()),
+    ))
+  }
+}
+
+fn escape_html(value: &str) -> String {
+  value
+    .replace('&', "&amp;")
+    .replace('<', "&lt;")
+    .replace('>', "&gt;")
+    .replace('"', "&quot;")
+    .replace('\'', "&#39;")
+}
+
+// unknown variables are left as they are, so that typos show up in the sent email
+fn substitute(template: &str, vars: &[(&str, &str)], html: bool) -> String {
+  let mut result = template.to_owned();
+  for (name, value) in vars {
+    let value = if html {
+      escape_html(value)
+    } else {
+      (*value).to_owned()
+    };
+    result = result.replace(&format!("{{{{{}}}}}", name), &value);
+  }
+  result
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn templates() -> EmailTemplates {
+    EmailTemplates::new(
+      PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("templates"),
+      "en".to_owned(),
+    )
+  }
+
+  #[test]
+  fn substitutes_and_escapes_html_only() {
+    let vars = [("user_name", "<b>Tom & Jerry's</b>"), ("link", "x")];
+    assert_eq!(
+      substitute("{{user_name}} {{link}} {{missing}}", &vars, true),
+      "&lt;b&gt;Tom &amp; Jerry&#39;s&lt;/b&gt; x {{missing}}"
+    );
+    assert_eq!(
+      substitute("{{user_name}}", &vars, false),
+      "<b>Tom & Jerry's</b>"
+    );
+  }
+
+  #[test]
+  fn looks_up_locale_then_language_then_default() {
+    let templates = templates();
+    assert_eq!(
+      templates.candidate_locales(Some("es-MX")),
+      vec!["es-MX", "es", "en"]
+    );
+    assert_eq!(templates.candidate_locales(Some("es")), vec!["es", "en"]);
+    assert_eq!(templates.candidate_locales(None), vec!["en"]);
+    // a locale can't point outside the template directory
+    assert_eq!(templates.candidate_locales(Some("../../etc")), vec!["en"]);
+  }
+
+  #[test]
+  fn renders_translations_with_fallback() {
+    let templates = templates();
+    let vars = [("app_origin", "https://example.com")];
+
+    let email = templates
+      .render("password_reset", Some("es-MX"), &vars)
+      .unwrap();
+    assert_eq!(email.title, "https://example.com: Restablecer contraseña");
+
+    // not translated yet
+    let email = templates.render("email_change", Some("es"), &vars).unwrap();
+    assert!(email.title.starts_with("https://example.com: "));
+
+    assert!(templates.render("no_such_template", None, &vars).is_err());
+  }
+
+  #[test]
+  fn every_email_template_has_all_its_parts() {
+    for locale in ["en", "es"] {
+      let dir = templates().dir.join(locale);
+      for entry in std::fs::read_dir(&dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().and_then(|x| x.to_str()) == Some("html") {
+          for extension in ["subject", "txt"] {
+            assert!(
+              path.with_extension(extension).exists(),
+              "{} has no .{}",
+              path.display(),
+              extension
+            );
+          }
+        }
+      }
+    }
+  }
+}
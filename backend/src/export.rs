@@ -0,0 +1,434 @@
+use serde::Serialize;
+
+use auth_service_api::request::AccountDeletionKind;
+use auth_service_api::request::ApiKeyKind;
+use auth_service_api::request::ParentPermissionKind;
+
+use super::db_types::*;
+
+use super::account_deletion_service;
+use super::api_key_service;
+use super::device_service;
+use super::email_change_service;
+use super::email_service;
+use super::notification_preference_service;
+use super::parent_permission_service;
+use super::password_reset_service;
+use super::password_service;
+use super::phone_challenge_service;
+use super::phone_service;
+use super::reauthentication_service;
+use super::second_factor_service;
+use super::security_notification_service;
+use super::user_data_service;
+use super::user_service;
+use super::utils;
+use super::verification_challenge_service;
+
+// bump this whenever a field is renamed or removed, adding fields is fine
+pub static EXPORT_SCHEMA_VERSION: i64 = 1;
+
+// Everything we hold on a single user.
+// The layout of this struct is documented in README.md, keep the two in sync.
+// Secrets (password hashes, key hashes) are never included.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserExport {
+    pub schema_version: i64,
+    pub export_time: i64,
+    pub user: UserRecord,
+    pub user_data: Vec<UserDataRecord>,
+    pub verification_challenges: Vec<VerificationChallengeRecord>,
+    pub emails: Vec<EmailRecord>,
+    pub email_changes: Vec<EmailChangeRecord>,
+    pub parent_permissions: Vec<ParentPermissionRecord>,
+    pub passwords: Vec<PasswordRecord>,
+    pub password_resets: Vec<PasswordResetRecord>,
+    pub api_keys: Vec<ApiKeyRecord>,
+    pub notification_preferences: Vec<NotificationPreferenceRecord>,
+    pub devices: Vec<DeviceRecord>,
+    pub security_notifications: Vec<SecurityNotificationRecord>,
+    pub account_deletions: Vec<AccountDeletionRecord>,
+    pub phone_challenges: Vec<PhoneChallengeRecord>,
+    pub phones: Vec<PhoneRecord>,
+    pub second_factors: Vec<SecondFactorRecord>,
+    pub reauthentications: Vec<ReauthenticationRecord>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserRecord {
+    pub user_id: i64,
+    pub creation_time: i64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserDataRecord {
+    pub user_data_id: i64,
+    pub creation_time: i64,
+    pub dateofbirth: i64,
+    pub username: String,
+    pub realname: String,
+    pub country: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VerificationChallengeRecord {
+    pub creation_time: i64,
+    pub to_parent: bool,
+    pub email: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EmailRecord {
+    pub email_id: i64,
+    pub creation_time: i64,
+    pub to_parent: bool,
+    pub email: String,
+    pub removed: bool,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EmailChangeRecord {
+    pub creation_time: i64,
+    pub old_email: String,
+    pub new_email: String,
+    pub reverted: bool,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ParentPermissionRecord {
+    pub parent_permission_id: i64,
+    pub creation_time: i64,
+    pub parent_email: String,
+    pub parent_permission_kind: ParentPermissionKind,
+    pub terms_version: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PasswordRecord {
+    pub password_id: i64,
+    pub creation_time: i64,
+    pub from_reset: bool,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PasswordResetRecord {
+    pub creation_time: i64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiKeyRecord {
+    pub api_key_id: i64,
+    pub creation_time: i64,
+    pub api_key_kind: ApiKeyKind,
+    pub duration: i64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NotificationPreferenceRecord {
+    pub notification_preference_id: i64,
+    pub creation_time: i64,
+    pub security_notifications: bool,
+    pub locale: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceRecord {
+    pub device_id: i64,
+    pub creation_time: i64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SecurityNotificationRecord {
+    pub creation_time: i64,
+    pub security_notification_kind: &'static str,
+    pub email: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountDeletionRecord {
+    pub account_deletion_id: i64,
+    pub creation_time: i64,
+    pub account_deletion_kind: AccountDeletionKind,
+    pub scheduled_time: i64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PhoneChallengeRecord {
+    pub creation_time: i64,
+    pub phone: String,
+    pub phone_challenge_kind: &'static str,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PhoneRecord {
+    pub phone_id: i64,
+    pub creation_time: i64,
+    // None if the phone was removed
+    pub phone: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SecondFactorRecord {
+    pub second_factor_id: i64,
+    pub creation_time: i64,
+    pub enabled: bool,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReauthenticationRecord {
+    pub reauthentication_id: i64,
+    pub creation_time: i64,
+    pub reauthentication_kind: &'static str,
+}
+
+fn phone_challenge_kind_name(kind: PhoneChallengeKind) -> &'static str {
+    match kind {
+        PhoneChallengeKind::Verify => "VERIFY",
+        PhoneChallengeKind::SecondFactor => "SECOND_FACTOR",
+    }
+}
+
+fn reauthentication_kind_name(kind: ReauthenticationKind) -> &'static str {
+    match kind {
+        ReauthenticationKind::Password => "PASSWORD",
+        ReauthenticationKind::SecondFactor => "SECOND_FACTOR",
+    }
+}
+
+fn security_notification_kind_name(kind: SecurityNotificationKind) -> &'static str {
+    match kind {
+        SecurityNotificationKind::NewLogin => "NEW_LOGIN",
+        SecurityNotificationKind::PasswordChange => "PASSWORD_CHANGE",
+        SecurityNotificationKind::PasswordReset => "PASSWORD_RESET",
+        SecurityNotificationKind::EmailChange => "EMAIL_CHANGE",
+    }
+}
+
+// assembles every record linked to the user, returns None if the user doesn't exist
+pub async fn export_user(
+    con: &mut tokio_postgres::Client,
+    user_id: i64,
+) -> Result<Option<UserExport>, tokio_postgres::Error> {
+    let user = match user_service::get_by_user_id(con, user_id).await? {
+        Some(user) => user,
+        None => return Ok(None),
+    };
+
+    let verification_challenges =
+        verification_challenge_service::get_all_by_user_id(con, user_id).await?;
+
+    let phone_challenges = phone_challenge_service::get_all_by_user_id(con, user_id).await?;
+
+    // phones only store the challenge id, so look up the number from the challenge
+    let phones = phone_service::get_all_by_user_id(con, user_id)
+        .await?
+        .into_iter()
+        .map(|p| PhoneRecord {
+            phone_id: p.phone_id,
+            creation_time: p.creation_time,
+            phone: phone_challenges
+                .iter()
+                .find(|pc| Some(pc.phone_challenge_id) == p.phone_challenge_id)
+                .map(|pc| pc.phone.clone()),
+        })
+        .collect();
+
+    // emails only store the key hash, so look up the address from the challenge
+    let mut emails = vec![];
+    for e in email_service::get_all_by_user_id(con, user_id).await? {
+        if let Some(vc) = verification_challenges
+            .iter()
+            .find(|vc| vc.verification_challenge_key_hash == e.verification_challenge_key_hash)
+        {
+            emails.push(EmailRecord {
+                email_id: e.email_id,
+                creation_time: e.creation_time,
+                to_parent: vc.to_parent,
+                email: vc.email.clone(),
+                removed: email_service::exists_removal_by_email_id(con, e.email_id).await?,
+            });
+        }
+    }
+
+    // same for email changes
+    let mut email_changes = vec![];
+    for ec in email_change_service::get_all_by_user_id(con, user_id).await? {
+        let old_vc = verification_challenges.iter().find(|vc| {
+            vc.verification_challenge_key_hash == ec.old_verification_challenge_key_hash
+        });
+        let new_vc = verification_challenges.iter().find(|vc| {
+            vc.verification_challenge_key_hash == ec.new_verification_challenge_key_hash
+        });
+        if let (Some(old_vc), Some(new_vc)) = (old_vc, new_vc) {
+            email_changes.push(EmailChangeRecord {
+                creation_time: ec.creation_time,
+                old_email: old_vc.email.clone(),
+                new_email: new_vc.email.clone(),
+                reverted: email_change_service::exists_revert_by_email_change_key_hash(
+                    con,
+                    &ec.email_change_key_hash,
+                )
+                .await?,
+            });
+        }
+    }
+
+    // same for parent permissions
+    let mut parent_permissions = vec![];
+    for pp in parent_permission_service::get_all_by_user_id(con, user_id).await? {
+        if let Some(vc) = verification_challenges
+            .iter()
+            .find(|vc| vc.verification_challenge_key_hash == pp.verification_challenge_key_hash)
+        {
+            parent_permissions.push(ParentPermissionRecord {
+                parent_permission_id: pp.parent_permission_id,
+                creation_time: pp.creation_time,
+                parent_email: vc.email.clone(),
+                parent_permission_kind: pp.parent_permission_kind,
+                terms_version: pp.terms_version,
+            });
+        }
+    }
+
+    Ok(Some(UserExport {
+        schema_version: EXPORT_SCHEMA_VERSION,
+        export_time: utils::current_time_millis(),
+        user: UserRecord {
+            user_id: user.user_id,
+            creation_time: user.creation_time,
+        },
+        user_data: user_data_service::get_all_by_user_id(con, user_id)
+            .await?
+            .into_iter()
+            .map(|x| UserDataRecord {
+                user_data_id: x.user_data_id,
+                creation_time: x.creation_time,
+                dateofbirth: x.dateofbirth,
+                username: x.username,
+                realname: x.realname,
+                country: x.country,
+            })
+            .collect(),
+        verification_challenges: verification_challenges
+            .into_iter()
+            .map(|x| VerificationChallengeRecord {
+                creation_time: x.creation_time,
+                to_parent: x.to_parent,
+                email: x.email,
+            })
+            .collect(),
+        emails,
+        email_changes,
+        parent_permissions,
+        passwords: password_service::get_all_by_user_id(con, user_id)
+            .await?
+            .into_iter()
+            .map(|x| PasswordRecord {
+                password_id: x.password_id,
+                creation_time: x.creation_time,
+                from_reset: x.password_reset_key_hash.is_some(),
+            })
+            .collect(),
+        password_resets: password_reset_service::get_all_by_user_id(con, user_id)
+            .await?
+            .into_iter()
+            .map(|x| PasswordResetRecord {
+                creation_time: x.creation_time,
+            })
+            .collect(),
+        api_keys: api_key_service::get_all_by_user_id(con, user_id)
+            .await?
+            .into_iter()
+            .map(|x| ApiKeyRecord {
+                api_key_id: x.api_key_id,
+                creation_time: x.creation_time,
+                api_key_kind: x.api_key_kind,
+                duration: x.duration,
+            })
+            .collect(),
+        notification_preferences: notification_preference_service::get_all_by_user_id(con, user_id)
+            .await?
+            .into_iter()
+            .map(|x| NotificationPreferenceRecord {
+                notification_preference_id: x.notification_preference_id,
+                creation_time: x.creation_time,
+                security_notifications: x.security_notifications,
+                locale: x.locale,
+            })
+            .collect(),
+        devices: device_service::get_all_by_user_id(con, user_id)
+            .await?
+            .into_iter()
+            .map(|x| DeviceRecord {
+                device_id: x.device_id,
+                creation_time: x.creation_time,
+            })
+            .collect(),
+        security_notifications: security_notification_service::get_all_by_user_id(con, user_id)
+            .await?
+            .into_iter()
+            .map(|x| SecurityNotificationRecord {
+                creation_time: x.creation_time,
+                security_notification_kind: security_notification_kind_name(
+                    x.security_notification_kind,
+                ),
+                email: x.email,
+            })
+            .collect(),
+        account_deletions: account_deletion_service::get_all_by_user_id(con, user_id)
+            .await?
+            .into_iter()
+            .map(|x| AccountDeletionRecord {
+                account_deletion_id: x.account_deletion_id,
+                creation_time: x.creation_time,
+                account_deletion_kind: x.account_deletion_kind,
+                scheduled_time: x.scheduled_time,
+            })
+            .collect(),
+        phone_challenges: phone_challenges
+            .into_iter()
+            .map(|x| PhoneChallengeRecord {
+                creation_time: x.creation_time,
+                phone: x.phone,
+                phone_challenge_kind: phone_challenge_kind_name(x.phone_challenge_kind),
+            })
+            .collect(),
+        phones,
+        second_factors: second_factor_service::get_all_by_user_id(con, user_id)
+            .await?
+            .into_iter()
+            .map(|x| SecondFactorRecord {
+                second_factor_id: x.second_factor_id,
+                creation_time: x.creation_time,
+                enabled: x.enabled,
+            })
+            .collect(),
+        reauthentications: reauthentication_service::get_all_by_user_id(con, user_id)
+            .await?
+            .into_iter()
+            .map(|x| ReauthenticationRecord {
+                reauthentication_id: x.reauthentication_id,
+                creation_time: x.creation_time,
+                reauthentication_kind: reauthentication_kind_name(x.reauthentication_kind),
+            })
+            .collect(),
+    }))
+}
@@ -1,7 +1,9 @@
 use std::fmt::Display;
 
 use super::Data;
+use actix_web::http::header;
 use actix_web::web;
+use actix_web::HttpRequest;
 use actix_web::HttpResponse;
 use actix_web::Responder;
 use actix_web::ResponseError;
@@ -10,21 +12,48 @@ use auth_service_api::response;
 use auth_service_api::response::AuthError;
 use actix_web::http::StatusCode;
 
+use super::account_deletion_service;
 use super::api_key_service;
+use super::consent;
 use super::db_types::*;
+use super::device_service;
+use super::email_change_service;
+use super::email_normalization;
+use super::email_outbox_service;
 use super::email_service;
+use super::email_suppression_service;
+use super::export;
+use super::notification_preference_service;
+use super::parent_access_service;
+use super::parent_permission_service;
+use super::password_policy;
 use super::password_reset_service;
 use super::password_service;
+use super::pepper;
+use super::phone_challenge_service;
+use super::phone_service;
+use super::reauthentication_service;
+use super::second_factor_service;
+use super::security_notification_service;
+use super::token_hasher::TokenKind;
 use super::user_data_service;
 use super::user_service;
 use super::utils;
 use super::verification_challenge_service;
+use super::verification_code_service;
 
-use mail_service_api::client::MailService;
-use mail_service_api::response::MailError;
+use tokio_postgres::GenericClient;
 
 static FIFTEEN_MINUTES: i64 = 15 * 60 * 1000;
-static THIRTEEN_YEARS: i64 = (13.0 * 365.25 * 24.0 * 60.0 * 60.0 * 1000.0) as i64;
+static ONE_HOUR: i64 = 60 * 60 * 1000;
+static ONE_DAY: i64 = 24 * 60 * 60 * 1000;
+static ONE_WEEK: i64 = 7 * 24 * 60 * 60 * 1000;
+
+// wrong guesses allowed before a verification code stops working
+static MAX_VERIFICATION_CODE_ATTEMPTS: i64 = 5;
+// asking for new codes mustn't give unlimited guesses, so these are counted across all of a user's codes
+static MAX_VERIFICATION_CODES_PER_DAY: i64 = 5;
+static MAX_VERIFICATION_CODE_FAILURES_PER_DAY: i64 = 10;
 
 #[derive(Debug, Clone)]
 pub struct AppError(response::AuthError);
@@ -39,17 +68,6 @@ fn report_postgres_err(e: tokio_postgres::Error) -> AppError {
     AppError(response::AuthError::InternalServerError)
 }
 
-fn report_mail_err(e: MailError) -> AppError {
-    let ae = match e {
-        MailError::DestinationBounced => response::AuthError::EmailBounced,
-        MailError::DestinationProhibited => response::AuthError::EmailBounced,
-        // TODO: log this
-        _ => response::AuthError::InternalServerError,
-    };
-    log::warn!("{}", e);
-    AppError(ae)
-}
-
 impl Display for AppError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         self.0.fmt(f)
@@ -70,9 +88,72 @@ impl ResponseError for AppError {
         match self.0 {
             AuthError::DecodeError => StatusCode::BAD_GATEWAY,
             AuthError::InternalServerError => StatusCode::INTERNAL_SERVER_ERROR,
-            AuthError::ApiKeyUnauthorized => StatusCode::UNAUTHORIZED,
-            AuthError::BadRequest => StatusCode::BAD_REQUEST,
-            AuthError::NotFound => StatusCode::NOT_FOUND,
+            // texting isn't set up on this server
+            AuthError::SmsUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            // the request is malformed or a field is invalid
+            AuthError::BadRequest
+            | AuthError::UserRealnameInvalid
+            | AuthError::UserUsernameInvalid
+            | AuthError::UserCountryInvalid
+            | AuthError::PhoneInvalid
+            | AuthError::NotificationPreferenceLocaleInvalid
+            | AuthError::ParentPermissionTermsVersionInvalid
+            | AuthError::VerificationChallengeWrongKind => StatusCode::BAD_REQUEST,
+            // well formed, but refused by policy
+            AuthError::PasswordPolicyViolated(_)
+            | AuthError::EmailDomainBlocked
+            | AuthError::EmailBounced => StatusCode::UNPROCESSABLE_ENTITY,
+            // the caller hasn't proven who they are, or hasn't recently enough
+            AuthError::ApiKeyUnauthorized
+            | AuthError::ApiKeyNonexistent
+            | AuthError::PasswordIncorrect
+            | AuthError::ReauthenticationRequired
+            | AuthError::SecondFactorRequired
+            | AuthError::EmailChangePasswordRequired
+            | AuthError::EmailDeliveryEventUnauthorized
+            | AuthError::VerificationCodeIncorrect
+            | AuthError::PhoneCodeIncorrect => StatusCode::UNAUTHORIZED,
+            AuthError::NotFound
+            | AuthError::UserNonexistent
+            | AuthError::UserDataNonexistent
+            | AuthError::EmailNonexistent
+            | AuthError::PasswordNonexistent
+            | AuthError::PasswordResetNonexistent
+            | AuthError::VerificationChallengeNonexistent
+            | AuthError::VerificationCodeNonexistent
+            | AuthError::AccountDeletionNonexistent
+            | AuthError::EmailChangeNonexistent
+            | AuthError::ParentAccessNonexistent
+            | AuthError::ParentPermissionNonexistent
+            | AuthError::PhoneChallengeNonexistent
+            | AuthError::PhoneNonexistent
+            | AuthError::SecurityNotificationNonexistent => StatusCode::NOT_FOUND,
+            // the key or code has stopped working
+            AuthError::PasswordResetTimedOut
+            | AuthError::VerificationChallengeTimedOut
+            | AuthError::EmailChangeTimedOut
+            | AuthError::ParentAccessTimedOut
+            | AuthError::PhoneChallengeTimedOut
+            | AuthError::SecurityNotificationTimedOut => StatusCode::GONE,
+            // conflicts with what is already there
+            AuthError::UserUsernameTaken
+            | AuthError::EmailExistent
+            | AuthError::PasswordExistent
+            | AuthError::PhoneExistent
+            | AuthError::AccountDeletionExistent
+            | AuthError::VerificationChallengeUsed
+            | AuthError::PhoneChallengeUsed
+            | AuthError::EmailChangeReverted
+            | AuthError::EmailChangeSuperseded
+            | AuthError::EmailPrimary
+            | AuthError::SecondFactorEnabled => StatusCode::CONFLICT,
+            // too many requests or wrong guesses, try again later
+            AuthError::EmailCooldown
+            | AuthError::PasswordResetCooldown
+            | AuthError::PhoneCooldown
+            | AuthError::VerificationCodeLocked
+            | AuthError::PhoneChallengeLocked => StatusCode::TOO_MANY_REQUESTS,
+            // eg AuthError::Unknown
             _ => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
@@ -99,6 +180,7 @@ async fn fill_user_data(
         dateofbirth: user_data.dateofbirth,
         username: user_data.username,
         realname: user_data.realname,
+        country: user_data.country,
     })
 }
 
@@ -130,9 +212,25 @@ async fn fill_email(
         .map_err(report_postgres_err)?
         .ok_or(response::AuthError::VerificationChallengeNonexistent)?;
 
+    let primary =
+        match email_service::get_primary_by_user_id(con, verification_challenge.creator_user_id)
+            .await
+            .map_err(report_postgres_err)?
+        {
+            Some(primary_email) => primary_email.email_id == email.email_id,
+            None => false,
+        };
+
+    // lets the user know to fix an address that bounced or marked our emails as spam
+    let deliverable = !email_suppression_service::is_suppressed(con, &verification_challenge.email)
+        .await
+        .map_err(report_postgres_err)?;
+
     Ok(response::Email {
         email_id: email.email_id,
         creation_time: email.creation_time,
+        primary,
+        deliverable,
         verification_challenge: fill_verification_challenge(con, verification_challenge).await?,
     })
 }
@@ -163,6 +261,107 @@ async fn fill_password(
     })
 }
 
+async fn fill_account_deletion(
+    _con: &tokio_postgres::Client,
+    account_deletion: AccountDeletion,
+) -> Result<response::AccountDeletion, AppError> {
+    Ok(response::AccountDeletion {
+        account_deletion_id: account_deletion.account_deletion_id,
+        creation_time: account_deletion.creation_time,
+        creator_user_id: account_deletion.creator_user_id,
+        account_deletion_kind: account_deletion.account_deletion_kind,
+        scheduled_time: account_deletion.scheduled_time,
+    })
+}
+
+async fn fill_parent_permission(
+    con: &mut tokio_postgres::Client,
+    parent_permission: ParentPermission,
+) -> Result<response::ParentPermission, AppError> {
+    let verification_challenge =
+        verification_challenge_service::get_by_verification_challenge_key_hash(
+            con,
+            &parent_permission.verification_challenge_key_hash,
+        )
+        .await
+        .map_err(report_postgres_err)?
+        .ok_or(response::AuthError::VerificationChallengeNonexistent)?;
+
+    Ok(response::ParentPermission {
+        parent_permission_id: parent_permission.parent_permission_id,
+        creation_time: parent_permission.creation_time,
+        creator_user_id: parent_permission.creator_user_id,
+        verification_challenge: fill_verification_challenge(con, verification_challenge).await?,
+        parent_permission_kind: parent_permission.parent_permission_kind,
+        terms_version: parent_permission.terms_version,
+    })
+}
+
+async fn fill_parent_child(
+    con: &mut tokio_postgres::Client,
+    user_id: i64,
+    pending_verification_challenge: Option<VerificationChallenge>,
+) -> Result<response::ParentChild, AppError> {
+    let user_data = user_data_service::get_by_user_id(con, user_id)
+        .await
+        .map_err(report_postgres_err)?
+        .ok_or(response::AuthError::UserDataNonexistent)?;
+
+    let parent_permission = match parent_permission_service::get_by_user_id(con, user_id)
+        .await
+        .map_err(report_postgres_err)?
+    {
+        Some(parent_permission) => Some(fill_parent_permission(con, parent_permission).await?),
+        None => None,
+    };
+
+    // the sessions the child currently has open
+    let api_keys =
+        api_key_service::get_current_by_creator_user_id(con, user_id, utils::current_time_millis())
+            .await
+            .map_err(report_postgres_err)?;
+
+    let mut resp_api_keys = vec![];
+    for u in api_keys.into_iter() {
+        resp_api_keys.push(fill_api_key(con, u, None).await?);
+    }
+
+    let pending_verification_challenge = match pending_verification_challenge {
+        Some(vc) => Some(fill_verification_challenge(con, vc).await?),
+        None => None,
+    };
+
+    Ok(response::ParentChild {
+        user_data: fill_user_data(con, user_data).await?,
+        parent_permission,
+        api_keys: resp_api_keys,
+        pending_verification_challenge,
+    })
+}
+
+async fn fill_parent_access(
+    _con: &tokio_postgres::Client,
+    parent_access: ParentAccess,
+) -> Result<response::ParentAccess, AppError> {
+    Ok(response::ParentAccess {
+        creation_time: parent_access.creation_time,
+        email: parent_access.email,
+    })
+}
+
+async fn fill_notification_preference(
+    _con: &tokio_postgres::Client,
+    notification_preference: NotificationPreference,
+) -> Result<response::NotificationPreference, AppError> {
+    Ok(response::NotificationPreference {
+        notification_preference_id: notification_preference.notification_preference_id,
+        creation_time: notification_preference.creation_time,
+        creator_user_id: notification_preference.creator_user_id,
+        security_notifications: notification_preference.security_notifications,
+        locale: notification_preference.locale,
+    })
+}
+
 async fn fill_password_reset(
     _con: &tokio_postgres::Client,
     password_reset: PasswordReset,
@@ -183,43 +382,187 @@ async fn fill_verification_challenge(
     })
 }
 
+async fn fill_phone_challenge(
+    _con: &tokio_postgres::Client,
+    phone_challenge: PhoneChallenge,
+) -> Result<response::PhoneChallenge, AppError> {
+    Ok(response::PhoneChallenge {
+        phone_challenge_id: phone_challenge.phone_challenge_id,
+        creation_time: phone_challenge.creation_time,
+        creator_user_id: phone_challenge.creator_user_id,
+        phone: phone_challenge.phone,
+    })
+}
+
+async fn fill_phone(
+    con: &mut tokio_postgres::Client,
+    phone: Phone,
+) -> Result<response::Phone, AppError> {
+    // the number is held by the challenge it was verified with
+    let phone_challenge = match phone.phone_challenge_id {
+        Some(phone_challenge_id) => Some(
+            phone_challenge_service::get_by_phone_challenge_id(con, phone_challenge_id)
+                .await
+                .map_err(report_postgres_err)?
+                .ok_or(response::AuthError::PhoneChallengeNonexistent)?,
+        ),
+        None => None,
+    };
+
+    Ok(response::Phone {
+        phone_id: phone.phone_id,
+        creation_time: phone.creation_time,
+        creator_user_id: phone.creator_user_id,
+        phone: phone_challenge.map(|x| x.phone),
+    })
+}
+
+async fn fill_second_factor(
+    _con: &tokio_postgres::Client,
+    second_factor: SecondFactor,
+) -> Result<response::SecondFactor, AppError> {
+    Ok(response::SecondFactor {
+        second_factor_id: second_factor.second_factor_id,
+        creation_time: second_factor.creation_time,
+        creator_user_id: second_factor.creator_user_id,
+        enabled: second_factor.enabled,
+    })
+}
+
+async fn fill_reauthentication(
+    _con: &tokio_postgres::Client,
+    reauthentication: Reauthentication,
+) -> Result<response::Reauthentication, AppError> {
+    Ok(response::Reauthentication {
+        reauthentication_id: reauthentication.reauthentication_id,
+        creation_time: reauthentication.creation_time,
+        creator_user_id: reauthentication.creator_user_id,
+    })
+}
+
 // returns the api key if not cancelled and the time is in bounds
 pub async fn get_api_key_if_current_noverify(
+    data: &Data,
     con: &mut tokio_postgres::Client,
     api_key: &str,
 ) -> Result<ApiKey, AppError> {
-    let creator_api_key = api_key_service::get_by_api_key_hash(con, &utils::hash_str(api_key))
-        .await
-        .map_err(report_postgres_err)?
-        .ok_or(response::AuthError::ApiKeyNonexistent)?;
+    let creator_api_key = get_api_key_if_current_any_kind(data, con, api_key).await?;
 
-    if utils::current_time_millis() > creator_api_key.creation_time + creator_api_key.duration {
-        Err(response::AuthError::ApiKeyUnauthorized)?;
+    // ensure is valid, noemail, or noparent
+    match creator_api_key.api_key_kind {
+        request::ApiKeyKind::Valid => Ok(creator_api_key),
+        request::ApiKeyKind::NoEmail => Ok(creator_api_key),
+        request::ApiKeyKind::NoParent => Ok(creator_api_key),
+        _ => Err(response::AuthError::ApiKeyUnauthorized)?,
     }
+}
+
+// like get_api_key_if_current_noverify, but also lets through keys whose password has expired
+// only for changing the password, which is the one thing such a key is good for
+async fn get_api_key_if_current_or_password_expired(
+    data: &Data,
+    con: &mut tokio_postgres::Client,
+    api_key: &str,
+) -> Result<ApiKey, AppError> {
+    let creator_api_key = get_api_key_if_current_any_kind(data, con, api_key).await?;
 
-    // ensure is valid, noemail, or noparent
     match creator_api_key.api_key_kind {
         request::ApiKeyKind::Valid => Ok(creator_api_key),
         request::ApiKeyKind::NoEmail => Ok(creator_api_key),
         request::ApiKeyKind::NoParent => Ok(creator_api_key),
+        request::ApiKeyKind::PasswordExpired => Ok(creator_api_key),
         _ => Err(response::AuthError::ApiKeyUnauthorized)?,
     }
 }
 
-// returns the api key if in bounds and it is valid
-pub async fn get_api_key_if_valid(
+// returns the api key if the time is in bounds, whatever its kind
+async fn get_api_key_if_current_any_kind(
+    data: &Data,
     con: &mut tokio_postgres::Client,
     api_key: &str,
 ) -> Result<ApiKey, AppError> {
-    let creator_api_key = api_key_service::get_by_api_key_hash(con, &utils::hash_str(api_key))
-        .await
-        .map_err(report_postgres_err)?
-        .ok_or(response::AuthError::ApiKeyNonexistent)?;
+    let creator_api_key =
+        api_key_service::get_by_api_key_hashes(con, &data.token_hasher.candidate_hashes(api_key))
+            .await
+            .map_err(report_postgres_err)?
+            .ok_or(response::AuthError::ApiKeyNonexistent)?;
 
     if utils::current_time_millis() > creator_api_key.creation_time + creator_api_key.duration {
         Err(response::AuthError::ApiKeyUnauthorized)?;
     }
 
+    Ok(creator_api_key)
+}
+
+// returns the kind of key the user should have right now, given their password age, email and parent permission
+pub async fn get_verification_status(
+    data: &Data,
+    con: &mut impl GenericClient,
+    user_id: i64,
+) -> Result<request::ApiKeyKind, AppError> {
+    // an old password has to be changed before anything else can be done
+    if let Some(max_password_age) = data.max_password_age {
+        let password = password_service::get_by_user_id(con, user_id)
+            .await
+            .map_err(report_postgres_err)?
+            .ok_or(response::AuthError::PasswordNonexistent)?;
+
+        if password.creation_time + max_password_age < utils::current_time_millis() {
+            return Ok(request::ApiKeyKind::PasswordExpired);
+        }
+    }
+
+    if email_service::get_primary_by_user_id(con, user_id)
+        .await
+        .map_err(report_postgres_err)?
+        .is_none()
+    {
+        return Ok(request::ApiKeyKind::NoEmail);
+    }
+
+    let user_data = user_data_service::get_by_user_id(con, user_id)
+        .await
+        .map_err(report_postgres_err)?
+        .ok_or(response::AuthError::UserDataNonexistent)?;
+
+    if !consent::needs_parent_permission(
+        &user_data,
+        &data.parental_consent_ages,
+        utils::current_time_millis(),
+    ) {
+        return Ok(request::ApiKeyKind::Valid);
+    }
+
+    match parent_permission_service::get_by_user_id(con, user_id)
+        .await
+        .map_err(report_postgres_err)?
+    {
+        Some(pp)
+            if consent::is_parent_permission_current(
+                &pp,
+                data.parental_consent_validity,
+                utils::current_time_millis(),
+            ) =>
+        {
+            Ok(request::ApiKeyKind::Valid)
+        }
+        _ => Ok(request::ApiKeyKind::NoParent),
+    }
+}
+
+// returns the api key if in bounds and it is valid
+// the kind stored with the key is only what it was at login, so the user's verification status is rechecked
+// the returned key has its kind set to the current one
+pub async fn get_api_key_if_valid(
+    data: &Data,
+    con: &mut tokio_postgres::Client,
+    api_key: &str,
+) -> Result<ApiKey, AppError> {
+    let mut creator_api_key = get_api_key_if_current_noverify(data, con, api_key).await?;
+
+    creator_api_key.api_key_kind =
+        get_verification_status(data, con, creator_api_key.creator_user_id).await?;
+
     // ensure is valid
     match creator_api_key.api_key_kind {
         request::ApiKeyKind::Valid => Ok(creator_api_key),
@@ -227,6 +570,56 @@ pub async fn get_api_key_if_valid(
     }
 }
 
+// returns the parent access if it exists and hasn't timed out
+pub async fn get_parent_access_if_valid(
+    data: &Data,
+    con: &mut tokio_postgres::Client,
+    parent_access_key: &str,
+) -> Result<ParentAccess, AppError> {
+    let parent_access = parent_access_service::get_by_parent_access_key_hashes(
+        con,
+        &data.token_hasher.candidate_hashes(parent_access_key),
+    )
+    .await
+    .map_err(report_postgres_err)?
+    .ok_or(response::AuthError::ParentAccessNonexistent)?;
+
+    if parent_access.expiry_time < utils::current_time_millis() {
+        Err(response::AuthError::ParentAccessTimedOut)?;
+    }
+
+    Ok(parent_access)
+}
+
+// returns the api key and its user's own email if the key is valid
+// a parent account is any account whose own email is some child's parent email
+pub async fn get_parent_email_if_valid(
+    data: &Data,
+    con: &mut tokio_postgres::Client,
+    api_key: &str,
+) -> Result<(ApiKey, String), AppError> {
+    let parent_key = get_api_key_if_valid(data, con, api_key).await?;
+
+    let parent_email = get_primary_email_address(con, parent_key.creator_user_id)
+        .await?
+        .ok_or(response::AuthError::EmailNonexistent)?;
+
+    Ok((parent_key, parent_email))
+}
+
+// returns true if the user's current parent email is this one
+async fn is_parent_of(
+    con: &mut tokio_postgres::Client,
+    parent_email: &str,
+    user_id: i64,
+) -> Result<bool, AppError> {
+    let children = email_service::get_child_user_ids_by_parent_email(con, parent_email)
+        .await
+        .map_err(report_postgres_err)?;
+
+    Ok(children.contains(&user_id))
+}
+
 // respond with info about stuff
 pub async fn info(data: web::Data<Data>) -> Result<impl Responder, AppError> {
     return Ok(web::Json(response::Info {
@@ -240,13 +633,27 @@ pub async fn info(data: web::Data<Data>) -> Result<impl Responder, AppError> {
     }));
 }
 
+// identifies the browser or app a request came from
+fn get_device_hash(req: &HttpRequest) -> String {
+    let user_agent = req
+        .headers()
+        .get(header::USER_AGENT)
+        .and_then(|x| x.to_str().ok())
+        .unwrap_or("");
+    utils::hash_str(user_agent)
+}
+
 pub async fn api_key_new_with_email(
+    req: HttpRequest,
     data: web::Data<Data>,
     props: web::Json<request::ApiKeyNewWithEmailProps>,
 ) -> Result<impl Responder, AppError> {
     let con = &mut *data.db.lock().await;
 
-    let email = email_service::get_by_own_email(con, &props.email)
+    let email_address =
+        email_normalization::normalize_email(&props.email, &data.email_folding_rules);
+
+    let email = email_service::get_by_own_email(con, &email_address)
         .await
         .map_err(report_postgres_err)?
         .ok_or(response::AuthError::EmailNonexistent)?;
@@ -266,10 +673,20 @@ pub async fn api_key_new_with_email(
         .ok_or(response::AuthError::UserNonexistent)?;
 
     // now delegate
-    internal_api_key_new_valid(con, userdata, props.password.clone(), props.duration).await
+    internal_api_key_new_valid(
+        &data,
+        con,
+        userdata,
+        props.password.clone(),
+        props.duration,
+        get_device_hash(&req),
+        props.second_factor_code.clone(),
+    )
+    .await
 }
 
 pub async fn api_key_new_with_username(
+    req: HttpRequest,
     data: web::Data<Data>,
     props: web::Json<request::ApiKeyNewWithUsernameProps>,
 ) -> Result<impl Responder, AppError> {
@@ -281,14 +698,26 @@ pub async fn api_key_new_with_username(
         .ok_or(response::AuthError::UserNonexistent)?;
 
     // now delegate
-    internal_api_key_new_valid(con, userdata, props.password.clone(), props.duration).await
+    internal_api_key_new_valid(
+        &data,
+        con,
+        userdata,
+        props.password.clone(),
+        props.duration,
+        get_device_hash(&req),
+        props.second_factor_code.clone(),
+    )
+    .await
 }
 
 pub async fn internal_api_key_new_valid(
+    data: &Data,
     con: &mut tokio_postgres::Client,
     user_data: UserData,
     user_password: String,
     duration: i64,
+    device_hash: String,
+    second_factor_code: Option<String>,
 ) -> Result<impl Responder, AppError> {
     // get user password
     let password = password_service::get_by_user_id(con, user_data.creator_user_id)
@@ -296,49 +725,97 @@ pub async fn internal_api_key_new_valid(
         .map_err(report_postgres_err)?
         .ok_or(response::AuthError::PasswordNonexistent)?;
 
-    // validate password with argon2 (password hashing algorithm)
-    if !utils::verify_password(&user_password, &password.password_hash)
+    // validate password with argon2 (password hashing algorithm), or the format it was imported in
+    if !data
+        .peppers
+        .verify_password(&user_password, &password.password_hash)
         .map_err(report_internal_err)?
     {
         Err(response::AuthError::PasswordIncorrect)?;
     }
 
-    let verification_status = if email_service::get_own_by_user_id(con, user_data.creator_user_id)
-        .await
-        .map_err(report_postgres_err)?
-        .is_some()
-    {
-        if utils::current_time_millis() - user_data.dateofbirth < THIRTEEN_YEARS {
-            match email_service::get_parent_by_user_id(con, user_data.creator_user_id)
-                .await
-                .map_err(report_postgres_err)?
-            {
-                Some(_) => request::ApiKeyKind::Valid,
-                None => request::ApiKeyKind::NoParent,
-            }
-        } else {
-            request::ApiKeyKind::Valid
-        }
+    // with a second factor, the right password only gets a code texted to the user's phone
+    let reauthentication_kind = if is_second_factor_enabled(con, user_data.creator_user_id).await? {
+        check_second_factor(
+            data,
+            con,
+            user_data.creator_user_id,
+            second_factor_code.as_deref(),
+        )
+        .await?;
+        ReauthenticationKind::SecondFactor
     } else {
-        request::ApiKeyKind::NoEmail
+        ReauthenticationKind::Password
     };
 
+    // a hash from before the current pepper, or imported from an older system, can only be
+    // rewrapped now, while we have the password
+    if data.peppers.needs_rehash(&password.password_hash) {
+        let password_hash = data
+            .peppers
+            .hash_password(&user_password)
+            .map_err(report_internal_err)?;
+
+        password_service::update_password_hash(con, password.password_id, &password_hash)
+            .await
+            .map_err(report_postgres_err)?;
+    }
+
+    let verification_status = get_verification_status(data, con, user_data.creator_user_id).await?;
+
     let mut sp = con.transaction().await.map_err(report_postgres_err)?;
 
-    let raw_api_key = utils::gen_random_string();
+    let raw_api_key = data.token_hasher.gen_token(TokenKind::ApiKey);
     // add new api key
     let api_key = api_key_service::add(
         &mut sp,
         user_data.creator_user_id,
-        utils::hash_str(&raw_api_key),
+        data.token_hasher.hash(&raw_api_key),
         verification_status,
         duration,
     )
     .await
     .map_err(report_postgres_err)?;
 
+    // logging in counts as proving who you are, so a new key can make sensitive changes straight away
+    reauthentication_service::add(
+        &mut sp,
+        user_data.creator_user_id,
+        api_key.api_key_hash.clone(),
+        reauthentication_kind,
+    )
+    .await
+    .map_err(report_postgres_err)?;
+
+    // remember the device, and find out if we've seen it before
+    let new_device = !device_service::exists_by_user_id_and_device_hash(
+        &mut sp,
+        user_data.creator_user_id,
+        &device_hash,
+    )
+    .await
+    .map_err(report_postgres_err)?;
+
+    if new_device {
+        device_service::add(&mut sp, user_data.creator_user_id, device_hash)
+            .await
+            .map_err(report_postgres_err)?;
+    }
+
     sp.commit().await.map_err(report_postgres_err)?;
 
+    if new_device {
+        let primary_email = get_primary_email_address(con, user_data.creator_user_id).await?;
+        notify_security_event(
+            data,
+            con,
+            &user_data,
+            SecurityNotificationKind::NewLogin,
+            primary_email.into_iter().collect(),
+        )
+        .await;
+    }
+
     Ok(web::Json(
         fill_api_key(con, api_key, Some(raw_api_key)).await?,
     ))
@@ -351,9 +828,9 @@ pub async fn api_key_new_cancel(
     let con = &mut *data.db.lock().await;
 
     // validate api key
-    let creator_key = get_api_key_if_valid(con, &props.api_key).await?;
+    let creator_key = get_api_key_if_valid(&data, con, &props.api_key).await?;
 
-    let to_cancel_key = get_api_key_if_valid(con, &props.api_key_to_cancel).await?;
+    let to_cancel_key = get_api_key_if_valid(&data, con, &props.api_key_to_cancel).await?;
 
     if creator_key.creator_user_id != to_cancel_key.creator_user_id {
         Err(response::AuthError::ApiKeyUnauthorized)?;
@@ -378,101 +855,611 @@ pub async fn api_key_new_cancel(
     Ok(web::Json(fill_api_key(con, key_cancel, None).await?))
 }
 
-pub async fn send_parent_permission_email(
-    mail_service: &MailService,
-    target_email: &str,
-    user_name: &str,
-    app_pub_origin: &str,
-    verification_challenge_key: &str,
-) -> Result<(), AppError> {
-    let _ = mail_service
-        .mail_new(mail_service_api::request::MailNewProps {
-            request_id: 0,
-            destination: target_email.to_owned(),
-            topic: "parent_permission".to_owned(),
-            title: format!("{}: Parent Permission For {}", app_pub_origin, user_name),
-            content: [
-                &format!(
-          "<p>Your child, <code>{}</code>, has requested permission to use: <code>{}</code></p>",
-          user_name, app_pub_origin
-        ),
-                "<p>If you did not make this request, then feel free to ignore.</p>",
-                "<p>This link is valid for up to 15 minutes.</p>",
-                "<p>Do not share this link with others.</p>",
-                &format!(
-          "<p>Verification link: {}/parent_permission_confirm?verificationChallengeKey={}</p>",
-          app_pub_origin, verification_challenge_key
-        ),
-            ]
-            .join(""),
-        })
-        .await
-        .map_err(report_mail_err)?;
-
-    Ok(())
-}
-
-pub async fn send_email_verification_email(
-    mail_service: &MailService,
-    target_email: &str,
-    user_name: &str,
-    app_pub_origin: &str,
-    verification_challenge_key: &str,
-) -> Result<(), AppError> {
-    let _ = mail_service
-        .mail_new(mail_service_api::request::MailNewProps {
-            request_id: 0,
-            destination: target_email.to_owned(),
-            topic: "verification_challenge".to_owned(),
-            title: format!("{}: Email Verification", app_pub_origin),
-            content: [
-                &format!(
-                    "<p>This email has been sent to verify for: <code>{}</code> </p>",
-                    &user_name
-                ),
-                "<p>If you did not make this request, then feel free to ignore.</p>",
-                "<p>This link is valid for up to 15 minutes.</p>",
-                "<p>Do not share this link with others.</p>",
-                &format!(
-                    "<p>Verification link: {}/email_confirm?verificationChallengeKey={}</p>",
-                    app_pub_origin, verification_challenge_key
-                ),
-            ]
-            .join(""),
-        })
-        .await
-        .map_err(report_mail_err)?;
-    Ok(())
-}
-
-pub async fn verification_challenge_new(
+pub async fn api_key_new_cancel_all(
     data: web::Data<Data>,
-    props: web::Json<request::VerificationChallengeNewProps>,
+    props: web::Json<request::ApiKeyNewCancelAllProps>,
 ) -> Result<impl Responder, AppError> {
-    // avoid sending email to obviously bad addresses
-    if props.email.is_empty() {
-        Err(response::AuthError::EmailBounced)?;
-    }
-
     let con = &mut *data.db.lock().await;
 
-    // you need to have an account but its fine not to be verified yet
-    let api_key = get_api_key_if_current_noverify(con, &props.api_key).await?;
+    // no api key verification needed, the key from the notification email is proof enough
+    let security_notification =
+        security_notification_service::get_by_security_notification_key_hashes(
+            con,
+            &data
+                .token_hasher
+                .candidate_hashes(&props.security_notification_key),
+        )
+        .await
+        .map_err(report_postgres_err)?
+        .ok_or(response::AuthError::SecurityNotificationNonexistent)?;
 
-    // don't let people spam emails
-    let num_emails = verification_challenge_service::get_num_challenges_by_creator_between(
+    // deny if timed out
+    if ONE_WEEK + security_notification.creation_time < utils::current_time_millis() {
+        Err(response::AuthError::SecurityNotificationTimedOut)?;
+    }
+
+    let to_cancel_keys = api_key_service::get_current_by_creator_user_id(
         con,
-        api_key.creator_user_id,
-        utils::current_time_millis() - FIFTEEN_MINUTES,
+        security_notification.creator_user_id,
         utils::current_time_millis(),
     )
     .await
     .map_err(report_postgres_err)?;
 
-    // limit to 4 emails in past 15 minutes
-    if num_emails > 4 {
-        // TODO: more descriptive error
-        Err(response::AuthError::EmailCooldown)?;
+    let mut sp = con.transaction().await.map_err(report_postgres_err)?;
+
+    // cancel every key the user has
+    let mut key_cancels = vec![];
+    for to_cancel_key in to_cancel_keys.into_iter() {
+        key_cancels.push(
+            api_key_service::add(
+                &mut sp,
+                security_notification.creator_user_id,
+                to_cancel_key.api_key_hash,
+                request::ApiKeyKind::Cancel,
+                0,
+            )
+            .await
+            .map_err(report_postgres_err)?,
+        );
+    }
+
+    sp.commit().await.map_err(report_postgres_err)?;
+
+    // return json
+    let mut resp_api_keys = vec![];
+    for u in key_cancels.into_iter() {
+        resp_api_keys.push(fill_api_key(con, u, None).await?);
+    }
+
+    Ok(web::Json(resp_api_keys))
+}
+
+pub async fn api_key_new_cancel_for_child(
+    data: web::Data<Data>,
+    props: web::Json<request::ApiKeyNewCancelForChildProps>,
+) -> Result<impl Responder, AppError> {
+    let con = &mut *data.db.lock().await;
+
+    let (parent_key, parent_email) = get_parent_email_if_valid(&data, con, &props.api_key).await?;
+
+    if !is_parent_of(con, &parent_email, props.user_id).await? {
+        Err(response::AuthError::ApiKeyUnauthorized)?;
+    }
+
+    let to_cancel_keys = api_key_service::get_current_by_creator_user_id(
+        con,
+        props.user_id,
+        utils::current_time_millis(),
+    )
+    .await
+    .map_err(report_postgres_err)?;
+
+    let mut sp = con.transaction().await.map_err(report_postgres_err)?;
+
+    // cancel every key the child has
+    let mut key_cancels = vec![];
+    for to_cancel_key in to_cancel_keys.into_iter() {
+        key_cancels.push(
+            api_key_service::add(
+                &mut sp,
+                props.user_id,
+                to_cancel_key.api_key_hash,
+                request::ApiKeyKind::Cancel,
+                0,
+            )
+            .await
+            .map_err(report_postgres_err)?,
+        );
+    }
+
+    sp.commit().await.map_err(report_postgres_err)?;
+
+    log::info!(
+        "user {} cancelled the keys of child {}",
+        parent_key.creator_user_id,
+        props.user_id
+    );
+
+    // return json
+    let mut resp_api_keys = vec![];
+    for u in key_cancels.into_iter() {
+        resp_api_keys.push(fill_api_key(con, u, None).await?);
+    }
+
+    Ok(web::Json(resp_api_keys))
+}
+
+// returns the language the user wants their emails in, if they've said
+async fn get_locale(
+    con: &mut tokio_postgres::Client,
+    user_id: i64,
+) -> Result<Option<String>, AppError> {
+    Ok(
+        notification_preference_service::get_by_user_id(con, user_id)
+            .await
+            .map_err(report_postgres_err)?
+            .and_then(|x| x.locale),
+    )
+}
+
+// an email to render from a template and queue
+struct TemplatedEmail<'a> {
+    target_email: &'a str,
+    // what the email is for, which decides whether a suppressed address still gets it, see jobs::is_blocked_by
+    topic: &'a str,
+    template: &'a str,
+    locale: Option<&'a str>,
+    // the email is only worth sending until then, when the link or code in it stops working
+    expiry_time: i64,
+    vars: &'a [(&'a str, &'a str)],
+}
+
+// renders the template in the given locale and queues it in the outbox
+// it is only delivered if con's transaction commits, see jobs::dispatch_email_outbox
+// app_origin is always available to the template
+// its contents are sealed while queued, since the links in them are as good as a password
+async fn send_templated_email(
+    data: &Data,
+    con: &mut impl GenericClient,
+    TemplatedEmail {
+        target_email,
+        topic,
+        template,
+        locale,
+        expiry_time,
+        vars,
+    }: TemplatedEmail<'_>,
+) -> Result<(), AppError> {
+    let mut vars = vars.to_vec();
+    vars.push(("app_origin", data.app_pub_origin_web.as_str()));
+
+    let rendered = data
+        .email_templates
+        .render(template, locale, &vars)
+        .map_err(report_internal_err)?;
+
+    email_outbox_service::add(
+        con,
+        expiry_time,
+        target_email.to_owned(),
+        topic.to_owned(),
+        rendered.title,
+        data.token_hasher.seal(&rendered.content),
+        data.token_hasher.seal(&rendered.content_plaintext),
+    )
+    .await
+    .map_err(report_postgres_err)?;
+
+    // the dispatcher needs the db lock to read the outbox, so it can't see the email before we commit
+    data.email_outbox_notify.notify_one();
+
+    Ok(())
+}
+
+pub async fn send_parent_permission_email(
+    data: &Data,
+    con: &mut impl GenericClient,
+    target_email: &str,
+    user_name: &str,
+    locale: Option<&str>,
+    verification_challenge_key: &str,
+) -> Result<(), AppError> {
+    send_templated_email(
+        data,
+        con,
+        TemplatedEmail {
+            target_email,
+            topic: "parent_permission",
+            template: "parent_permission",
+            locale,
+            expiry_time: utils::current_time_millis() + FIFTEEN_MINUTES,
+            vars: &[
+                ("user_name", user_name),
+                ("expiry_minutes", "15"),
+                (
+                    "link",
+                    &format!(
+                        "{}/parent_permission_confirm?verificationChallengeKey={}",
+                        data.app_pub_origin_web, verification_challenge_key
+                    ),
+                ),
+            ],
+        },
+    )
+    .await
+}
+
+// if there's a code, it is sent along with the link
+pub async fn send_email_verification_email(
+    data: &Data,
+    con: &mut impl GenericClient,
+    target_email: &str,
+    user_name: &str,
+    locale: Option<&str>,
+    verification_challenge_key: &str,
+    verification_code: Option<&str>,
+) -> Result<(), AppError> {
+    let link = format!(
+        "{}/email_confirm?verificationChallengeKey={}",
+        data.app_pub_origin_web, verification_challenge_key
+    );
+
+    let mut vars = vec![
+        ("user_name", user_name),
+        ("expiry_minutes", "15"),
+        ("link", link.as_str()),
+    ];
+
+    let template = match verification_code {
+        Some(verification_code) => {
+            vars.push(("code", verification_code));
+            "verification_code"
+        }
+        None => "verification_challenge",
+    };
+
+    send_templated_email(
+        data,
+        con,
+        TemplatedEmail {
+            target_email,
+            topic: "verification_challenge",
+            template,
+            locale,
+            expiry_time: utils::current_time_millis() + FIFTEEN_MINUTES,
+            vars: &vars,
+        },
+    )
+    .await
+}
+
+pub async fn send_password_reset_email(
+    data: &Data,
+    con: &mut impl GenericClient,
+    target_email: &str,
+    user_name: &str,
+    locale: Option<&str>,
+    password_reset_key: &str,
+) -> Result<(), AppError> {
+    send_templated_email(
+        data,
+        con,
+        TemplatedEmail {
+            target_email,
+            topic: "password_reset",
+            template: "password_reset",
+            locale,
+            expiry_time: utils::current_time_millis() + FIFTEEN_MINUTES,
+            vars: &[
+                ("user_name", user_name),
+                ("expiry_minutes", "15"),
+                (
+                    "link",
+                    &format!(
+                        "{}/reset_password?resetKey={}",
+                        data.app_pub_origin_web, password_reset_key
+                    ),
+                ),
+            ],
+        },
+    )
+    .await
+}
+
+pub async fn send_security_notification_email(
+    data: &Data,
+    con: &mut impl GenericClient,
+    target_email: &str,
+    user_name: &str,
+    locale: Option<&str>,
+    security_notification_kind: SecurityNotificationKind,
+    security_notification_key: &str,
+) -> Result<(), AppError> {
+    // each kind of event has its own template, so that the whole email can be translated
+    let template = match security_notification_kind {
+        SecurityNotificationKind::NewLogin => "security_notification_new_login",
+        SecurityNotificationKind::PasswordChange => "security_notification_password_change",
+        SecurityNotificationKind::PasswordReset => "security_notification_password_reset",
+        SecurityNotificationKind::EmailChange => "security_notification_email_change",
+    };
+
+    send_templated_email(
+        data,
+        con,
+        TemplatedEmail {
+            target_email,
+            topic: "security_notification",
+            template,
+            locale,
+            expiry_time: utils::current_time_millis() + ONE_WEEK,
+            vars: &[
+                ("user_name", user_name),
+                ("expiry_days", "7"),
+                (
+                    "link",
+                    &format!(
+                        "{}/revoke_access?securityNotificationKey={}",
+                        data.app_pub_origin_web, security_notification_key
+                    ),
+                ),
+            ],
+        },
+    )
+    .await
+}
+
+pub async fn send_email_change_email(
+    data: &Data,
+    con: &mut impl GenericClient,
+    target_email: &str,
+    new_email: &str,
+    user_name: &str,
+    locale: Option<&str>,
+    email_change_key: &str,
+) -> Result<(), AppError> {
+    send_templated_email(
+        data,
+        con,
+        TemplatedEmail {
+            target_email,
+            topic: "email_change",
+            template: "email_change",
+            locale,
+            expiry_time: utils::current_time_millis() + data.email_change_revert_period,
+            vars: &[
+                ("user_name", user_name),
+                ("new_email", new_email),
+                (
+                    "expiry_days",
+                    &(data.email_change_revert_period / (24 * 60 * 60 * 1000)).to_string(),
+                ),
+                (
+                    "link",
+                    &format!(
+                        "{}/email_revert?emailChangeKey={}",
+                        data.app_pub_origin_web, email_change_key
+                    ),
+                ),
+            ],
+        },
+    )
+    .await
+}
+
+// the link has no key in it, since the email may sit unread for weeks
+// from the dashboard the parent asks for an access link, which works for an hour like any other
+pub async fn send_parent_permission_reminder_email(
+    data: &Data,
+    con: &mut impl GenericClient,
+    target_email: &str,
+    user_name: &str,
+    locale: Option<&str>,
+    expiry_time: i64,
+) -> Result<(), AppError> {
+    let one_day = 24 * 60 * 60 * 1000;
+    let expiry_days = (expiry_time - utils::current_time_millis() + one_day - 1) / one_day;
+
+    send_templated_email(
+        data,
+        con,
+        TemplatedEmail {
+            target_email,
+            topic: "parent_permission_reminder",
+            template: "parent_permission_reminder",
+            locale,
+            expiry_time,
+            vars: &[
+                ("user_name", user_name),
+                ("expiry_days", &expiry_days.to_string()),
+                (
+                    "link",
+                    &format!("{}/parent_dashboard", data.app_pub_origin_web),
+                ),
+            ],
+        },
+    )
+    .await
+}
+
+pub async fn send_parent_access_email(
+    data: &Data,
+    con: &mut impl GenericClient,
+    target_email: &str,
+    parent_access_key: &str,
+) -> Result<(), AppError> {
+    // there's no account to take a locale from, so this is always in the default one
+    send_templated_email(
+        data,
+        con,
+        TemplatedEmail {
+            target_email,
+            topic: "parent_access",
+            template: "parent_access",
+            locale: None,
+            expiry_time: utils::current_time_millis() + ONE_HOUR,
+            vars: &[
+                ("expiry_minutes", "60"),
+                (
+                    "link",
+                    &format!(
+                        "{}/parent_dashboard?parentAccessKey={}",
+                        data.app_pub_origin_web, parent_access_key
+                    ),
+                ),
+            ],
+        },
+    )
+    .await
+}
+
+// returns the address of the user's primary email
+async fn get_primary_email_address(
+    con: &mut tokio_postgres::Client,
+    user_id: i64,
+) -> Result<Option<String>, AppError> {
+    let email = match email_service::get_primary_by_user_id(con, user_id)
+        .await
+        .map_err(report_postgres_err)?
+    {
+        Some(email) => email,
+        None => return Ok(None),
+    };
+
+    let verification_challenge =
+        verification_challenge_service::get_by_verification_challenge_key_hash(
+            con,
+            &email.verification_challenge_key_hash,
+        )
+        .await
+        .map_err(report_postgres_err)?
+        .ok_or(response::AuthError::VerificationChallengeNonexistent)?;
+
+    Ok(Some(verification_challenge.email))
+}
+
+// sends a security notification to each of the target emails, unless the user has opted out
+// the change being notified about has already happened, so failures are only logged
+async fn notify_security_event(
+    data: &Data,
+    con: &mut tokio_postgres::Client,
+    user_data: &UserData,
+    security_notification_kind: SecurityNotificationKind,
+    target_emails: Vec<String>,
+) {
+    let preference =
+        match notification_preference_service::get_by_user_id(con, user_data.creator_user_id).await
+        {
+            Ok(maybe_preference) => maybe_preference,
+            Err(e) => {
+                report_postgres_err(e);
+                return;
+            }
+        };
+
+    // notifications are on unless the user turned them off
+    if !preference.as_ref().is_none_or(|x| x.security_notifications) {
+        return;
+    }
+
+    let locale = preference.and_then(|x| x.locale);
+
+    for target_email in target_emails {
+        // the failure has already been logged
+        let _ = add_security_notification(
+            data,
+            con,
+            user_data,
+            locale.as_deref(),
+            security_notification_kind,
+            target_email,
+        )
+        .await;
+    }
+}
+
+// records a security notification and queues its email
+async fn add_security_notification(
+    data: &Data,
+    con: &mut tokio_postgres::Client,
+    user_data: &UserData,
+    locale: Option<&str>,
+    security_notification_kind: SecurityNotificationKind,
+    target_email: String,
+) -> Result<(), AppError> {
+    let security_notification_key = data.token_hasher.gen_token(TokenKind::SecurityNotification);
+
+    let mut sp = con.transaction().await.map_err(report_postgres_err)?;
+
+    security_notification_service::add(
+        &mut sp,
+        data.token_hasher.hash(&security_notification_key),
+        user_data.creator_user_id,
+        security_notification_kind,
+        target_email.clone(),
+    )
+    .await
+    .map_err(report_postgres_err)?;
+
+    send_security_notification_email(
+        data,
+        &mut sp,
+        &target_email,
+        &user_data.realname,
+        locale,
+        security_notification_kind,
+        &security_notification_key,
+    )
+    .await?;
+
+    sp.commit().await.map_err(report_postgres_err)?;
+
+    Ok(())
+}
+
+// rejects addresses at domains the deployment doesn't allow for this purpose
+async fn check_email_domain(data: &Data, email: &str, to_parent: bool) -> Result<(), AppError> {
+    if let Err(reason) = data
+        .email_domain_policy
+        .lock()
+        .await
+        .check(email, to_parent)
+    {
+        log::info!("email domain policy rejected an address: {}", reason);
+        Err(response::AuthError::EmailDomainBlocked)?;
+    }
+
+    Ok(())
+}
+
+pub async fn verification_challenge_new(
+    data: web::Data<Data>,
+    props: web::Json<request::VerificationChallengeNewProps>,
+) -> Result<impl Responder, AppError> {
+    let email_address =
+        email_normalization::normalize_email(&props.email, &data.email_folding_rules);
+
+    // avoid sending email to obviously bad addresses
+    if email_address.is_empty() {
+        Err(response::AuthError::EmailBounced)?;
+    }
+
+    check_email_domain(&data, &email_address, props.to_parent).await?;
+
+    let con = &mut *data.db.lock().await;
+
+    // you need to have an account but its fine not to be verified yet
+    let api_key = get_api_key_if_current_noverify(&data, con, &props.api_key).await?;
+
+    // a parent has to open the link, so that they see what they're agreeing to
+    if props.code && props.to_parent {
+        Err(response::AuthError::VerificationChallengeWrongKind)?;
+    }
+
+    // adding another own email requires the password, see email_new_change
+    if !props.to_parent
+        && email_service::get_primary_by_user_id(con, api_key.creator_user_id)
+            .await
+            .map_err(report_postgres_err)?
+            .is_some()
+    {
+        Err(response::AuthError::EmailChangePasswordRequired)?;
+    }
+
+    // don't let people spam emails
+    let num_emails = verification_challenge_service::get_num_challenges_by_creator_between(
+        con,
+        api_key.creator_user_id,
+        utils::current_time_millis() - FIFTEEN_MINUTES,
+        utils::current_time_millis(),
+    )
+    .await
+    .map_err(report_postgres_err)?;
+
+    // limit to 4 emails in past 15 minutes
+    if num_emails > 4 {
+        // TODO: more descriptive error
+        Err(response::AuthError::EmailCooldown)?;
     }
 
     // get user data to generate
@@ -481,220 +1468,1733 @@ pub async fn verification_challenge_new(
         .map_err(report_postgres_err)?
         .ok_or(response::AuthError::UserDataNonexistent)?;
 
-    // generate random string
-    let verification_challenge_key = utils::gen_random_string();
+    // parents get emails in the child's language too, since we don't know theirs
+    let locale = get_locale(con, api_key.creator_user_id).await?;
+
+    // generate random string
+    let verification_challenge_key = data
+        .token_hasher
+        .gen_token(TokenKind::VerificationChallenge);
+
+    let mut sp = con.transaction().await.map_err(report_postgres_err)?;
+
+    // insert into database
+    let verification_challenge = verification_challenge_service::add(
+        &mut sp,
+        data.token_hasher.hash(&verification_challenge_key),
+        email_address.clone(),
+        api_key.creator_user_id,
+        props.to_parent,
+    )
+    .await
+    .map_err(report_postgres_err)?;
+
+    // the code can only be entered with the api key that asked for it
+    let verification_code = if props.code {
+        let num_codes = verification_code_service::get_num_by_creator_between(
+            &mut sp,
+            api_key.creator_user_id,
+            utils::current_time_millis() - ONE_DAY,
+            utils::current_time_millis(),
+        )
+        .await
+        .map_err(report_postgres_err)?;
+
+        if num_codes >= MAX_VERIFICATION_CODES_PER_DAY {
+            Err(response::AuthError::EmailCooldown)?;
+        }
+
+        let verification_code = utils::gen_verification_code();
+
+        verification_code_service::add(
+            &mut sp,
+            verification_challenge
+                .verification_challenge_key_hash
+                .clone(),
+            api_key.api_key_hash.clone(),
+            data.token_hasher.hash(&verification_code),
+        )
+        .await
+        .map_err(report_postgres_err)?;
+
+        Some(verification_code)
+    } else {
+        None
+    };
+
+    // queue email depending on kind
+    if props.to_parent {
+        send_parent_permission_email(
+            &data,
+            &mut sp,
+            &email_address,
+            &user_data.realname,
+            locale.as_deref(),
+            &verification_challenge_key,
+        )
+        .await?;
+    } else {
+        send_email_verification_email(
+            &data,
+            &mut sp,
+            &email_address,
+            &user_data.realname,
+            locale.as_deref(),
+            &verification_challenge_key,
+            verification_code.as_deref(),
+        )
+        .await?;
+    }
+
+    sp.commit().await.map_err(report_postgres_err)?;
+
+    // return json
+    Ok(web::Json(
+        fill_verification_challenge(con, verification_challenge).await?,
+    ))
+}
+
+async fn verify_current_password(
+    data: &Data,
+    con: &mut tokio_postgres::Client,
+    user_id: i64,
+    password: &str,
+) -> Result<(), AppError> {
+    let current_password = password_service::get_by_user_id(con, user_id)
+        .await
+        .map_err(report_postgres_err)?
+        .ok_or(response::AuthError::PasswordNonexistent)?;
+
+    if !data
+        .peppers
+        .verify_password(password, &current_password.password_hash)
+        .map_err(report_internal_err)?
+    {
+        Err(response::AuthError::PasswordIncorrect)?;
+    }
+
+    Ok(())
+}
+
+// A stolen api key alone shouldn't be enough to lock the owner out, so changing the password or email,
+// or deleting the account, needs the key's holder to have proved who they are in the last
+// --reauthentication-window-minutes, by logging in or through reauthentication_new.
+// Giving the current password with the request works too, and counts as proving it for later requests.
+async fn require_recent_authentication(
+    data: &Data,
+    con: &mut tokio_postgres::Client,
+    api_key: &ApiKey,
+    password: Option<&str>,
+) -> Result<(), AppError> {
+    if let Some(password) = password {
+        verify_current_password(data, con, api_key.creator_user_id, password).await?;
+
+        reauthentication_service::add(
+            con,
+            api_key.creator_user_id,
+            api_key.api_key_hash.clone(),
+            ReauthenticationKind::Password,
+        )
+        .await
+        .map_err(report_postgres_err)?;
+
+        return Ok(());
+    }
+
+    match reauthentication_service::get_latest_by_api_key_hash(con, &api_key.api_key_hash)
+        .await
+        .map_err(report_postgres_err)?
+    {
+        Some(reauthentication)
+            if reauthentication.creation_time + data.reauthentication_window
+                >= utils::current_time_millis() =>
+        {
+            Ok(())
+        }
+        _ => Err(response::AuthError::ReauthenticationRequired)?,
+    }
+}
+
+pub async fn reauthentication_new(
+    data: web::Data<Data>,
+    props: web::Json<request::ReauthenticationNewProps>,
+) -> Result<impl Responder, AppError> {
+    let con = &mut *data.db.lock().await;
+
+    let api_key = get_api_key_if_current_noverify(&data, con, &props.api_key).await?;
+
+    let reauthentication_kind = match &props.password {
+        Some(password) => {
+            verify_current_password(&data, con, api_key.creator_user_id, password).await?;
+            ReauthenticationKind::Password
+        }
+        None => {
+            // without the password, only a second factor will do
+            if !is_second_factor_enabled(con, api_key.creator_user_id).await? {
+                Err(response::AuthError::PasswordIncorrect)?;
+            }
+
+            check_second_factor(
+                &data,
+                con,
+                api_key.creator_user_id,
+                props.second_factor_code.as_deref(),
+            )
+            .await?;
+            ReauthenticationKind::SecondFactor
+        }
+    };
+
+    let reauthentication = reauthentication_service::add(
+        con,
+        api_key.creator_user_id,
+        api_key.api_key_hash,
+        reauthentication_kind,
+    )
+    .await
+    .map_err(report_postgres_err)?;
+
+    Ok(web::Json(
+        fill_reauthentication(con, reauthentication).await?,
+    ))
+}
+
+pub async fn email_new_change(
+    data: web::Data<Data>,
+    props: web::Json<request::EmailNewChangeProps>,
+) -> Result<impl Responder, AppError> {
+    let email_address =
+        email_normalization::normalize_email(&props.email, &data.email_folding_rules);
+
+    // avoid sending email to obviously bad addresses
+    if email_address.is_empty() {
+        Err(response::AuthError::EmailBounced)?;
+    }
+
+    check_email_domain(&data, &email_address, false).await?;
+
+    let con = &mut *data.db.lock().await;
+
+    // you need to have an account but its fine not to be verified yet
+    let api_key = get_api_key_if_current_noverify(&data, con, &props.api_key).await?;
+
+    // a stolen api key isn't enough to take over the account's email
+    require_recent_authentication(&data, con, &api_key, props.password.as_deref()).await?;
+
+    // check that the email isn't already in use by another user
+    if email_service::exists_by_own_email(con, &email_address)
+        .await
+        .map_err(report_postgres_err)?
+    {
+        Err(response::AuthError::EmailExistent)?;
+    }
+
+    // don't let people spam emails
+    let num_emails = verification_challenge_service::get_num_challenges_by_creator_between(
+        con,
+        api_key.creator_user_id,
+        utils::current_time_millis() - FIFTEEN_MINUTES,
+        utils::current_time_millis(),
+    )
+    .await
+    .map_err(report_postgres_err)?;
+
+    // limit to 4 emails in past 15 minutes
+    if num_emails > 4 {
+        Err(response::AuthError::EmailCooldown)?;
+    }
+
+    let user_data = user_data_service::get_by_user_id(con, api_key.creator_user_id)
+        .await
+        .map_err(report_postgres_err)?
+        .ok_or(response::AuthError::UserDataNonexistent)?;
+
+    let locale = get_locale(con, api_key.creator_user_id).await?;
+
+    let verification_challenge_key = data
+        .token_hasher
+        .gen_token(TokenKind::VerificationChallenge);
+
+    let mut sp = con.transaction().await.map_err(report_postgres_err)?;
+
+    let verification_challenge = verification_challenge_service::add(
+        &mut sp,
+        data.token_hasher.hash(&verification_challenge_key),
+        email_address.clone(),
+        api_key.creator_user_id,
+        false,
+    )
+    .await
+    .map_err(report_postgres_err)?;
+
+    // the email is only added once the new address is confirmed through email_new
+    send_email_verification_email(
+        &data,
+        &mut sp,
+        &email_address,
+        &user_data.realname,
+        locale.as_deref(),
+        &verification_challenge_key,
+        None,
+    )
+    .await?;
+
+    sp.commit().await.map_err(report_postgres_err)?;
+
+    Ok(web::Json(
+        fill_verification_challenge(con, verification_challenge).await?,
+    ))
+}
+
+// rejects passwords the policy doesn't allow, giving every reason so the frontend can explain them
+// used at signup, when there are no previous passwords to compare against
+fn check_password_policy(
+    data: &Data,
+    password: &str,
+    personal_info: password_policy::PersonalInfo,
+) -> Result<(), AppError> {
+    let violations = data.password_policy.check(password, &personal_info);
+    if !violations.is_empty() {
+        Err(response::AuthError::PasswordPolicyViolated(violations))?;
+    }
+
+    Ok(())
+}
+
+// checks a new password for an existing user, who shouldn't use their username, name or email in it,
+// or go back to any of their last --password-history-count passwords
+async fn check_user_password_policy(
+    data: &Data,
+    con: &mut tokio_postgres::Client,
+    user_id: i64,
+    password: &str,
+) -> Result<(), AppError> {
+    let user_data = user_data_service::get_by_user_id(con, user_id)
+        .await
+        .map_err(report_postgres_err)?
+        .ok_or(response::AuthError::UserDataNonexistent)?;
+
+    let email = get_primary_email_address(con, user_id).await?;
+
+    let mut violations = data.password_policy.check(
+        password,
+        &password_policy::PersonalInfo {
+            username: &user_data.username,
+            realname: &user_data.realname,
+            email: email.as_deref(),
+        },
+    );
+
+    // only the hashes are kept, so each one has to be verified in turn
+    let previous_passwords =
+        password_service::get_recent_by_user_id(con, user_id, data.password_history_count)
+            .await
+            .map_err(report_postgres_err)?;
+
+    for previous_password in previous_passwords.iter() {
+        match data
+            .peppers
+            .verify_password(password, &previous_password.password_hash)
+        {
+            Ok(true) => {
+                violations.push(response::PasswordPolicyViolation::Reused {
+                    history_count: data.password_history_count,
+                });
+                break;
+            }
+            Ok(false) => {}
+            // old passwords may be under a pepper that has since been retired
+            Err(pepper::PepperError::UnknownKeyId(_)) => {}
+            Err(e) => Err(report_internal_err(e))?,
+        }
+    }
+
+    if !violations.is_empty() {
+        Err(response::AuthError::PasswordPolicyViolated(violations))?;
+    }
+
+    Ok(())
+}
+
+// once the password has been changed, keys marked as having an expired password can be used again
+async fn unexpire_api_keys(
+    data: &Data,
+    con: &mut impl GenericClient,
+    user_id: i64,
+) -> Result<(), AppError> {
+    let verification_status = get_verification_status(data, con, user_id).await?;
+
+    api_key_service::change_current_kind(
+        con,
+        user_id,
+        request::ApiKeyKind::PasswordExpired,
+        verification_status,
+        utils::current_time_millis(),
+    )
+    .await
+    .map_err(report_postgres_err)?;
+
+    Ok(())
+}
+
+pub async fn user_new(
+    req: HttpRequest,
+    data: web::Data<Data>,
+    props: web::Json<request::UserNewProps>,
+) -> Result<impl Responder, AppError> {
+    if !utils::is_realname_valid(&props.realname) {
+        Err(response::AuthError::UserRealnameInvalid)?;
+    }
+
+    if !utils::is_username_valid(&props.username) {
+        Err(response::AuthError::UserUsernameInvalid)?;
+    }
+
+    if let Some(country) = &props.country {
+        if !utils::is_country_valid(country) {
+            Err(response::AuthError::UserCountryInvalid)?;
+        }
+    }
+
+    // server side validation of password strength
+    check_password_policy(
+        &data,
+        &props.password,
+        password_policy::PersonalInfo {
+            username: &props.username,
+            realname: &props.realname,
+            email: None,
+        },
+    )?;
+
+    let con = &mut *data.db.lock().await;
+
+    let mut sp = con.transaction().await.map_err(report_postgres_err)?;
+
+    // check username is not used
+    if user_data_service::get_by_username(&mut sp, &props.username)
+        .await
+        .map_err(report_postgres_err)?
+        .is_some()
+    {
+        Err(response::AuthError::UserUsernameTaken)?;
+    }
+
+    // create user
+    let user = user_service::add(&mut sp)
+        .await
+        .map_err(report_postgres_err)?;
+
+    // create user data
+    let user_data = user_data_service::add(
+        &mut sp,
+        user.user_id,
+        props.dateofbirth,
+        props.username.clone(),
+        props.realname.clone(),
+        props.country.clone(),
+    )
+    .await
+    .map_err(report_postgres_err)?;
+
+    // create password
+    let password_hash = data
+        .peppers
+        .hash_password(&props.password)
+        .map_err(report_internal_err)?;
+    password_service::add(&mut sp, user.user_id, password_hash, None)
+        .await
+        .map_err(report_postgres_err)?;
+
+    let raw_api_key = data.token_hasher.gen_token(TokenKind::ApiKey);
+    // add new api key
+    let api_key = api_key_service::add(
+        &mut sp,
+        user_data.creator_user_id,
+        data.token_hasher.hash(&raw_api_key),
+        request::ApiKeyKind::NoEmail,
+        // 1 hour
+        props.api_key_duration as i64,
+    )
+    .await
+    .map_err(report_postgres_err)?;
+
+    // the device used to sign up shouldn't trigger a new login notification
+    device_service::add(&mut sp, user.user_id, get_device_hash(&req))
+        .await
+        .map_err(report_postgres_err)?;
+
+    sp.commit().await.map_err(report_postgres_err)?;
+
+    // return api key
+    Ok(web::Json(
+        fill_api_key(con, api_key, Some(raw_api_key)).await?,
+    ))
+}
+
+pub async fn user_data_new(
+    data: web::Data<Data>,
+    props: web::Json<request::UserDataNewProps>,
+) -> Result<impl Responder, AppError> {
+    // ensure names are valid
+    if !utils::is_realname_valid(&props.realname) {
+        Err(response::AuthError::UserRealnameInvalid)?;
+    }
+
+    if !utils::is_username_valid(&props.username) {
+        Err(response::AuthError::UserUsernameInvalid)?;
+    }
+
+    if let Some(country) = &props.country {
+        if !utils::is_country_valid(country) {
+            Err(response::AuthError::UserCountryInvalid)?;
+        }
+    }
+
+    let con = &mut *data.db.lock().await;
+
+    // api key verification required (email or parent permission not needed)
+    let creator_key = get_api_key_if_current_noverify(&data, con, &props.api_key).await?;
+
+    // check username is not used
+    let maybe_user_data = user_data_service::get_by_username(con, &props.username)
+        .await
+        .map_err(report_postgres_err)?;
+
+    let username_not_taken = match maybe_user_data {
+        Some(UserData {
+            creator_user_id, ..
+        }) => creator_user_id == creator_key.creator_user_id,
+        None => true,
+    };
+
+    if !username_not_taken {
+        Err(response::AuthError::UserUsernameTaken)?;
+    }
+
+    // create key data
+    let user_data = user_data_service::add(
+        con,
+        creator_key.creator_user_id,
+        props.dateofbirth,
+        props.username.clone(),
+        props.realname.clone(),
+        props.country.clone(),
+    )
+    .await
+    .map_err(report_postgres_err)?;
+
+    // return json
+    Ok(web::Json(fill_user_data(con, user_data).await?))
+}
+
+// returns the verification challenge if it is of the right kind and can still be used to make an email
+async fn get_unused_verification_challenge(
+    con: &mut tokio_postgres::Client,
+    vckh: &str,
+    to_parent: bool,
+) -> Result<VerificationChallenge, AppError> {
+    // check that the verification challenge exists
+    let vc = verification_challenge_service::get_by_verification_challenge_key_hash(con, vckh)
+        .await
+        .map_err(report_postgres_err)?
+        .ok_or(response::AuthError::VerificationChallengeNonexistent)?;
+
+    // check that it hasn't timed out
+    if FIFTEEN_MINUTES as i64 + vc.creation_time < utils::current_time_millis() {
+        Err(response::AuthError::VerificationChallengeTimedOut)?;
+    }
+
+    // check that the verification challenge is meant for the correct purpose
+    if vc.to_parent != to_parent {
+        Err(response::AuthError::VerificationChallengeWrongKind)?;
+    }
+
+    // check if the verification challenge was not already used to make a new email
+    if email_service::get_by_verification_challenge_key_hash(con, vckh)
+        .await
+        .map_err(report_postgres_err)?
+        .is_some()
+    {
+        Err(response::AuthError::VerificationChallengeUsed)?;
+    }
+
+    Ok(vc)
+}
+
+// returns the hash the verification challenge key from a link was stored under
+async fn get_verification_challenge_key_hash(
+    data: &Data,
+    con: &mut tokio_postgres::Client,
+    verification_challenge_key: &str,
+) -> Result<String, AppError> {
+    let vc = verification_challenge_service::get_by_verification_challenge_key_hashes(
+        con,
+        &data
+            .token_hasher
+            .candidate_hashes(verification_challenge_key),
+    )
+    .await
+    .map_err(report_postgres_err)?
+    .ok_or(response::AuthError::VerificationChallengeNonexistent)?;
+
+    Ok(vc.verification_challenge_key_hash)
+}
+
+// returns the hash of the verification challenge the code was sent for, recording the attempt
+// after too many wrong guesses the code stops working, and a new one has to be asked for
+async fn get_verification_challenge_key_hash_by_code(
+    data: &Data,
+    con: &mut tokio_postgres::Client,
+    api_key: &str,
+    verification_code: &str,
+) -> Result<String, AppError> {
+    let api_key = get_api_key_if_current_noverify(data, con, api_key).await?;
+
+    let code = verification_code_service::get_latest_by_api_key_hash(con, &api_key.api_key_hash)
+        .await
+        .map_err(report_postgres_err)?
+        .ok_or(response::AuthError::VerificationCodeNonexistent)?;
+
+    let num_failed = verification_code_service::get_num_failed_attempts(
+        con,
+        &code.verification_challenge_key_hash,
+    )
+    .await
+    .map_err(report_postgres_err)?;
+
+    if num_failed >= MAX_VERIFICATION_CODE_ATTEMPTS {
+        Err(response::AuthError::VerificationCodeLocked)?;
+    }
+
+    let num_failed_today = verification_code_service::get_num_failed_attempts_by_creator_between(
+        con,
+        api_key.creator_user_id,
+        utils::current_time_millis() - ONE_DAY,
+        utils::current_time_millis(),
+    )
+    .await
+    .map_err(report_postgres_err)?;
+
+    // the link in the email still works
+    if num_failed_today >= MAX_VERIFICATION_CODE_FAILURES_PER_DAY {
+        Err(response::AuthError::VerificationCodeLocked)?;
+    }
+
+    let success = data
+        .token_hasher
+        .verify(verification_code.trim(), &code.verification_code_hash);
+
+    verification_code_service::add_attempt(con, &code.verification_challenge_key_hash, success)
+        .await
+        .map_err(report_postgres_err)?;
+
+    if !success {
+        Err(response::AuthError::VerificationCodeIncorrect)?;
+    }
+
+    Ok(code.verification_challenge_key_hash)
+}
+
+pub async fn email_new(
+    data: web::Data<Data>,
+    props: web::Json<request::EmailNewProps>,
+) -> Result<impl Responder, AppError> {
+    let con = &mut *data.db.lock().await;
+
+    // either the key from the link, or the code sent with it typed in on the device that asked for it
+    let vckh = match (
+        &props.verification_challenge_key,
+        &props.api_key,
+        &props.verification_code,
+    ) {
+        (Some(verification_challenge_key), _, _) => {
+            get_verification_challenge_key_hash(&data, con, verification_challenge_key).await?
+        }
+        (None, Some(api_key), Some(verification_code)) => {
+            get_verification_challenge_key_hash_by_code(&data, con, api_key, verification_code)
+                .await?
+        }
+        _ => Err(response::AuthError::VerificationChallengeNonexistent)?,
+    };
+
+    let vc = get_unused_verification_challenge(con, &vckh, props.to_parent).await?;
+
+    // challenges made before addresses were normalized may hold any form of the address
+    let email_address = email_normalization::normalize_email(&vc.email, &data.email_folding_rules);
+
+    // (if not parent) check that the email isn't already in use by another user
+    if !vc.to_parent {
+        let held_emails = email_service::get_all_by_own_email(con, &email_address)
+            .await
+            .map_err(report_postgres_err)?;
+
+        let mut held_by_user = None;
+        for held_email in held_emails.into_iter() {
+            let held_vc = verification_challenge_service::get_by_verification_challenge_key_hash(
+                con,
+                &held_email.verification_challenge_key_hash,
+            )
+            .await
+            .map_err(report_postgres_err)?
+            .ok_or(response::AuthError::VerificationChallengeNonexistent)?;
+
+            if held_vc.creator_user_id != vc.creator_user_id {
+                Err(response::AuthError::EmailExistent)?;
+            }
+
+            held_by_user.get_or_insert(held_email);
+        }
+
+        // verifying an address the user already holds again changes nothing
+        if let Some(held_email) = held_by_user {
+            return Ok(web::Json(fill_email(con, held_email).await?));
+        }
+    }
+
+    // (if not parent) find out whether the user already has a primary email
+    let primary_email = if vc.to_parent {
+        None
+    } else {
+        email_service::get_primary_by_user_id(con, vc.creator_user_id)
+            .await
+            .map_err(report_postgres_err)?
+    };
+
+    let primary_vc = match &primary_email {
+        Some(primary_email) => Some(
+            verification_challenge_service::get_by_verification_challenge_key_hash(
+                con,
+                &primary_email.verification_challenge_key_hash,
+            )
+            .await
+            .map_err(report_postgres_err)?
+            .ok_or(response::AuthError::VerificationChallengeNonexistent)?,
+        ),
+        None => None,
+    };
+
+    // the primary address may be stored in a form from before it was normalized, so the lookup above can miss it
+    // it isn't a change, so there's nothing to record or undo
+    if let (Some(primary_email), Some(primary_vc)) = (&primary_email, &primary_vc) {
+        if email_normalization::normalize_email(&primary_vc.email, &data.email_folding_rules)
+            == email_address
+        {
+            return Ok(web::Json(fill_email(con, primary_email.clone()).await?));
+        }
+    }
+
+    let user_data = user_data_service::get_by_user_id(con, vc.creator_user_id)
+        .await
+        .map_err(report_postgres_err)?
+        .ok_or(response::AuthError::UserDataNonexistent)?;
+
+    let locale = get_locale(con, vc.creator_user_id).await?;
+
+    // the primary address gets a link to undo the change
+    let email_change_key = data.token_hasher.gen_token(TokenKind::EmailChange);
+
+    let mut sp = con.transaction().await.map_err(report_postgres_err)?;
+
+    // create key data
+    let email = email_service::add(&mut sp, vckh.clone())
+        .await
+        .map_err(report_postgres_err)?;
+
+    // the first own email is the primary one, later ones are added alongside it
+    if !vc.to_parent {
+        match &primary_vc {
+            Some(primary_vc) => {
+                email_change_service::add(
+                    &mut sp,
+                    data.token_hasher.hash(&email_change_key),
+                    vc.creator_user_id,
+                    primary_vc.verification_challenge_key_hash.clone(),
+                    vckh.clone(),
+                )
+                .await
+                .map_err(report_postgres_err)?;
+
+                // this isn't optional, so it ignores the notification preference
+                send_email_change_email(
+                    &data,
+                    &mut sp,
+                    &primary_vc.email,
+                    &vc.email,
+                    &user_data.realname,
+                    locale.as_deref(),
+                    &email_change_key,
+                )
+                .await?;
+            }
+            None => {
+                email_service::add_primary(&mut sp, vc.creator_user_id, email.email_id)
+                    .await
+                    .map_err(report_postgres_err)?;
+            }
+        }
+    }
+
+    // the address received the verification email, so it can be sent to again
+    if email_suppression_service::is_suppressed(&mut sp, &vc.email)
+        .await
+        .map_err(report_postgres_err)?
+    {
+        email_suppression_service::add(&mut sp, vc.email.clone(), EmailSuppressionKind::Clear)
+            .await
+            .map_err(report_postgres_err)?;
+    }
+
+    // a verified parent email is permission from the parent, but we don't know what terms they saw
+    if vc.to_parent {
+        parent_permission_service::add(
+            &mut sp,
+            vc.creator_user_id,
+            vckh,
+            request::ParentPermissionKind::Grant,
+            None,
+        )
+        .await
+        .map_err(report_postgres_err)?;
+    }
+
+    sp.commit().await.map_err(report_postgres_err)?;
+
+    // the primary address has been told how to undo the change, now notify the new one
+    if primary_vc.is_some() {
+        notify_security_event(
+            &data,
+            con,
+            &user_data,
+            SecurityNotificationKind::EmailChange,
+            vec![vc.email.clone()],
+        )
+        .await;
+    }
+
+    // return json
+    Ok(web::Json(fill_email(con, email).await?))
+}
+
+pub async fn email_new_revert(
+    data: web::Data<Data>,
+    props: web::Json<request::EmailNewRevertProps>,
+) -> Result<impl Responder, AppError> {
+    let con = &mut *data.db.lock().await;
+
+    // no api key verification needed, the key from the email change notice is proof enough
+    let email_change = email_change_service::get_by_email_change_key_hashes(
+        con,
+        &data.token_hasher.candidate_hashes(&props.email_change_key),
+    )
+    .await
+    .map_err(report_postgres_err)?
+    .ok_or(response::AuthError::EmailChangeNonexistent)?;
+
+    // deny if timed out
+    if data.email_change_revert_period + email_change.creation_time < utils::current_time_millis() {
+        Err(response::AuthError::EmailChangeTimedOut)?;
+    }
+
+    // deny if already reverted
+    if email_change_service::exists_revert_by_email_change_key_hash(
+        con,
+        &email_change.email_change_key_hash,
+    )
+    .await
+    .map_err(report_postgres_err)?
+    {
+        Err(response::AuthError::EmailChangeReverted)?;
+    }
+
+    // deny if the added email has been removed since
+    let new_email = email_service::get_own_by_verification_challenge_key_hash(
+        con,
+        &email_change.new_verification_challenge_key_hash,
+    )
+    .await
+    .map_err(report_postgres_err)?
+    .ok_or(response::AuthError::EmailChangeSuperseded)?;
+
+    // the old email may have been removed by whoever added the new one
+    let maybe_old_email = email_service::get_own_by_verification_challenge_key_hash(
+        con,
+        &email_change.old_verification_challenge_key_hash,
+    )
+    .await
+    .map_err(report_postgres_err)?;
+
+    let old_vc = verification_challenge_service::get_by_verification_challenge_key_hash(
+        con,
+        &email_change.old_verification_challenge_key_hash,
+    )
+    .await
+    .map_err(report_postgres_err)?
+    .ok_or(response::AuthError::VerificationChallengeNonexistent)?;
+
+    // if so, check that nobody else has taken the old address in the meantime
+    if maybe_old_email.is_none()
+        && email_service::exists_by_own_email(con, &old_vc.email)
+            .await
+            .map_err(report_postgres_err)?
+    {
+        Err(response::AuthError::EmailExistent)?;
+    }
+
+    let to_cancel_keys = api_key_service::get_current_by_creator_user_id(
+        con,
+        email_change.creator_user_id,
+        utils::current_time_millis(),
+    )
+    .await
+    .map_err(report_postgres_err)?;
+
+    let mut sp = con.transaction().await.map_err(report_postgres_err)?;
+
+    let email = match maybe_old_email {
+        Some(old_email) => old_email,
+        None => {
+            // each email needs its own verification challenge
+            // clicking the revert link proves the old address just as well as a verification email would,
+            // so this key is never sent to anyone
+            let raw_key = data
+                .token_hasher
+                .gen_token(TokenKind::VerificationChallenge);
+            let revert_vc = verification_challenge_service::add(
+                &mut sp,
+                data.token_hasher.hash(&raw_key),
+                old_vc.email.clone(),
+                email_change.creator_user_id,
+                false,
+            )
+            .await
+            .map_err(report_postgres_err)?;
+
+            email_service::add(&mut sp, revert_vc.verification_challenge_key_hash)
+                .await
+                .map_err(report_postgres_err)?
+        }
+    };
+
+    // the old email becomes primary again, and the added one is dropped
+    email_service::add_primary(&mut sp, email_change.creator_user_id, email.email_id)
+        .await
+        .map_err(report_postgres_err)?;
+
+    email_service::add_removal(&mut sp, new_email.email_id)
+        .await
+        .map_err(report_postgres_err)?;
+
+    email_change_service::add_revert(&mut sp, &email_change.email_change_key_hash)
+        .await
+        .map_err(report_postgres_err)?;
+
+    // whoever added the email may still be logged in
+    for to_cancel_key in to_cancel_keys.into_iter() {
+        api_key_service::add(
+            &mut sp,
+            email_change.creator_user_id,
+            to_cancel_key.api_key_hash,
+            request::ApiKeyKind::Cancel,
+            0,
+        )
+        .await
+        .map_err(report_postgres_err)?;
+    }
+
+    sp.commit().await.map_err(report_postgres_err)?;
+
+    log::info!(
+        "user {} reverted an email change",
+        email_change.creator_user_id
+    );
+
+    // return json
+    Ok(web::Json(fill_email(con, email).await?))
+}
+
+pub async fn email_new_primary(
+    data: web::Data<Data>,
+    props: web::Json<request::EmailNewPrimaryProps>,
+) -> Result<impl Responder, AppError> {
+    let con = &mut *data.db.lock().await;
+
+    // api key verification required (email or parent permission not needed)
+    let creator_key = get_api_key_if_current_noverify(&data, con, &props.api_key).await?;
+
+    // a stolen api key isn't enough to point notifications at another of the account's addresses
+    require_recent_authentication(&data, con, &creator_key, props.password.as_deref()).await?;
+
+    // the email must be one the user still holds
+    let email = email_service::get_own_by_user_id_and_email_id(
+        con,
+        creator_key.creator_user_id,
+        props.email_id,
+    )
+    .await
+    .map_err(report_postgres_err)?
+    .ok_or(response::AuthError::EmailNonexistent)?;
+
+    email_service::add_primary(con, creator_key.creator_user_id, email.email_id)
+        .await
+        .map_err(report_postgres_err)?;
+
+    // return json
+    Ok(web::Json(fill_email(con, email).await?))
+}
+
+pub async fn email_new_remove(
+    data: web::Data<Data>,
+    props: web::Json<request::EmailNewRemoveProps>,
+) -> Result<impl Responder, AppError> {
+    let con = &mut *data.db.lock().await;
+
+    // api key verification required (email or parent permission not needed)
+    let creator_key = get_api_key_if_current_noverify(&data, con, &props.api_key).await?;
+
+    // a stolen api key isn't enough to take the owner's address off the account
+    require_recent_authentication(&data, con, &creator_key, props.password.as_deref()).await?;
+
+    // the email must be one the user still holds
+    let email = email_service::get_own_by_user_id_and_email_id(
+        con,
+        creator_key.creator_user_id,
+        props.email_id,
+    )
+    .await
+    .map_err(report_postgres_err)?
+    .ok_or(response::AuthError::EmailNonexistent)?;
+
+    // notifications must always have somewhere to go, so make another email primary first
+    if let Some(primary_email) =
+        email_service::get_primary_by_user_id(con, creator_key.creator_user_id)
+            .await
+            .map_err(report_postgres_err)?
+    {
+        if primary_email.email_id == email.email_id {
+            Err(response::AuthError::EmailPrimary)?;
+        }
+    }
+
+    email_service::add_removal(con, email.email_id)
+        .await
+        .map_err(report_postgres_err)?;
+
+    // return json
+    Ok(web::Json(fill_email(con, email).await?))
+}
+
+// renders a text message template in the user's locale and sends it
+// unlike emails these are sent straight away, since the user is waiting for the code
+async fn send_sms(
+    data: &Data,
+    phone: &str,
+    template: &str,
+    locale: Option<&str>,
+    vars: &[(&str, &str)],
+) -> Result<(), AppError> {
+    let sms_sender = data
+        .sms_sender
+        .as_ref()
+        .ok_or(response::AuthError::SmsUnavailable)?;
+
+    let mut vars = vars.to_vec();
+    vars.push(("app_origin", data.app_pub_origin_web.as_str()));
+
+    let message = data
+        .email_templates
+        .render_sms(template, locale, &vars)
+        .map_err(report_internal_err)?;
+
+    sms_sender
+        .send(phone, &message)
+        .await
+        .map_err(report_internal_err)?;
+
+    Ok(())
+}
+
+// returns the number of the user's verified phone
+async fn get_phone_number(
+    con: &mut tokio_postgres::Client,
+    user_id: i64,
+) -> Result<Option<String>, AppError> {
+    let phone_challenge_id = match phone_service::get_by_user_id(con, user_id)
+        .await
+        .map_err(report_postgres_err)?
+        .and_then(|x| x.phone_challenge_id)
+    {
+        Some(phone_challenge_id) => phone_challenge_id,
+        None => return Ok(None),
+    };
+
+    let phone_challenge =
+        phone_challenge_service::get_by_phone_challenge_id(con, phone_challenge_id)
+            .await
+            .map_err(report_postgres_err)?
+            .ok_or(response::AuthError::PhoneChallengeNonexistent)?;
+
+    Ok(Some(phone_challenge.phone))
+}
+
+// texts a new code to the phone, replacing any earlier code of the same kind
+async fn add_phone_challenge(
+    data: &Data,
+    con: &mut tokio_postgres::Client,
+    user_id: i64,
+    phone: &str,
+    phone_challenge_kind: PhoneChallengeKind,
+) -> Result<PhoneChallenge, AppError> {
+    let current_time = utils::current_time_millis();
+
+    // texts cost money, so limit them both per user and per number
+    let num_by_user = phone_challenge_service::get_num_by_user_id_between(
+        con,
+        user_id,
+        current_time - FIFTEEN_MINUTES,
+        current_time,
+    )
+    .await
+    .map_err(report_postgres_err)?;
+
+    let num_by_phone = phone_challenge_service::get_num_by_phone_between(
+        con,
+        phone,
+        current_time - FIFTEEN_MINUTES,
+        current_time,
+    )
+    .await
+    .map_err(report_postgres_err)?;
+
+    // limit to 4 texts in past 15 minutes
+    if num_by_user >= 4 || num_by_phone >= 4 {
+        Err(response::AuthError::PhoneCooldown)?;
+    }
+
+    let locale = get_locale(con, user_id).await?;
+
+    let phone_code = utils::gen_verification_code();
+
+    let template = match phone_challenge_kind {
+        PhoneChallengeKind::Verify => "phone_challenge",
+        PhoneChallengeKind::SecondFactor => "second_factor",
+    };
+
+    let mut sp = con.transaction().await.map_err(report_postgres_err)?;
+
+    let phone_challenge = phone_challenge_service::add(
+        &mut sp,
+        user_id,
+        phone.to_owned(),
+        phone_challenge_kind,
+        data.token_hasher.hash(&phone_code),
+    )
+    .await
+    .map_err(report_postgres_err)?;
+
+    // if the text can't be sent, the challenge is rolled back
+    send_sms(
+        data,
+        phone,
+        template,
+        locale.as_deref(),
+        &[("code", &phone_code), ("expiry_minutes", "15")],
+    )
+    .await?;
+
+    sp.commit().await.map_err(report_postgres_err)?;
+
+    Ok(phone_challenge)
+}
+
+// checks the code against the latest challenge of this kind texted for the user, recording the attempt
+// after too many wrong guesses the code stops working, and a new one has to be asked for
+async fn check_phone_code(
+    data: &Data,
+    con: &mut tokio_postgres::Client,
+    user_id: i64,
+    phone_challenge_kind: PhoneChallengeKind,
+    phone_code: &str,
+) -> Result<PhoneChallenge, AppError> {
+    let phone_challenge =
+        phone_challenge_service::get_latest_by_user_id_and_kind(con, user_id, phone_challenge_kind)
+            .await
+            .map_err(report_postgres_err)?
+            .ok_or(response::AuthError::PhoneChallengeNonexistent)?;
+
+    if phone_challenge.creation_time + FIFTEEN_MINUTES < utils::current_time_millis() {
+        Err(response::AuthError::PhoneChallengeTimedOut)?;
+    }
+
+    if phone_challenge_service::exists_successful_attempt(con, phone_challenge.phone_challenge_id)
+        .await
+        .map_err(report_postgres_err)?
+    {
+        Err(response::AuthError::PhoneChallengeUsed)?;
+    }
+
+    let num_failed =
+        phone_challenge_service::get_num_failed_attempts(con, phone_challenge.phone_challenge_id)
+            .await
+            .map_err(report_postgres_err)?;
+
+    if num_failed >= MAX_VERIFICATION_CODE_ATTEMPTS {
+        Err(response::AuthError::PhoneChallengeLocked)?;
+    }
+
+    let success = data
+        .token_hasher
+        .verify(phone_code.trim(), &phone_challenge.phone_code_hash);
+
+    phone_challenge_service::add_attempt(con, phone_challenge.phone_challenge_id, success)
+        .await
+        .map_err(report_postgres_err)?;
+
+    if !success {
+        Err(response::AuthError::PhoneCodeIncorrect)?;
+    }
+
+    Ok(phone_challenge)
+}
+
+async fn is_second_factor_enabled(
+    con: &mut tokio_postgres::Client,
+    user_id: i64,
+) -> Result<bool, AppError> {
+    Ok(second_factor_service::get_by_user_id(con, user_id)
+        .await
+        .map_err(report_postgres_err)?
+        .is_some_and(|x| x.enabled))
+}
+
+// checks a second factor code, or without one texts a code to the user's phone and fails with SecondFactorRequired,
+// so that the request can be made again with the code
+async fn check_second_factor(
+    data: &Data,
+    con: &mut tokio_postgres::Client,
+    user_id: i64,
+    second_factor_code: Option<&str>,
+) -> Result<(), AppError> {
+    match second_factor_code {
+        Some(second_factor_code) => {
+            check_phone_code(
+                data,
+                con,
+                user_id,
+                PhoneChallengeKind::SecondFactor,
+                second_factor_code,
+            )
+            .await?;
+        }
+        None => {
+            let phone = get_phone_number(con, user_id)
+                .await?
+                .ok_or(response::AuthError::PhoneNonexistent)?;
+
+            add_phone_challenge(data, con, user_id, &phone, PhoneChallengeKind::SecondFactor)
+                .await?;
+
+            Err(response::AuthError::SecondFactorRequired)?;
+        }
+    }
+
+    Ok(())
+}
+
+// fails if someone else already holds the number
+async fn check_phone_unused(
+    con: &mut tokio_postgres::Client,
+    user_id: i64,
+    phone: &str,
+) -> Result<(), AppError> {
+    if let Some(holder) = phone_service::get_by_phone(con, phone)
+        .await
+        .map_err(report_postgres_err)?
+    {
+        if holder.creator_user_id != user_id {
+            Err(response::AuthError::PhoneExistent)?;
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn phone_challenge_new(
+    data: web::Data<Data>,
+    props: web::Json<request::PhoneChallengeNewProps>,
+) -> Result<impl Responder, AppError> {
+    let phone = utils::normalize_phone(&props.phone);
+
+    if !utils::is_phone_valid(&phone) {
+        Err(response::AuthError::PhoneInvalid)?;
+    }
+
+    let con = &mut *data.db.lock().await;
+
+    let api_key = get_api_key_if_valid(&data, con, &props.api_key).await?;
+
+    // the phone can reset the password, so a stolen api key shouldn't be enough to add one
+    require_recent_authentication(&data, con, &api_key, props.password.as_deref()).await?;
+
+    check_phone_unused(con, api_key.creator_user_id, &phone).await?;
+
+    let phone_challenge = add_phone_challenge(
+        &data,
+        con,
+        api_key.creator_user_id,
+        &phone,
+        PhoneChallengeKind::Verify,
+    )
+    .await?;
+
+    Ok(web::Json(fill_phone_challenge(con, phone_challenge).await?))
+}
+
+pub async fn phone_new(
+    data: web::Data<Data>,
+    props: web::Json<request::PhoneNewProps>,
+) -> Result<impl Responder, AppError> {
+    let con = &mut *data.db.lock().await;
+
+    let api_key = get_api_key_if_valid(&data, con, &props.api_key).await?;
+
+    let phone_challenge = check_phone_code(
+        &data,
+        con,
+        api_key.creator_user_id,
+        PhoneChallengeKind::Verify,
+        &props.phone_code,
+    )
+    .await?;
 
-    // send email depending on kind
-    if props.to_parent {
-        send_parent_permission_email(
-            &data.mail_service,
-            &props.email,
-            &user_data.realname,
-            &data.app_pub_origin_web,
-            &verification_challenge_key,
-        )
-        .await?;
-    } else {
-        send_email_verification_email(
-            &data.mail_service,
-            &props.email,
-            &user_data.realname,
-            &data.app_pub_origin_web,
-            &verification_challenge_key,
-        )
-        .await?;
-    }
+    // someone else may have verified the number while the code was on its way
+    check_phone_unused(con, api_key.creator_user_id, &phone_challenge.phone).await?;
 
-    // insert into database
-    let verification_challenge = verification_challenge_service::add(
+    let phone = phone_service::add(
         con,
-        utils::hash_str(&verification_challenge_key),
-        props.email.clone(),
         api_key.creator_user_id,
-        props.to_parent,
+        Some(phone_challenge.phone_challenge_id),
     )
     .await
     .map_err(report_postgres_err)?;
 
-    // return json
-    Ok(web::Json(
-        fill_verification_challenge(con, verification_challenge).await?,
-    ))
+    Ok(web::Json(fill_phone(con, phone).await?))
 }
 
-pub async fn user_new(
+pub async fn phone_new_remove(
     data: web::Data<Data>,
-    props: web::Json<request::UserNewProps>,
+    props: web::Json<request::PhoneNewRemoveProps>,
 ) -> Result<impl Responder, AppError> {
-    if !utils::is_realname_valid(&props.realname) {
-        Err(response::AuthError::UserRealnameInvalid)?;
-    }
+    let con = &mut *data.db.lock().await;
 
-    if !utils::is_username_valid(&props.username) {
-        Err(response::AuthError::UserUsernameInvalid)?;
+    let api_key = get_api_key_if_valid(&data, con, &props.api_key).await?;
+
+    // the phone can reset the password, so a stolen api key shouldn't be enough to take it off either
+    require_recent_authentication(&data, con, &api_key, props.password.as_deref()).await?;
+
+    if phone_service::get_by_user_id(con, api_key.creator_user_id)
+        .await
+        .map_err(report_postgres_err)?
+        .is_none()
+    {
+        Err(response::AuthError::PhoneNonexistent)?;
     }
 
-    // server side validation of password strength
-    if !utils::is_secure_password(&props.password) {
-        Err(response::AuthError::PasswordInsecure)?;
+    // otherwise the user couldn't log in any more
+    if second_factor_service::get_by_user_id(con, api_key.creator_user_id)
+        .await
+        .map_err(report_postgres_err)?
+        .is_some_and(|x| x.enabled)
+    {
+        Err(response::AuthError::SecondFactorEnabled)?;
     }
 
+    let phone = phone_service::add(con, api_key.creator_user_id, None)
+        .await
+        .map_err(report_postgres_err)?;
+
+    Ok(web::Json(fill_phone(con, phone).await?))
+}
+
+pub async fn second_factor_new(
+    data: web::Data<Data>,
+    props: web::Json<request::SecondFactorNewProps>,
+) -> Result<impl Responder, AppError> {
     let con = &mut *data.db.lock().await;
 
-    let mut sp = con.transaction().await.map_err(report_postgres_err)?;
+    let api_key = get_api_key_if_valid(&data, con, &props.api_key).await?;
 
-    // check username is not used
-    if user_data_service::get_by_username(&mut sp, &props.username)
-        .await
-        .map_err(report_postgres_err)?
-        .is_some()
+    // turning it off is as sensitive as removing the phone
+    require_recent_authentication(&data, con, &api_key, props.password.as_deref()).await?;
+
+    // codes are texted to the verified phone
+    if props.enabled
+        && phone_service::get_by_user_id(con, api_key.creator_user_id)
+            .await
+            .map_err(report_postgres_err)?
+            .is_none()
     {
-        Err(response::AuthError::UserUsernameTaken)?;
+        Err(response::AuthError::PhoneNonexistent)?;
     }
 
-    // create user
-    let user = user_service::add(&mut sp)
+    let second_factor = second_factor_service::add(con, api_key.creator_user_id, props.enabled)
         .await
         .map_err(report_postgres_err)?;
 
-    // create user data
-    let user_data = user_data_service::add(
-        &mut sp,
-        user.user_id,
-        props.dateofbirth,
-        props.username.clone(),
-        props.realname.clone(),
-    )
-    .await
-    .map_err(report_postgres_err)?;
+    Ok(web::Json(fill_second_factor(con, second_factor).await?))
+}
 
-    // create password
-    let password_hash = utils::hash_password(&props.password).map_err(report_internal_err)?;
-    password_service::add(&mut sp, user.user_id, password_hash, None)
+pub async fn parent_permission_new(
+    data: web::Data<Data>,
+    props: web::Json<request::ParentPermissionNewProps>,
+) -> Result<impl Responder, AppError> {
+    // the parent must have been shown some version of the terms
+    if props.terms_version.is_empty() {
+        Err(response::AuthError::ParentPermissionTermsVersionInvalid)?;
+    }
+
+    let con = &mut *data.db.lock().await;
+
+    // no api key verification needed, the verification challenge proves this is the parent
+    let vckh =
+        get_verification_challenge_key_hash(&data, con, &props.verification_challenge_key).await?;
+    let vc = get_unused_verification_challenge(con, &vckh, true).await?;
+
+    let mut sp = con.transaction().await.map_err(report_postgres_err)?;
+
+    // record the parent's email
+    email_service::add(&mut sp, vc.verification_challenge_key_hash.clone())
         .await
         .map_err(report_postgres_err)?;
 
-    let raw_api_key = utils::gen_random_string();
-    // add new api key
-    let api_key = api_key_service::add(
+    // record what the parent agreed to
+    let parent_permission = parent_permission_service::add(
         &mut sp,
-        user_data.creator_user_id,
-        utils::hash_str(&raw_api_key),
-        request::ApiKeyKind::NoEmail,
-        // 1 hour
-        props.api_key_duration as i64,
+        vc.creator_user_id,
+        vc.verification_challenge_key_hash,
+        request::ParentPermissionKind::Grant,
+        Some(props.terms_version.clone()),
     )
     .await
     .map_err(report_postgres_err)?;
 
     sp.commit().await.map_err(report_postgres_err)?;
 
-    // return api key
+    // return json
     Ok(web::Json(
-        fill_api_key(con, api_key, Some(raw_api_key)).await?,
+        fill_parent_permission(con, parent_permission).await?,
     ))
 }
 
-pub async fn user_data_new(
+pub async fn parent_permission_new_with_api_key(
     data: web::Data<Data>,
-    props: web::Json<request::UserDataNewProps>,
+    props: web::Json<request::ParentPermissionNewWithApiKeyProps>,
 ) -> Result<impl Responder, AppError> {
-    // ensure names are valid
-    if !utils::is_realname_valid(&props.realname) {
-        Err(response::AuthError::UserRealnameInvalid)?;
-    }
-
-    if !utils::is_username_valid(&props.username) {
-        Err(response::AuthError::UserUsernameInvalid)?;
+    // the parent must have been shown some version of the terms
+    if props.terms_version.is_empty() {
+        Err(response::AuthError::ParentPermissionTermsVersionInvalid)?;
     }
 
     let con = &mut *data.db.lock().await;
 
-    // api key verification required (email or parent permission not needed)
-    let creator_key = get_api_key_if_current_noverify(con, &props.api_key).await?;
+    // logging in as the parent stands in for clicking the link in the email
+    let (_, parent_email) = get_parent_email_if_valid(&data, con, &props.api_key).await?;
 
-    // check username is not used
-    let maybe_user_data = user_data_service::get_by_username(con, &props.username)
+    // approve the child's most recent request to this parent
+    let vc = verification_challenge_service::get_pending_parent_by_email(
+        con,
+        &parent_email,
+        utils::current_time_millis() - ONE_WEEK,
+    )
+    .await
+    .map_err(report_postgres_err)?
+    .into_iter()
+    .rfind(|vc| vc.creator_user_id == props.user_id)
+    .ok_or(response::AuthError::VerificationChallengeNonexistent)?;
+
+    let mut sp = con.transaction().await.map_err(report_postgres_err)?;
+
+    // record the parent's email
+    email_service::add(&mut sp, vc.verification_challenge_key_hash.clone())
         .await
         .map_err(report_postgres_err)?;
 
-    let username_not_taken = match maybe_user_data {
-        Some(UserData {
-            creator_user_id, ..
-        }) => creator_user_id == creator_key.creator_user_id,
-        None => true,
+    // record what the parent agreed to
+    let parent_permission = parent_permission_service::add(
+        &mut sp,
+        vc.creator_user_id,
+        vc.verification_challenge_key_hash,
+        request::ParentPermissionKind::Grant,
+        Some(props.terms_version.clone()),
+    )
+    .await
+    .map_err(report_postgres_err)?;
+
+    sp.commit().await.map_err(report_postgres_err)?;
+
+    // return json
+    Ok(web::Json(
+        fill_parent_permission(con, parent_permission).await?,
+    ))
+}
+
+// renews the child's permission if it was last granted through this parent email, even if it lapsed
+async fn internal_parent_permission_renew(
+    con: &mut tokio_postgres::Client,
+    parent_email: &str,
+    user_id: i64,
+    terms_version: String,
+) -> Result<response::ParentPermission, AppError> {
+    // the parent must have been shown some version of the terms
+    if terms_version.is_empty() {
+        Err(response::AuthError::ParentPermissionTermsVersionInvalid)?;
+    }
+
+    let current_permission = match parent_permission_service::get_by_user_id(con, user_id)
+        .await
+        .map_err(report_postgres_err)?
+    {
+        Some(
+            pp @ ParentPermission {
+                parent_permission_kind: request::ParentPermissionKind::Grant,
+                ..
+            },
+        ) => pp,
+        _ => Err(response::AuthError::ParentPermissionNonexistent)?,
     };
 
-    if !username_not_taken {
-        Err(response::AuthError::UserUsernameTaken)?;
+    let vc = verification_challenge_service::get_by_verification_challenge_key_hash(
+        con,
+        &current_permission.verification_challenge_key_hash,
+    )
+    .await
+    .map_err(report_postgres_err)?
+    .ok_or(response::AuthError::VerificationChallengeNonexistent)?;
+
+    if vc.email != parent_email {
+        Err(response::AuthError::ParentPermissionNonexistent)?;
     }
 
-    // create key data
-    let user_data = user_data_service::add(
+    let parent_permission = parent_permission_service::add(
         con,
-        creator_key.creator_user_id,
-        props.dateofbirth,
-        props.username.clone(),
-        props.realname.clone(),
+        user_id,
+        current_permission.verification_challenge_key_hash,
+        request::ParentPermissionKind::Grant,
+        Some(terms_version),
     )
     .await
     .map_err(report_postgres_err)?;
 
-    // return json
-    Ok(web::Json(fill_user_data(con, user_data).await?))
+    fill_parent_permission(con, parent_permission).await
 }
 
-pub async fn email_new(
+pub async fn parent_permission_new_renew(
     data: web::Data<Data>,
-    props: web::Json<request::EmailNewProps>,
+    props: web::Json<request::ParentPermissionNewRenewProps>,
 ) -> Result<impl Responder, AppError> {
     let con = &mut *data.db.lock().await;
 
-    let vckh = utils::hash_str(&props.verification_challenge_key);
+    let parent_access = get_parent_access_if_valid(&data, con, &props.parent_access_key).await?;
 
-    // check that the verification challenge exists
-    let vc = verification_challenge_service::get_by_verification_challenge_key_hash(con, &vckh)
-        .await
-        .map_err(report_postgres_err)?
-        .ok_or(response::AuthError::VerificationChallengeNonexistent)?;
+    // now delegate
+    Ok(web::Json(
+        internal_parent_permission_renew(
+            con,
+            &parent_access.email,
+            props.user_id,
+            props.terms_version.clone(),
+        )
+        .await?,
+    ))
+}
 
-    // check that it hasn't timed out
-    if FIFTEEN_MINUTES as i64 + vc.creation_time < utils::current_time_millis() {
-        Err(response::AuthError::VerificationChallengeTimedOut)?;
+pub async fn parent_permission_new_renew_with_api_key(
+    data: web::Data<Data>,
+    props: web::Json<request::ParentPermissionNewRenewWithApiKeyProps>,
+) -> Result<impl Responder, AppError> {
+    let con = &mut *data.db.lock().await;
+
+    let (_, parent_email) = get_parent_email_if_valid(&data, con, &props.api_key).await?;
+
+    // now delegate
+    Ok(web::Json(
+        internal_parent_permission_renew(
+            con,
+            &parent_email,
+            props.user_id,
+            props.terms_version.clone(),
+        )
+        .await?,
+    ))
+}
+
+pub async fn parent_access_new(
+    data: web::Data<Data>,
+    props: web::Json<request::ParentAccessNewProps>,
+) -> Result<impl Responder, AppError> {
+    let email_address =
+        email_normalization::normalize_email(&props.email, &data.email_folding_rules);
+
+    let con = &mut *data.db.lock().await;
+
+    // don't let people spam emails
+    let num_emails = parent_access_service::get_num_by_email_between(
+        con,
+        &email_address,
+        utils::current_time_millis() - FIFTEEN_MINUTES,
+        utils::current_time_millis(),
+    )
+    .await
+    .map_err(report_postgres_err)?;
+
+    // limit to 4 emails in past 15 minutes
+    if num_emails > 4 {
+        Err(response::AuthError::EmailCooldown)?;
     }
 
-    // check that the verification challenge is meant for the correct purpose
-    if vc.to_parent != props.to_parent {
-        Err(response::AuthError::VerificationChallengeWrongKind)?;
+    let children = parent_permission_service::get_by_parent_email(con, &email_address)
+        .await
+        .map_err(report_postgres_err)?;
+
+    let raw_key = data.token_hasher.gen_token(TokenKind::ParentAccess);
+
+    let mut sp = con.transaction().await.map_err(report_postgres_err)?;
+
+    let parent_access = parent_access_service::add(
+        &mut sp,
+        data.token_hasher.hash(&raw_key),
+        utils::current_time_millis() + ONE_HOUR,
+        email_address.clone(),
+    )
+    .await
+    .map_err(report_postgres_err)?;
+
+    // only send mail if there is something to manage,
+    // but respond the same either way so that this can't be used to find out who is a parent
+    if !children.is_empty() {
+        send_parent_access_email(&data, &mut sp, &email_address, &raw_key).await?;
     }
 
-    // check if the verification challenge was not already used to make a new email
-    if email_service::get_by_verification_challenge_key_hash(con, &vckh)
+    sp.commit().await.map_err(report_postgres_err)?;
+
+    // return json
+    Ok(web::Json(fill_parent_access(con, parent_access).await?))
+}
+
+pub async fn parent_permission_new_revoke(
+    data: web::Data<Data>,
+    props: web::Json<request::ParentPermissionNewRevokeProps>,
+) -> Result<impl Responder, AppError> {
+    let con = &mut *data.db.lock().await;
+
+    let parent_access = get_parent_access_if_valid(&data, con, &props.parent_access_key).await?;
+
+    // the child's current permission must have come from this parent
+    let current_permission = match parent_permission_service::get_by_user_id(con, props.user_id)
         .await
         .map_err(report_postgres_err)?
-        .is_some()
     {
-        Err(response::AuthError::VerificationChallengeUsed)?;
+        Some(
+            pp @ ParentPermission {
+                parent_permission_kind: request::ParentPermissionKind::Grant,
+                ..
+            },
+        ) => pp,
+        _ => Err(response::AuthError::ParentPermissionNonexistent)?,
+    };
+
+    let vc = verification_challenge_service::get_by_verification_challenge_key_hash(
+        con,
+        &current_permission.verification_challenge_key_hash,
+    )
+    .await
+    .map_err(report_postgres_err)?
+    .ok_or(response::AuthError::VerificationChallengeNonexistent)?;
+
+    if vc.email != parent_access.email {
+        Err(response::AuthError::ParentPermissionNonexistent)?;
     }
 
-    // (if not parent) check that the email isn't already in use by another user
-    if !vc.to_parent {
-        if email_service::get_by_own_email(con, &vc.email)
-            .await
-            .map_err(report_postgres_err)?
-            .is_some()
-        {
-            Err(response::AuthError::EmailExistent)?;
+    let mut sp = con.transaction().await.map_err(report_postgres_err)?;
+
+    let parent_permission = parent_permission_service::add(
+        &mut sp,
+        props.user_id,
+        current_permission.verification_challenge_key_hash,
+        request::ParentPermissionKind::Revoke,
+        None,
+    )
+    .await
+    .map_err(report_postgres_err)?;
+
+    // the child can no longer use keys that relied on this permission
+    api_key_service::change_current_kind(
+        &mut sp,
+        props.user_id,
+        request::ApiKeyKind::Valid,
+        request::ApiKeyKind::NoParent,
+        utils::current_time_millis(),
+    )
+    .await
+    .map_err(report_postgres_err)?;
+
+    sp.commit().await.map_err(report_postgres_err)?;
+
+    // return json
+    Ok(web::Json(
+        fill_parent_permission(con, parent_permission).await?,
+    ))
+}
+
+// called by the mail provider when an email bounces or is reported as spam after it was accepted
+pub async fn email_delivery_event_new(
+    req: HttpRequest,
+    data: web::Data<Data>,
+    props: web::Json<request::EmailDeliveryEventNewProps>,
+) -> Result<impl Responder, AppError> {
+    // the provider is configured to send the secret as a bearer token
+    let authorization = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|x| x.to_str().ok());
+
+    // compare hashes so that the time taken doesn't give away how much of the secret matched
+    let authorized = match (&data.email_delivery_webhook_secret, authorization) {
+        (Some(secret), Some(authorization)) => {
+            utils::hash_str(authorization) == utils::hash_str(&format!("Bearer {}", secret))
         }
+        _ => false,
+    };
+
+    if !authorized {
+        Err(response::AuthError::EmailDeliveryEventUnauthorized)?;
     }
 
-    // create key data
-    let email = email_service::add(con, vckh)
-        .await
-        .map_err(report_postgres_err)?;
+    let email_address =
+        email_normalization::normalize_email(&props.email, &data.email_folding_rules);
+
+    let email_suppression_kind = match props.email_delivery_event_kind {
+        request::EmailDeliveryEventKind::Bounce => EmailSuppressionKind::Bounce,
+        request::EmailDeliveryEventKind::Complaint => EmailSuppressionKind::Complaint,
+    };
 
-    // return json
-    Ok(web::Json(fill_email(con, email).await?))
+    let con = &mut *data.db.lock().await;
+
+    let email_suppression =
+        email_suppression_service::add(con, email_address, email_suppression_kind)
+            .await
+            .map_err(report_postgres_err)?;
+
+    log::info!(
+        "suppressed an address after a {:?}",
+        email_suppression.email_suppression_kind
+    );
+
+    Ok(web::Json(response::EmailDeliveryEvent {
+        creation_time: email_suppression.creation_time,
+        email: email_suppression.email,
+        email_delivery_event_kind: props.email_delivery_event_kind.clone(),
+    }))
 }
 
 pub async fn password_reset_new(
@@ -703,11 +3203,29 @@ pub async fn password_reset_new(
 ) -> Result<impl Responder, AppError> {
     let con = &mut *data.db.lock().await;
 
-    let email = email_service::get_by_own_email(con, &props.email)
+    let email_address =
+        email_normalization::normalize_email(&props.email, &data.email_folding_rules);
+
+    let email = email_service::get_by_own_email(con, &email_address)
         .await
         .map_err(report_postgres_err)?
         .ok_or(response::AuthError::EmailNonexistent)?;
 
+    // if the email was just changed to this address, give the old address time to revert it
+    if let Some(email_change) = email_change_service::get_by_new_verification_challenge_key_hash(
+        con,
+        &email.verification_challenge_key_hash,
+    )
+    .await
+    .map_err(report_postgres_err)?
+    {
+        if email_change.creation_time + data.email_change_reset_cooldown
+            > utils::current_time_millis()
+        {
+            Err(response::AuthError::PasswordResetCooldown)?;
+        }
+    }
+
     let verification_challenge =
         verification_challenge_service::get_by_verification_challenge_key_hash(
             con,
@@ -717,47 +3235,165 @@ pub async fn password_reset_new(
         .map_err(report_postgres_err)?
         .ok_or(response::AuthError::VerificationChallengeNonexistent)?;
 
-    let raw_key = utils::gen_random_string();
-
-    // send mail
-    let _ = data
-        .mail_service
-        .mail_new(mail_service_api::request::MailNewProps {
-            request_id: 0,
-            destination: props.email.clone(),
-            topic: "password_reset".to_owned(),
-            title: format!("{}: Password Reset", &data.app_pub_origin_web),
-            content: [
-                "<p>Requested password reset service: </p>",
-                "<p>If you did not make this request, then feel free to ignore.</p>",
-                "<p>This link is valid for up to 15 minutes.</p>",
-                "<p>Do not share this link with others.</p>",
-                &format!(
-                    "<p>Password change link: {}/reset_password?resetKey={}</p>",
-                    &data.app_pub_origin_web, raw_key
-                ),
-            ]
-            .join(""),
-        })
+    let user_data = user_data_service::get_by_user_id(con, verification_challenge.creator_user_id)
         .await
-        .map_err(report_mail_err)?;
+        .map_err(report_postgres_err)?
+        .ok_or(response::AuthError::UserDataNonexistent)?;
+
+    let locale = get_locale(con, verification_challenge.creator_user_id).await?;
+
+    let raw_key = data.token_hasher.gen_token(TokenKind::PasswordReset);
 
     let mut sp = con.transaction().await.map_err(report_postgres_err)?;
 
     let password_reset = password_reset_service::add(
         &mut sp,
-        utils::hash_str(&raw_key),
+        data.token_hasher.hash(&raw_key),
         verification_challenge.creator_user_id,
     )
     .await
     .map_err(report_postgres_err)?;
 
+    // send to the address as it was verified
+    send_password_reset_email(
+        &data,
+        &mut sp,
+        &verification_challenge.email,
+        &user_data.realname,
+        locale.as_deref(),
+        &raw_key,
+    )
+    .await?;
+
     sp.commit().await.map_err(report_postgres_err)?;
 
     // fill struct
     Ok(web::Json(fill_password_reset(con, password_reset).await?))
 }
 
+pub async fn password_reset_new_with_phone(
+    data: web::Data<Data>,
+    props: web::Json<request::PasswordResetNewWithPhoneProps>,
+) -> Result<impl Responder, AppError> {
+    let phone_number = utils::normalize_phone(&props.phone);
+
+    if data.sms_sender.is_none() {
+        Err(response::AuthError::SmsUnavailable)?;
+    }
+
+    // respond the same whether or not anything was texted,
+    // so that this can't be used to find out which numbers belong to users
+    let nothing_sent = response::PasswordReset {
+        creation_time: utils::current_time_millis(),
+    };
+
+    let con = &mut *data.db.lock().await;
+
+    let phone = match phone_service::get_by_phone(con, &phone_number)
+        .await
+        .map_err(report_postgres_err)?
+    {
+        Some(phone) => phone,
+        None => return Ok(web::Json(nothing_sent)),
+    };
+
+    // texts cost money, so don't let people spam them
+    let num_resets = password_reset_service::get_num_by_user_id_between(
+        con,
+        phone.creator_user_id,
+        utils::current_time_millis() - FIFTEEN_MINUTES,
+        utils::current_time_millis(),
+    )
+    .await
+    .map_err(report_postgres_err)?;
+
+    // limit to 4 texts in past 15 minutes, a cooldown would also say that the number is a user's
+    if num_resets >= 4 {
+        return Ok(web::Json(nothing_sent));
+    }
+
+    let user_data = user_data_service::get_by_user_id(con, phone.creator_user_id)
+        .await
+        .map_err(report_postgres_err)?
+        .ok_or(response::AuthError::UserDataNonexistent)?;
+
+    let locale = get_locale(con, phone.creator_user_id).await?;
+
+    let raw_key = data.token_hasher.gen_token(TokenKind::PasswordReset);
+
+    let mut sp = con.transaction().await.map_err(report_postgres_err)?;
+
+    let password_reset = password_reset_service::add(
+        &mut sp,
+        data.token_hasher.hash(&raw_key),
+        phone.creator_user_id,
+    )
+    .await
+    .map_err(report_postgres_err)?;
+
+    // the same link as the email, finished through password_new_reset
+    send_sms(
+        &data,
+        &phone_number,
+        "password_reset",
+        locale.as_deref(),
+        &[
+            ("user_name", &user_data.realname),
+            ("expiry_minutes", "15"),
+            (
+                "link",
+                &format!(
+                    "{}/reset_password?resetKey={}",
+                    data.app_pub_origin_web, raw_key
+                ),
+            ),
+        ],
+    )
+    .await?;
+
+    sp.commit().await.map_err(report_postgres_err)?;
+
+    Ok(web::Json(fill_password_reset(con, password_reset).await?))
+}
+
+// notifies the user's own email that their password was changed
+// the password has already been changed, so failures are only logged
+async fn notify_password_event(
+    data: &Data,
+    con: &mut tokio_postgres::Client,
+    user_id: i64,
+    security_notification_kind: SecurityNotificationKind,
+) {
+    let user_data = match user_data_service::get_by_user_id(con, user_id).await {
+        Ok(Some(user_data)) => user_data,
+        Ok(None) => {
+            log::error!("user {} has no user data", user_id);
+            return;
+        }
+        Err(e) => {
+            report_postgres_err(e);
+            return;
+        }
+    };
+
+    let primary_email = match get_primary_email_address(con, user_id).await {
+        Ok(primary_email) => primary_email,
+        Err(e) => {
+            log::error!("{}", e);
+            return;
+        }
+    };
+
+    notify_security_event(
+        data,
+        con,
+        &user_data,
+        security_notification_kind,
+        primary_email.into_iter().collect(),
+    )
+    .await;
+}
+
 pub async fn password_new_reset(
     data: web::Data<Data>,
     props: web::Json<request::PasswordNewResetProps>,
@@ -767,9 +3403,11 @@ pub async fn password_new_reset(
     let con = &mut *data.db.lock().await;
 
     // get password reset
-    let psr = password_reset_service::get_by_password_reset_key_hash(
+    let psr = password_reset_service::get_by_password_reset_key_hashes(
         con,
-        &utils::hash_str(&props.password_reset_key),
+        &data
+            .token_hasher
+            .candidate_hashes(&props.password_reset_key),
     )
     .await
     .map_err(report_postgres_err)?
@@ -789,13 +3427,13 @@ pub async fn password_new_reset(
     }
 
     // reject insecure passwords
-    if !utils::is_secure_password(&props.new_password) {
-        Err(response::AuthError::PasswordInsecure)?;
-    }
+    check_user_password_policy(&data, con, psr.creator_user_id, &props.new_password).await?;
 
     // attempt to hash password
-    let new_password_hash =
-        utils::hash_password(&props.new_password).map_err(report_internal_err)?;
+    let new_password_hash = data
+        .peppers
+        .hash_password(&props.new_password)
+        .map_err(report_internal_err)?;
 
     let mut sp = con.transaction().await.map_err(report_postgres_err)?;
 
@@ -809,8 +3447,19 @@ pub async fn password_new_reset(
     .await
     .map_err(report_postgres_err)?;
 
+    // in the same transaction, so a failure here doesn't leave the new password with its keys still expired
+    unexpire_api_keys(&data, &mut sp, psr.creator_user_id).await?;
+
     sp.commit().await.map_err(report_postgres_err)?;
 
+    notify_password_event(
+        &data,
+        con,
+        psr.creator_user_id,
+        SecurityNotificationKind::PasswordReset,
+    )
+    .await;
+
     Ok(web::Json(fill_password(con, password).await?))
 }
 
@@ -821,16 +3470,22 @@ pub async fn password_new_change(
     let con = &mut *data.db.lock().await;
 
     // api key verification required (no parent permission needed tho)
-    let creator_key = get_api_key_if_current_noverify(con, &props.api_key).await?;
+    // an expired password is what this is for, so keys marked that way are accepted here
+    let creator_key =
+        get_api_key_if_current_or_password_expired(&data, con, &props.api_key).await?;
+
+    // a stolen api key isn't enough to lock the owner out
+    require_recent_authentication(&data, con, &creator_key, props.password.as_deref()).await?;
 
     // reject insecure passwords
-    if !utils::is_secure_password(&props.new_password) {
-        Err(response::AuthError::PasswordInsecure)?;
-    }
+    check_user_password_policy(&data, con, creator_key.creator_user_id, &props.new_password)
+        .await?;
 
     // attempt to hash password
-    let new_password_hash =
-        utils::hash_password(&props.new_password).map_err(report_internal_err)?;
+    let new_password_hash = data
+        .peppers
+        .hash_password(&props.new_password)
+        .map_err(report_internal_err)?;
 
     let mut sp = con.transaction().await.map_err(report_postgres_err)?;
 
@@ -844,19 +3499,133 @@ pub async fn password_new_change(
     .await
     .map_err(report_postgres_err)?;
 
+    unexpire_api_keys(&data, &mut sp, creator_key.creator_user_id).await?;
+
     sp.commit().await.map_err(report_postgres_err)?;
 
+    notify_password_event(
+        &data,
+        con,
+        creator_key.creator_user_id,
+        SecurityNotificationKind::PasswordChange,
+    )
+    .await;
+
     // return filled struct
     Ok(web::Json(fill_password(con, password).await?))
 }
 
+pub async fn notification_preference_new(
+    data: web::Data<Data>,
+    props: web::Json<request::NotificationPreferenceNewProps>,
+) -> Result<impl Responder, AppError> {
+    let con = &mut *data.db.lock().await;
+
+    // api key verification required (email or parent permission not needed)
+    let creator_key = get_api_key_if_current_noverify(&data, con, &props.api_key).await?;
+
+    if let Some(locale) = &props.locale {
+        if !utils::is_locale_valid(locale) {
+            Err(response::AuthError::NotificationPreferenceLocaleInvalid)?;
+        }
+    }
+
+    let notification_preference = notification_preference_service::add(
+        con,
+        creator_key.creator_user_id,
+        props.security_notifications,
+        props.locale.clone(),
+    )
+    .await
+    .map_err(report_postgres_err)?;
+
+    // return json
+    Ok(web::Json(
+        fill_notification_preference(con, notification_preference).await?,
+    ))
+}
+
+pub async fn account_deletion_new(
+    data: web::Data<Data>,
+    props: web::Json<request::AccountDeletionNewProps>,
+) -> Result<impl Responder, AppError> {
+    let con = &mut *data.db.lock().await;
+
+    // api key verification required (email or parent permission not needed)
+    let creator_key = get_api_key_if_current_noverify(&data, con, &props.api_key).await?;
+
+    // a stolen api key isn't enough to delete the account
+    require_recent_authentication(&data, con, &creator_key, props.password.as_deref()).await?;
+
+    // deny if there's already a deletion in progress
+    if let Some(AccountDeletion {
+        account_deletion_kind: request::AccountDeletionKind::Pending,
+        ..
+    }) = account_deletion_service::get_by_user_id(con, creator_key.creator_user_id)
+        .await
+        .map_err(report_postgres_err)?
+    {
+        Err(response::AuthError::AccountDeletionExistent)?;
+    }
+
+    let account_deletion = account_deletion_service::add(
+        con,
+        creator_key.creator_user_id,
+        request::AccountDeletionKind::Pending,
+        utils::current_time_millis() + data.account_deletion_grace_period,
+    )
+    .await
+    .map_err(report_postgres_err)?;
+
+    // return json
+    Ok(web::Json(
+        fill_account_deletion(con, account_deletion).await?,
+    ))
+}
+
+pub async fn account_deletion_new_cancel(
+    data: web::Data<Data>,
+    props: web::Json<request::AccountDeletionNewCancelProps>,
+) -> Result<impl Responder, AppError> {
+    let con = &mut *data.db.lock().await;
+
+    // api key verification required (email or parent permission not needed)
+    let creator_key = get_api_key_if_current_noverify(&data, con, &props.api_key).await?;
+
+    // can only cancel a deletion that hasn't happened yet
+    match account_deletion_service::get_by_user_id(con, creator_key.creator_user_id)
+        .await
+        .map_err(report_postgres_err)?
+    {
+        Some(AccountDeletion {
+            account_deletion_kind: request::AccountDeletionKind::Pending,
+            ..
+        }) => (),
+        _ => Err(response::AuthError::AccountDeletionNonexistent)?,
+    }
+
+    let account_deletion = account_deletion_service::add(
+        con,
+        creator_key.creator_user_id,
+        request::AccountDeletionKind::Cancel,
+        0,
+    )
+    .await
+    .map_err(report_postgres_err)?;
+
+    // return json
+    Ok(web::Json(
+        fill_account_deletion(con, account_deletion).await?,
+    ))
+}
+
 pub async fn user_view(
     data: web::Data<Data>,
     props: web::Json<request::UserViewProps>,
 ) -> Result<impl Responder, AppError> {
     let con = &mut *data.db.lock().await;
     // api key verification required
-    let _ = get_api_key_if_current_noverify(con, &props.api_key).await?;
+    let _ = get_api_key_if_current_noverify(&data, con, &props.api_key).await?;
     // get users
     let users = user_service::query(con, props.into_inner())
         .await
@@ -876,7 +3645,7 @@ pub async fn user_data_view(
 ) -> Result<impl Responder, AppError> {
     let con = &mut *data.db.lock().await;
     // api key verification required
-    let _ = get_api_key_if_current_noverify(con, &props.api_key).await?;
+    let _ = get_api_key_if_current_noverify(&data, con, &props.api_key).await?;
     // get user_datas
     let user_datas = user_data_service::query(con, props.into_inner())
         .await
@@ -896,7 +3665,7 @@ pub async fn email_view(
 ) -> Result<impl Responder, AppError> {
     let con = &mut *data.db.lock().await;
     // api key verification required
-    let _ = get_api_key_if_current_noverify(con, &props.api_key).await?;
+    let _ = get_api_key_if_current_noverify(&data, con, &props.api_key).await?;
     // get emails
     let emails = email_service::query(con, props.into_inner())
         .await
@@ -917,7 +3686,7 @@ pub async fn password_view(
 ) -> Result<impl Responder, AppError> {
     let con = &mut *data.db.lock().await;
     // api key verification required
-    let _ = get_api_key_if_current_noverify(con, &props.api_key).await?;
+    let _ = get_api_key_if_current_noverify(&data, con, &props.api_key).await?;
     // get passwords
     let passwords = password_service::query(con, props.into_inner())
         .await
@@ -932,13 +3701,166 @@ pub async fn password_view(
     Ok(web::Json(resp_passwords))
 }
 
+pub async fn notification_preference_view(
+    data: web::Data<Data>,
+    props: web::Json<request::NotificationPreferenceViewProps>,
+) -> Result<impl Responder, AppError> {
+    let con = &mut *data.db.lock().await;
+    // api key verification required
+    let _ = get_api_key_if_current_noverify(&data, con, &props.api_key).await?;
+    // get notification preferences
+    let notification_preferences = notification_preference_service::query(con, props.into_inner())
+        .await
+        .map_err(report_postgres_err)?;
+
+    // return notification preferences
+    let mut resp_notification_preferences = vec![];
+    for u in notification_preferences.into_iter() {
+        resp_notification_preferences.push(fill_notification_preference(con, u).await?);
+    }
+
+    Ok(web::Json(resp_notification_preferences))
+}
+
+pub async fn phone_view(
+    data: web::Data<Data>,
+    props: web::Json<request::PhoneViewProps>,
+) -> Result<impl Responder, AppError> {
+    let con = &mut *data.db.lock().await;
+
+    // a phone number is only shown to its holder
+    let api_key = get_api_key_if_current_noverify(&data, con, &props.api_key).await?;
+
+    let phones = phone_service::get_by_user_id(con, api_key.creator_user_id)
+        .await
+        .map_err(report_postgres_err)?;
+
+    let mut resp_phones = vec![];
+    for u in phones.into_iter() {
+        resp_phones.push(fill_phone(con, u).await?);
+    }
+
+    Ok(web::Json(resp_phones))
+}
+
+pub async fn account_deletion_view(
+    data: web::Data<Data>,
+    props: web::Json<request::AccountDeletionViewProps>,
+) -> Result<impl Responder, AppError> {
+    let con = &mut *data.db.lock().await;
+    // api key verification required
+    let _ = get_api_key_if_current_noverify(&data, con, &props.api_key).await?;
+    // get account deletions
+    let account_deletions = account_deletion_service::query(con, props.into_inner())
+        .await
+        .map_err(report_postgres_err)?;
+
+    // return account deletions
+    let mut resp_account_deletions = vec![];
+    for u in account_deletions.into_iter() {
+        resp_account_deletions.push(fill_account_deletion(con, u).await?);
+    }
+
+    Ok(web::Json(resp_account_deletions))
+}
+
+pub async fn parent_permission_view(
+    data: web::Data<Data>,
+    props: web::Json<request::ParentPermissionViewProps>,
+) -> Result<impl Responder, AppError> {
+    let con = &mut *data.db.lock().await;
+    // api key verification required
+    let _ = get_api_key_if_current_noverify(&data, con, &props.api_key).await?;
+    // get parent permissions
+    let parent_permissions = parent_permission_service::query(con, props.into_inner())
+        .await
+        .map_err(report_postgres_err)?;
+
+    // return parent permissions
+    let mut resp_parent_permissions = vec![];
+    for u in parent_permissions.into_iter() {
+        resp_parent_permissions.push(fill_parent_permission(con, u).await?);
+    }
+
+    Ok(web::Json(resp_parent_permissions))
+}
+
+pub async fn parent_child_view(
+    data: web::Data<Data>,
+    props: web::Json<request::ParentChildViewProps>,
+) -> Result<impl Responder, AppError> {
+    let con = &mut *data.db.lock().await;
+    // parent access verification required
+    let parent_access = get_parent_access_if_valid(&data, con, &props.parent_access_key).await?;
+    // get every child with this parent
+    let parent_permissions =
+        parent_permission_service::get_by_parent_email(con, &parent_access.email)
+            .await
+            .map_err(report_postgres_err)?;
+
+    // return children
+    let mut resp_parent_children = vec![];
+    for u in parent_permissions.into_iter() {
+        resp_parent_children.push(fill_parent_child(con, u.creator_user_id, None).await?);
+    }
+
+    Ok(web::Json(resp_parent_children))
+}
+
+pub async fn parent_child_view_with_api_key(
+    data: web::Data<Data>,
+    props: web::Json<request::ParentChildViewWithApiKeyProps>,
+) -> Result<impl Responder, AppError> {
+    let con = &mut *data.db.lock().await;
+    // api key verification required, and the user must be a parent
+    let (_, parent_email) = get_parent_email_if_valid(&data, con, &props.api_key).await?;
+
+    // children who already have this parent
+    let child_user_ids = email_service::get_child_user_ids_by_parent_email(con, &parent_email)
+        .await
+        .map_err(report_postgres_err)?;
+
+    // children who are waiting for this parent's permission
+    let pending_verification_challenges =
+        verification_challenge_service::get_pending_parent_by_email(
+            con,
+            &parent_email,
+            utils::current_time_millis() - ONE_WEEK,
+        )
+        .await
+        .map_err(report_postgres_err)?;
+
+    // return children
+    let mut resp_parent_children = vec![];
+    for user_id in child_user_ids.iter() {
+        let pending = pending_verification_challenges
+            .iter()
+            .rfind(|vc| vc.creator_user_id == *user_id)
+            .cloned();
+        resp_parent_children.push(fill_parent_child(con, *user_id, pending).await?);
+    }
+    let mut pending_user_ids = vec![];
+    for vc in pending_verification_challenges.iter().rev() {
+        if child_user_ids.contains(&vc.creator_user_id)
+            || pending_user_ids.contains(&vc.creator_user_id)
+        {
+            continue;
+        }
+        pending_user_ids.push(vc.creator_user_id);
+        resp_parent_children
+            .push(fill_parent_child(con, vc.creator_user_id, Some(vc.clone())).await?);
+    }
+
+    Ok(web::Json(resp_parent_children))
+}
+
 pub async fn api_key_view(
     data: web::Data<Data>,
     props: web::Json<request::ApiKeyViewProps>,
 ) -> Result<impl Responder, AppError> {
     let con = &mut *data.db.lock().await;
     // api key verification required
-    let _ = get_api_key_if_current_noverify(con, &props.api_key).await?;
+    let _ = get_api_key_if_current_noverify(&data, con, &props.api_key).await?;
     // get users
     let api_keys = api_key_service::query(con, props.into_inner())
         .await
@@ -953,6 +3875,28 @@ pub async fn api_key_view(
     Ok(web::Json(resp_api_keys))
 }
 
+pub async fn user_export(
+    data: web::Data<Data>,
+    props: web::Json<request::UserExportProps>,
+) -> Result<impl Responder, AppError> {
+    let con = &mut *data.db.lock().await;
+    // api key verification required (email or parent permission not needed)
+    let creator_key = get_api_key_if_current_noverify(&data, con, &props.api_key).await?;
+
+    let user_export = export::export_user(con, creator_key.creator_user_id)
+        .await
+        .map_err(report_postgres_err)?
+        .ok_or(response::AuthError::UserNonexistent)?;
+
+    // send as a file download
+    Ok(HttpResponse::Ok()
+        .insert_header(header::ContentDisposition::attachment(format!(
+            "user-{}-export.json",
+            creator_key.creator_user_id
+        )))
+        .json(user_export))
+}
+
 // special internal api
 pub async fn get_user_by_id(
     data: web::Data<Data>,
@@ -974,7 +3918,7 @@ pub async fn get_user_by_api_key_if_valid(
 ) -> Result<impl Responder, AppError> {
     let con = &mut *data.db.lock().await;
 
-    let api_key = get_api_key_if_valid(con, &props.api_key).await?;
+    let api_key = get_api_key_if_valid(&data, con, &props.api_key).await?;
 
     let user = user_service::get_by_user_id(con, api_key.creator_user_id)
         .await
@@ -983,3 +3927,47 @@ pub async fn get_user_by_api_key_if_valid(
 
     Ok(web::Json(fill_user(con, user).await?))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_internal_errors_are_server_errors() {
+        for error in [
+            AuthError::InternalServerError,
+            AuthError::Unknown,
+            AuthError::DecodeError,
+            AuthError::SmsUnavailable,
+        ] {
+            assert!(AppError(error).status_code().is_server_error());
+        }
+
+        for (error, status_code) in [
+            (
+                AuthError::PasswordPolicyViolated(vec![]),
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+            (AuthError::ApiKeyNonexistent, StatusCode::UNAUTHORIZED),
+            (
+                AuthError::ReauthenticationRequired,
+                StatusCode::UNAUTHORIZED,
+            ),
+            (AuthError::SecondFactorRequired, StatusCode::UNAUTHORIZED),
+            (
+                AuthError::VerificationCodeIncorrect,
+                StatusCode::UNAUTHORIZED,
+            ),
+            (AuthError::PhoneChallengeTimedOut, StatusCode::GONE),
+            (AuthError::PhoneExistent, StatusCode::CONFLICT),
+            (AuthError::PhoneCooldown, StatusCode::TOO_MANY_REQUESTS),
+            (
+                AuthError::VerificationCodeLocked,
+                StatusCode::TOO_MANY_REQUESTS,
+            ),
+            (AuthError::UserCountryInvalid, StatusCode::BAD_REQUEST),
+        ] {
+            assert_eq!(AppError(error).status_code(), status_code);
+        }
+    }
+}
@@ -0,0 +1,229 @@
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::path::Path;
+
+use super::email_normalization;
+use super::password_hash_format;
+use super::token_hasher::{TokenHasher, TokenKind};
+
+use super::email_service;
+use super::password_service;
+use super::user_data_service;
+use super::user_service;
+use super::utils;
+use super::verification_challenge_service;
+
+// A user moved over from an older system, read by the import-users subcommand.
+// The field names are the same in both formats, eg as CSV:
+//   username,realname,dateofbirth,country,emails,passwordHash
+//   alice,Alice Liddell,946684800000,GB,alice@example.com;alice@example.org,$2b$12$...
+// The emails were verified by the old system, the first becomes the primary one.
+// The password hash can be in any format in password_hash_format.rs, and is replaced with argon2 at the next login.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportRecord {
+    pub username: String,
+    pub realname: String,
+    // milliseconds since the epoch
+    pub dateofbirth: i64,
+    #[serde(default)]
+    pub country: Option<String>,
+    #[serde(default)]
+    pub emails: ImportEmails,
+    pub password_hash: String,
+}
+
+// a list in JSON, but CSV has no lists so there they are separated by ;
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum ImportEmails {
+    List(Vec<String>),
+    Joined(String),
+}
+
+impl Default for ImportEmails {
+    fn default() -> ImportEmails {
+        ImportEmails::List(vec![])
+    }
+}
+
+impl ImportEmails {
+    fn to_vec(&self) -> Vec<String> {
+        let emails: Vec<&str> = match self {
+            ImportEmails::List(emails) => emails.iter().map(|x| x.as_str()).collect(),
+            ImportEmails::Joined(emails) => emails.split(';').collect(),
+        };
+        emails
+            .into_iter()
+            .map(|x| x.trim())
+            .filter(|x| !x.is_empty())
+            .map(|x| x.to_owned())
+            .collect()
+    }
+}
+
+// a record that wasn't imported, and why
+#[derive(Clone, Debug)]
+pub struct Rejection {
+    // counting from 1, not including the CSV header
+    pub record_number: usize,
+    pub username: String,
+    pub reason: String,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ImportReport {
+    // username and the user id it was given
+    pub imported: Vec<(String, i64)>,
+    pub rejected: Vec<Rejection>,
+}
+
+// usernames and emails taken by earlier records in this run
+// a dry run rolls each record back, so the database alone would let two records in the file claim the same one
+#[derive(Clone, Debug, Default)]
+struct Claimed {
+    usernames: HashSet<String>,
+    emails: HashSet<String>,
+}
+
+pub fn read_csv(path: &Path) -> Result<Vec<ImportRecord>, Box<dyn std::error::Error + 'static>> {
+    let mut reader = csv::Reader::from_path(path)?;
+    let mut records = vec![];
+    for record in reader.deserialize() {
+        records.push(record?);
+    }
+    Ok(records)
+}
+
+// a JSON array of records
+pub fn read_json(path: &Path) -> Result<Vec<ImportRecord>, Box<dyn std::error::Error + 'static>> {
+    let contents = std::fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&contents)?)
+}
+
+// Each record is imported in its own transaction, so a bad record is reported and skipped
+// rather than stopping the whole import. Records are checked the same way as at sign up,
+// except for the password policy, since we only have the hash.
+pub async fn import_users(
+    con: &mut tokio_postgres::Client,
+    records: Vec<ImportRecord>,
+    folding_rules: &email_normalization::FoldingRules,
+    token_hasher: &TokenHasher,
+    dry_run: bool,
+) -> Result<ImportReport, tokio_postgres::Error> {
+    let mut report = ImportReport::default();
+    let mut claimed = Claimed::default();
+
+    for (i, record) in records.into_iter().enumerate() {
+        let mut sp = con.transaction().await?;
+
+        match import_user(&mut sp, &record, folding_rules, token_hasher, &mut claimed).await? {
+            Ok(user_id) => {
+                if !dry_run {
+                    sp.commit().await?;
+                }
+                report.imported.push((record.username, user_id));
+            }
+            Err(reason) => report.rejected.push(Rejection {
+                record_number: i + 1,
+                username: record.username,
+                reason,
+            }),
+        }
+    }
+
+    Ok(report)
+}
+
+// returns the new user id, or why the record can't be imported
+// once imported, its username and emails are added to claimed
+async fn import_user(
+    con: &mut tokio_postgres::Transaction<'_>,
+    record: &ImportRecord,
+    folding_rules: &email_normalization::FoldingRules,
+    token_hasher: &TokenHasher,
+    claimed: &mut Claimed,
+) -> Result<Result<i64, String>, tokio_postgres::Error> {
+    if !utils::is_username_valid(&record.username) {
+        return Ok(Err("invalid username".to_owned()));
+    }
+
+    if !utils::is_realname_valid(&record.realname) {
+        return Ok(Err("invalid realname".to_owned()));
+    }
+
+    let country = record.country.clone().filter(|x| !x.is_empty());
+    if let Some(country) = &country {
+        if !utils::is_country_valid(country) {
+            return Ok(Err(format!("invalid country {}", country)));
+        }
+    }
+
+    // a hash that can't be verified would lock the user out
+    if let Err(e) = password_hash_format::check(&record.password_hash) {
+        return Ok(Err(e.to_string()));
+    }
+
+    if claimed.usernames.contains(&record.username)
+        || user_data_service::get_by_username(con, &record.username)
+            .await?
+            .is_some()
+    {
+        return Ok(Err("username taken".to_owned()));
+    }
+
+    let emails: Vec<String> = record
+        .emails
+        .to_vec()
+        .iter()
+        .map(|x| email_normalization::normalize_email(x, folding_rules))
+        .collect();
+
+    for (i, email) in emails.iter().enumerate() {
+        if emails[..i].contains(email) {
+            return Ok(Err(format!("email {} listed twice", email)));
+        }
+
+        if claimed.emails.contains(email) || email_service::exists_by_own_email(con, email).await? {
+            return Ok(Err(format!("email {} taken", email)));
+        }
+    }
+
+    let user = user_service::add(con).await?;
+
+    user_data_service::add(
+        con,
+        user.user_id,
+        record.dateofbirth,
+        record.username.clone(),
+        record.realname.clone(),
+        country,
+    )
+    .await?;
+
+    password_service::add(con, user.user_id, record.password_hash.clone(), None).await?;
+
+    for (i, email) in emails.iter().enumerate() {
+        // each email needs its own verification challenge
+        // the old system verified the address, so the key is never sent to anyone
+        let vc = verification_challenge_service::add(
+            con,
+            token_hasher.hash(&token_hasher.gen_token(TokenKind::VerificationChallenge)),
+            email.clone(),
+            user.user_id,
+            false,
+        )
+        .await?;
+
+        let email = email_service::add(con, vc.verification_challenge_key_hash).await?;
+
+        if i == 0 {
+            email_service::add_primary(con, user.user_id, email.email_id).await?;
+        }
+    }
+
+    claimed.usernames.insert(record.username.clone());
+    claimed.emails.extend(emails);
+
+    Ok(Ok(user.user_id))
+}
@@ -0,0 +1,426 @@
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use super::Data;
+use auth_service_api::request;
+
+use super::account_deletion_service;
+use super::api_key_service;
+use super::consent;
+use super::db_types::*;
+use super::email_domain_policy::DomainPolicy;
+use super::email_outbox_service;
+use super::email_suppression_service;
+use super::handlers;
+use super::mailer::{MailerError, OutgoingEmail};
+use super::notification_preference_service;
+use super::parent_permission_service;
+use super::user_data_service;
+use super::utils;
+use super::verification_challenge_service;
+
+static ONE_HOUR: u64 = 60 * 60 * 1000;
+static THIRTY_SECONDS: u64 = 30 * 1000;
+static FIVE_SECONDS: u64 = 5 * 1000;
+
+// how many queued emails are read from the outbox at once
+static EMAIL_OUTBOX_BATCH: i64 = 50;
+// a failed email is retried after 30 seconds, doubling each time up to an hour
+static EMAIL_RETRY_BASE: i64 = 30 * 1000;
+static EMAIL_RETRY_MAX: i64 = 60 * 60 * 1000;
+
+// periodically purges the accounts whose deletion grace period has run out
+pub async fn purge_deleted_accounts(data: Data) {
+    loop {
+        if let Err(e) = purge_due_accounts(&data).await {
+            log::error!("{}", e);
+        }
+
+        tokio::time::sleep(Duration::from_millis(ONE_HOUR)).await;
+    }
+}
+
+async fn purge_due_accounts(data: &Data) -> Result<(), tokio_postgres::Error> {
+    let con = &mut *data.db.lock().await;
+
+    let account_deletions =
+        account_deletion_service::get_pending_due(con, utils::current_time_millis()).await?;
+
+    for account_deletion in account_deletions.into_iter() {
+        let mut sp = con.transaction().await?;
+
+        account_deletion_service::purge_user(&mut sp, account_deletion.creator_user_id).await?;
+
+        account_deletion_service::add(
+            &mut sp,
+            account_deletion.creator_user_id,
+            request::AccountDeletionKind::Complete,
+            0,
+        )
+        .await?;
+
+        sp.commit().await?;
+
+        log::info!("purged user {}", account_deletion.creator_user_id);
+    }
+
+    Ok(())
+}
+
+// periodically reminds parents whose permission is about to lapse,
+// and downgrades the keys of children whose parent permission has lapsed
+pub async fn expire_parent_permissions(data: Data) {
+    loop {
+        if let Err(e) = remind_parents(&data).await {
+            log::error!("{}", e);
+        }
+
+        if let Err(e) = downgrade_lapsed_children(&data).await {
+            log::error!("{}", e);
+        }
+
+        tokio::time::sleep(Duration::from_millis(ONE_HOUR)).await;
+    }
+}
+
+async fn remind_parents(data: &Data) -> Result<(), tokio_postgres::Error> {
+    let con = &mut *data.db.lock().await;
+
+    let current_time = utils::current_time_millis();
+
+    // grants that will lapse within the reminder window
+    let parent_permissions = parent_permission_service::get_unreminded_grants_before(
+        con,
+        current_time + data.parental_consent_reminder - data.parental_consent_validity,
+    )
+    .await?;
+
+    for parent_permission in parent_permissions.into_iter() {
+        let expiry_time = parent_permission.creation_time + data.parental_consent_validity;
+
+        // record the reminder first, so that a broken mail service doesn't mean a reminder every hour
+        parent_permission_service::add_reminder(con, parent_permission.parent_permission_id)
+            .await?;
+
+        // too late to remind
+        if expiry_time <= current_time {
+            continue;
+        }
+
+        let user_data = match user_data_service::get_by_user_id(
+            con,
+            parent_permission.creator_user_id,
+        )
+        .await?
+        {
+            Some(user_data) => user_data,
+            None => continue,
+        };
+
+        // the child will be old enough by the time the permission lapses
+        if !consent::needs_parent_permission(&user_data, &data.parental_consent_ages, expiry_time) {
+            continue;
+        }
+
+        let vc = match verification_challenge_service::get_by_verification_challenge_key_hash(
+            con,
+            &parent_permission.verification_challenge_key_hash,
+        )
+        .await?
+        {
+            Some(vc) => vc,
+            None => continue,
+        };
+
+        let locale =
+            notification_preference_service::get_by_user_id(con, parent_permission.creator_user_id)
+                .await?
+                .and_then(|x| x.locale);
+
+        // the failure has already been logged
+        let _ = handlers::send_parent_permission_reminder_email(
+            data,
+            con,
+            &vc.email,
+            &user_data.realname,
+            locale.as_deref(),
+            expiry_time,
+        )
+        .await;
+    }
+
+    Ok(())
+}
+
+async fn downgrade_lapsed_children(data: &Data) -> Result<(), tokio_postgres::Error> {
+    let con = &mut *data.db.lock().await;
+
+    let current_time = utils::current_time_millis();
+
+    let parent_permissions = parent_permission_service::get_lapsed_grants_with_valid_api_keys(
+        con,
+        current_time - data.parental_consent_validity,
+        current_time,
+    )
+    .await?;
+
+    for parent_permission in parent_permissions.into_iter() {
+        let user_data = match user_data_service::get_by_user_id(
+            con,
+            parent_permission.creator_user_id,
+        )
+        .await?
+        {
+            Some(user_data) => user_data,
+            None => continue,
+        };
+
+        // children who have grown up don't need permission anymore
+        if !consent::needs_parent_permission(&user_data, &data.parental_consent_ages, current_time)
+        {
+            continue;
+        }
+
+        let mut sp = con.transaction().await?;
+
+        api_key_service::change_current_kind(
+            &mut sp,
+            parent_permission.creator_user_id,
+            request::ApiKeyKind::Valid,
+            request::ApiKeyKind::NoParent,
+            current_time,
+        )
+        .await?;
+
+        sp.commit().await?;
+
+        log::info!(
+            "parent permission for user {} lapsed",
+            parent_permission.creator_user_id
+        );
+    }
+
+    Ok(())
+}
+
+// delivers the emails queued in the outbox through the configured mailer
+// wakes up whenever a request queues an email, and every few seconds otherwise to pick up retries
+pub async fn dispatch_email_outbox(data: Data) {
+    loop {
+        if let Err(e) = dispatch_due_emails(&data).await {
+            log::error!("{}", e);
+        }
+
+        let _ = tokio::time::timeout(
+            Duration::from_millis(FIVE_SECONDS),
+            data.email_outbox_notify.notified(),
+        )
+        .await;
+    }
+}
+
+async fn dispatch_due_emails(data: &Data) -> Result<(), tokio_postgres::Error> {
+    loop {
+        // the db lock isn't held while waiting on the mail service
+        let emails = {
+            let con = &mut *data.db.lock().await;
+
+            // a retry can come after the link in the email has stopped working
+            let expired =
+                email_outbox_service::add_expired_dead_letters(con, utils::current_time_millis())
+                    .await?;
+            if expired > 0 {
+                log::warn!(
+                    "giving up on {} emails that expired before delivery",
+                    expired
+                );
+            }
+
+            email_outbox_service::get_due(con, utils::current_time_millis(), EMAIL_OUTBOX_BATCH)
+                .await?
+        };
+
+        let batch_full = emails.len() as i64 == EMAIL_OUTBOX_BATCH;
+
+        for email in emails.into_iter() {
+            {
+                let con = &mut *data.db.lock().await;
+
+                let suppression =
+                    email_suppression_service::get_by_email(con, &email.destination).await?;
+
+                if is_blocked_by(suppression.map(|x| x.email_suppression_kind), &email.topic) {
+                    log::info!(
+                        "not sending email {} to a suppressed address",
+                        email.email_outbox_id
+                    );
+                    email_outbox_service::add_dead_letter(
+                        con,
+                        email.email_outbox_id,
+                        "address is suppressed",
+                    )
+                    .await?;
+                    continue;
+                }
+            }
+
+            // eg the token hash key it was sealed with has been removed from the file
+            let (content, content_plaintext) = match (
+                data.token_hasher.unseal(&email.content),
+                data.token_hasher.unseal(&email.content_plaintext),
+            ) {
+                (Ok(content), Ok(content_plaintext)) => (content, content_plaintext),
+                (Err(e), _) | (_, Err(e)) => {
+                    log::error!("giving up on email {}: {}", email.email_outbox_id, e);
+                    let con = &mut *data.db.lock().await;
+                    email_outbox_service::add_dead_letter(
+                        con,
+                        email.email_outbox_id,
+                        &e.to_string(),
+                    )
+                    .await?;
+                    continue;
+                }
+            };
+
+            let result = data
+                .mailer
+                .send(&OutgoingEmail {
+                    destination: email.destination.clone(),
+                    topic: email.topic,
+                    title: email.title,
+                    content,
+                    content_plaintext,
+                })
+                .await;
+
+            let con = &mut *data.db.lock().await;
+
+            match result {
+                Ok(_) => email_outbox_service::delete(con, email.email_outbox_id).await?,
+                Err(e) => {
+                    // eg the address will never accept mail, so retrying won't help
+                    let permanent = matches!(e, MailerError::Permanent(_));
+
+                    // later emails to the address would fail the same way
+                    if permanent {
+                        email_suppression_service::add(
+                            con,
+                            email.destination,
+                            EmailSuppressionKind::Bounce,
+                        )
+                        .await?;
+                    }
+
+                    if permanent || email.attempts + 1 >= data.email_outbox_max_attempts {
+                        log::warn!("giving up on email {}: {}", email.email_outbox_id, e);
+                        email_outbox_service::add_dead_letter(
+                            con,
+                            email.email_outbox_id,
+                            &e.to_string(),
+                        )
+                        .await?;
+                    } else {
+                        log::warn!("could not send email {}: {}", email.email_outbox_id, e);
+                        let backoff =
+                            (EMAIL_RETRY_BASE << email.attempts.min(16)).min(EMAIL_RETRY_MAX);
+                        email_outbox_service::add_failure(
+                            con,
+                            email.email_outbox_id,
+                            utils::current_time_millis() + backoff,
+                            &e.to_string(),
+                        )
+                        .await?;
+                    }
+                }
+            }
+        }
+
+        // failed emails are pushed into the future, so this can't keep reading the same ones
+        if !batch_full {
+            return Ok(());
+        }
+    }
+}
+
+// emails aren't sent to addresses that bounced or complained
+// verification emails still get past a bounce, since verifying the address again is how it is cleared
+// a complaint still lets through the emails the address's owner asked for themselves,
+// so they can verify it again or reset their password rather than being locked out
+fn is_blocked_by(suppression_kind: Option<EmailSuppressionKind>, topic: &str) -> bool {
+    match suppression_kind {
+        Some(EmailSuppressionKind::Bounce) => {
+            topic != "verification_challenge" && topic != "parent_permission"
+        }
+        Some(EmailSuppressionKind::Complaint) => {
+            topic != "verification_challenge" && topic != "password_reset"
+        }
+        Some(EmailSuppressionKind::Clear) | None => false,
+    }
+}
+
+// reloads the email domain policy whenever its file is modified
+// if the new file can't be read, the old policy stays in place
+pub async fn reload_email_domain_policy(data: Data, path: PathBuf) {
+    let mut last_modified = modified_time(&path);
+
+    loop {
+        tokio::time::sleep(Duration::from_millis(THIRTY_SECONDS)).await;
+
+        let modified = modified_time(&path);
+        if modified == last_modified {
+            continue;
+        }
+        last_modified = modified;
+
+        // the error isn't Send, so it can't be held across the await below
+        let loaded = DomainPolicy::load(&path).map_err(|e| e.to_string());
+
+        match loaded {
+            Ok(policy) => {
+                *data.email_domain_policy.lock().await = policy;
+                log::info!("reloaded email domain policy from {}", path.display());
+            }
+            Err(e) => log::error!("could not reload {}: {}", path.display(), e),
+        }
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|x| x.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unsuppressed_addresses_get_everything() {
+        for kind in [None, Some(EmailSuppressionKind::Clear)] {
+            assert!(!is_blocked_by(kind, "security_notification"));
+            assert!(!is_blocked_by(kind, "parent_permission_reminder"));
+        }
+    }
+
+    #[test]
+    fn bounced_addresses_only_get_verification() {
+        let kind = Some(EmailSuppressionKind::Bounce);
+        assert!(!is_blocked_by(kind, "verification_challenge"));
+        assert!(!is_blocked_by(kind, "parent_permission"));
+        assert!(is_blocked_by(kind, "password_reset"));
+        assert!(is_blocked_by(kind, "security_notification"));
+    }
+
+    #[test]
+    fn complaining_addresses_can_still_recover_the_account() {
+        let kind = Some(EmailSuppressionKind::Complaint);
+        assert!(!is_blocked_by(kind, "verification_challenge"));
+        assert!(!is_blocked_by(kind, "password_reset"));
+        // asked for by the child, not the parent who complained
+        assert!(is_blocked_by(kind, "parent_permission"));
+        assert!(is_blocked_by(kind, "security_notification"));
+        assert!(is_blocked_by(kind, "email_change"));
+    }
+}
@@ -0,0 +1,122 @@
+use super::db_types::*;
+use tokio_postgres::GenericClient;
+
+impl From<tokio_postgres::row::Row> for NotificationPreference {
+  // select * from notification_preference order only, otherwise it will fail
+  fn from(row: tokio_postgres::row::Row) -> NotificationPreference {
+    NotificationPreference {
+      notification_preference_id: row.get("notification_preference_id"),
+      creation_time: row.get("creation_time"),
+      creator_user_id: row.get("creator_user_id"),
+      security_notifications: row.get("security_notifications"),
+      locale: row.get("locale"),
+    }
+  }
+}
+
+pub async fn add(
+  con: &mut impl GenericClient,
+  creator_user_id: i64,
+  security_notifications: bool,
+  locale: Option<String>,
+) -> Result<NotificationPreference, tokio_postgres::Error> {
+  let row = con
+    .query_one(
+      "INSERT INTO
+       notification_preference_t(
+         creator_user_id,
+         security_notifications,
+         locale
+       )
+       VALUES ($1, $2, $3)
+       RETURNING notification_preference_id, creation_time
+      ",
+      &[&creator_user_id, &security_notifications, &locale],
+    )
+    .await?;
+
+  Ok(NotificationPreference {
+    notification_preference_id: row.get(0),
+    creation_time: row.get(1),
+    creator_user_id,
+    security_notifications,
+    locale,
+  })
+}
+
+// gets most recent notification preference by user_id
+pub async fn get_by_user_id(
+  con: &mut impl GenericClient,
+  user_id: i64,
+) -> Result<Option<NotificationPreference>, tokio_postgres::Error> {
+  let result = con
+    .query_opt(
+      "SELECT np.* FROM recent_notification_preference_v np
+       WHERE np.creator_user_id = $1
+      ",
+      &[&user_id],
+    )
+    .await?
+    .map(|x| x.into());
+
+  Ok(result)
+}
+
+// gets every version of the notification preference by user_id
+pub async fn get_all_by_user_id(
+  con: &mut impl GenericClient,
+  user_id: i64,
+) -> Result<Vec<NotificationPreference>, tokio_postgres::Error> {
+  let results = con
+    .query(
+      "SELECT np.* FROM notification_preference_t np
+       WHERE np.creator_user_id = $1
+       ORDER BY np.notification_preference_id
+      ",
+      &[&user_id],
+    )
+    .await?
+    .into_iter()
+    .map(|x| x.into())
+    .collect();
+
+  Ok(results)
+}
+
+pub async fn query(
+  con: &mut impl GenericClient,
+  props: auth_service_api::request::NotificationPreferenceViewProps,
+) -> Result<Vec<NotificationPreference>, tokio_postgres::Error> {
+  let sql = [
+    if props.only_recent {
+      "SELECT np.* FROM recent_notification_preference_v np"
+    } else {
+      "SELECT np.* FROM notification_preference_t np"
+    },
+    " WHERE 1 = 1",
+    " AND ($1::bigint[] IS NULL OR np.notification_preference_id = ANY($1))",
+    " AND ($2::bigint   IS NULL OR np.creation_time >= $2)",
+    " AND ($3::bigint   IS NULL OR np.creation_time <= $3)",
+    " AND ($4::bigint[] IS NULL OR np.creator_user_id = ANY($4))",
+    " ORDER BY np.notification_preference_id",
+  ]
+  .join("\n");
+
+  let stmnt = con.prepare(&sql).await?;
+
+  let results = con
+    .query(
+      &stmnt,
+      &[
+        &props.notification_preference_id,
+        &props.min_creation_time,
+        &props.max_creation_time,
+        &props.creator_user_id,
+      ],
+    )
+    .await?
+    .into_iter()
+    .map(|row| row.into())
+    .collect();
+  Ok(results)
+}
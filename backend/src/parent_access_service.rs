@@ -0,0 +1,83 @@
+use super::db_types::*;
+use tokio_postgres::GenericClient;
+
+impl From<tokio_postgres::row::Row> for ParentAccess {
+  // select * from parent_access order only, otherwise it will fail
+  fn from(row: tokio_postgres::row::Row) -> ParentAccess {
+    ParentAccess {
+      parent_access_key_hash: row.get("parent_access_key_hash"),
+      creation_time: row.get("creation_time"),
+      expiry_time: row.get("expiry_time"),
+      email: row.get("email"),
+    }
+  }
+}
+
+pub async fn add(
+  con: &mut impl GenericClient,
+  parent_access_key_hash: String,
+  expiry_time: i64,
+  email: String,
+) -> Result<ParentAccess, tokio_postgres::Error> {
+  let row = con
+    .query_one(
+      "INSERT INTO
+       parent_access_t(
+         parent_access_key_hash,
+         expiry_time,
+         email
+       )
+       VALUES ($1, $2, $3)
+       RETURNING creation_time
+      ",
+      &[&parent_access_key_hash, &expiry_time, &email],
+    )
+    .await?;
+
+  Ok(ParentAccess {
+    parent_access_key_hash,
+    creation_time: row.get(0),
+    expiry_time,
+    email,
+  })
+}
+
+// the hashes are every hash the key may have been stored under, see token_hasher.rs
+pub async fn get_by_parent_access_key_hashes(
+  con: &mut impl GenericClient,
+  parent_access_key_hashes: &[String],
+) -> Result<Option<ParentAccess>, tokio_postgres::Error> {
+  let result = con
+    .query_opt(
+      "SELECT * FROM parent_access_t WHERE parent_access_key_hash=ANY($1)",
+      &[&parent_access_key_hashes],
+    )
+    .await?
+    .map(|row| row.into());
+
+  Ok(result)
+}
+
+pub async fn get_num_by_email_between(
+  con: &mut impl GenericClient,
+  email: &str,
+  min_time: i64,
+  max_time: i64,
+) -> Result<i64, tokio_postgres::Error> {
+  let count = con
+    .query_one(
+      "
+      SELECT COUNT(*)
+      FROM parent_access_t
+      WHERE 1 = 1
+      AND lower(email)=lower($1)
+      AND creation_time >= $2
+      AND creation_time <= $3
+      ",
+      &[&email, &min_time, &max_time],
+    )
+    .await?
+    .get(0);
+
+  Ok(count)
+}
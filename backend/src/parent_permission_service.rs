@@ -0,0 +1,239 @@
+use super::db_types::*;
+use auth_service_api::request::ParentPermissionKind;
+use std::convert::TryInto;
+use tokio_postgres::GenericClient;
+
+impl From<tokio_postgres::row::Row> for ParentPermission {
+  // select * from parent_permission order only, otherwise it will fail
+  fn from(row: tokio_postgres::row::Row) -> ParentPermission {
+    ParentPermission {
+      parent_permission_id: row.get("parent_permission_id"),
+      creation_time: row.get("creation_time"),
+      creator_user_id: row.get("creator_user_id"),
+      verification_challenge_key_hash: row.get("verification_challenge_key_hash"),
+      // means that there's a mismatch between the values of the enum and the value stored in the column
+      parent_permission_kind: (row.get::<&str, i64>("parent_permission_kind") as u8)
+        .try_into()
+        .unwrap(),
+      terms_version: row.get("terms_version"),
+    }
+  }
+}
+
+pub async fn add(
+  con: &mut impl GenericClient,
+  creator_user_id: i64,
+  verification_challenge_key_hash: String,
+  parent_permission_kind: ParentPermissionKind,
+  terms_version: Option<String>,
+) -> Result<ParentPermission, tokio_postgres::Error> {
+  let row = con
+    .query_one(
+      "INSERT INTO
+       parent_permission_t(
+         creator_user_id,
+         verification_challenge_key_hash,
+         parent_permission_kind,
+         terms_version
+       )
+       VALUES ($1, $2, $3, $4)
+       RETURNING parent_permission_id, creation_time
+      ",
+      &[
+        &creator_user_id,
+        &verification_challenge_key_hash,
+        &(parent_permission_kind.clone() as i64),
+        &terms_version,
+      ],
+    )
+    .await?;
+
+  Ok(ParentPermission {
+    parent_permission_id: row.get(0),
+    creation_time: row.get(1),
+    creator_user_id,
+    verification_challenge_key_hash,
+    parent_permission_kind,
+    terms_version,
+  })
+}
+
+// gets most recent parent permission by user_id (of the child)
+pub async fn get_by_user_id(
+  con: &mut impl GenericClient,
+  user_id: i64,
+) -> Result<Option<ParentPermission>, tokio_postgres::Error> {
+  let result = con
+    .query_opt(
+      "SELECT pp.* FROM recent_parent_permission_v pp
+       WHERE pp.creator_user_id = $1
+      ",
+      &[&user_id],
+    )
+    .await?
+    .map(|x| x.into());
+
+  Ok(result)
+}
+
+// gets the most recent parent permission of every child whose current parent email is this one
+pub async fn get_by_parent_email(
+  con: &mut impl GenericClient,
+  email: &str,
+) -> Result<Vec<ParentPermission>, tokio_postgres::Error> {
+  let results = con
+    .query(
+      "SELECT pp.* FROM recent_parent_permission_v pp
+       JOIN verification_challenge_t vc USING(verification_challenge_key_hash)
+       WHERE lower(vc.email) = lower($1)
+       ORDER BY pp.creator_user_id
+      ",
+      &[&email],
+    )
+    .await?
+    .into_iter()
+    .map(|x| x.into())
+    .collect();
+
+  Ok(results)
+}
+
+// gets the current grants made before `max_creation_time` that haven't had a reminder sent yet
+pub async fn get_unreminded_grants_before(
+  con: &mut impl GenericClient,
+  max_creation_time: i64,
+) -> Result<Vec<ParentPermission>, tokio_postgres::Error> {
+  let results = con
+    .query(
+      "SELECT pp.* FROM recent_parent_permission_v pp
+       WHERE pp.parent_permission_kind = $1
+       AND pp.creation_time <= $2
+       AND NOT EXISTS (
+         SELECT 1 FROM parent_permission_reminder_t ppr
+         WHERE ppr.parent_permission_id = pp.parent_permission_id
+       )
+       ORDER BY pp.parent_permission_id
+      ",
+      &[&(ParentPermissionKind::Grant as i64), &max_creation_time],
+    )
+    .await?
+    .into_iter()
+    .map(|x| x.into())
+    .collect();
+
+  Ok(results)
+}
+
+// gets the current grants made before `max_creation_time` whose child still has valid api keys
+pub async fn get_lapsed_grants_with_valid_api_keys(
+  con: &mut impl GenericClient,
+  max_creation_time: i64,
+  current_time: i64,
+) -> Result<Vec<ParentPermission>, tokio_postgres::Error> {
+  let results = con
+    .query(
+      "SELECT pp.* FROM recent_parent_permission_v pp
+       WHERE pp.parent_permission_kind = $1
+       AND pp.creation_time <= $2
+       AND EXISTS (
+         SELECT 1 FROM recent_api_key_v ak
+         WHERE ak.creator_user_id = pp.creator_user_id
+         AND ak.api_key_kind = $3
+         AND ak.creation_time + ak.duration > $4
+       )
+       ORDER BY pp.parent_permission_id
+      ",
+      &[
+        &(ParentPermissionKind::Grant as i64),
+        &max_creation_time,
+        &(auth_service_api::request::ApiKeyKind::Valid as i64),
+        &current_time,
+      ],
+    )
+    .await?
+    .into_iter()
+    .map(|x| x.into())
+    .collect();
+
+  Ok(results)
+}
+
+pub async fn add_reminder(
+  con: &mut impl GenericClient,
+  parent_permission_id: i64,
+) -> Result<(), tokio_postgres::Error> {
+  con
+    .execute(
+      "INSERT INTO parent_permission_reminder_t(parent_permission_id) VALUES ($1)",
+      &[&parent_permission_id],
+    )
+    .await?;
+
+  Ok(())
+}
+
+// gets every parent permission granted or revoked for the user
+pub async fn get_all_by_user_id(
+  con: &mut impl GenericClient,
+  user_id: i64,
+) -> Result<Vec<ParentPermission>, tokio_postgres::Error> {
+  let results = con
+    .query(
+      "SELECT pp.* FROM parent_permission_t pp
+       WHERE pp.creator_user_id = $1
+       ORDER BY pp.parent_permission_id
+      ",
+      &[&user_id],
+    )
+    .await?
+    .into_iter()
+    .map(|x| x.into())
+    .collect();
+
+  Ok(results)
+}
+
+pub async fn query(
+  con: &mut impl GenericClient,
+  props: auth_service_api::request::ParentPermissionViewProps,
+) -> Result<Vec<ParentPermission>, tokio_postgres::Error> {
+  let sql = [
+    if props.only_recent {
+      "SELECT pp.* FROM recent_parent_permission_v pp"
+    } else {
+      "SELECT pp.* FROM parent_permission_t pp"
+    },
+    " JOIN verification_challenge_t vc USING(verification_challenge_key_hash)",
+    " WHERE 1 = 1",
+    " AND ($1::bigint[] IS NULL OR pp.parent_permission_id = ANY($1))",
+    " AND ($2::bigint   IS NULL OR pp.creation_time >= $2)",
+    " AND ($3::bigint   IS NULL OR pp.creation_time <= $3)",
+    " AND ($4::bigint[] IS NULL OR pp.creator_user_id = ANY($4))",
+    " AND ($5::text[]   IS NULL OR vc.email = ANY($5))",
+    " AND ($6::bigint[] IS NULL OR pp.parent_permission_kind = ANY($6))",
+    " ORDER BY pp.parent_permission_id",
+  ]
+  .join("\n");
+
+  let stmnt = con.prepare(&sql).await?;
+
+  let results = con
+    .query(
+      &stmnt,
+      &[
+        &props.parent_permission_id,
+        &props.min_creation_time,
+        &props.max_creation_time,
+        &props.creator_user_id,
+        &props.email,
+        &props
+          .parent_permission_kind
+          .map(|x| x.into_iter().map(|e| e as i64).collect::<Vec<i64>>()),
+      ],
+    )
+    .await?
+    .into_iter()
+    .map(|row| row.into())
+    .collect();
+  Ok(results)
+}
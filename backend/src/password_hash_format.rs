@@ -0,0 +1,463 @@
+use sha2::{Sha256, Sha512};
+
+// Password hashes are argon2, but users imported from older systems (see import.rs) keep the hash they had there
+// until they next log in, when it is replaced with an argon2 one. These are the formats that can be imported:
+//   bcrypt: $2a$, $2b$ or $2y$ modular crypt, eg $2b$12$<22 character salt><31 character hash>
+//   PBKDF2: PHC strings, eg $pbkdf2-sha256$i=600000,l=32$<salt>$<hash> or passlib's $pbkdf2-sha512$25000$<salt>$<hash>,
+//           and Django's pbkdf2_sha256$<iterations>$<salt>$<hash>
+//   scrypt: PHC strings, eg $scrypt$ln=16,r=8,p=1$<salt>$<hash>
+// Imported hashes are verified while a login holds the database lock, so their cost is capped:
+// well above what these formats use by default, but low enough that a crafted hash can't stall the server.
+static MAX_BCRYPT_COST: u32 = 14;
+static MAX_PBKDF2_ITERATIONS: u32 = 2_000_000;
+// scrypt needs 128 * r * 2^ln bytes, and p times that much work
+static MAX_SCRYPT_MEMORY: u64 = 128 * 1024 * 1024;
+static MAX_SCRYPT_WORK: u64 = 2 * MAX_SCRYPT_MEMORY;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HashFormat {
+  Argon2,
+  Bcrypt,
+  Pbkdf2,
+  Scrypt,
+}
+
+impl HashFormat {
+  // from the prefix alone, without checking the rest of the hash
+  pub fn of(password_hash: &str) -> Option<HashFormat> {
+    if password_hash.starts_with("$argon2") {
+      Some(HashFormat::Argon2)
+    } else if ["$2a$", "$2b$", "$2y$"]
+      .iter()
+      .any(|x| password_hash.starts_with(x))
+    {
+      Some(HashFormat::Bcrypt)
+    } else if ["$pbkdf2-sha256$", "$pbkdf2-sha512$", "pbkdf2_sha256$"]
+      .iter()
+      .any(|x| password_hash.starts_with(x))
+    {
+      Some(HashFormat::Pbkdf2)
+    } else if password_hash.starts_with("$scrypt$") {
+      Some(HashFormat::Scrypt)
+    } else {
+      None
+    }
+  }
+}
+
+impl std::fmt::Display for HashFormat {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      HashFormat::Argon2 => write!(f, "argon2"),
+      HashFormat::Bcrypt => write!(f, "bcrypt"),
+      HashFormat::Pbkdf2 => write!(f, "PBKDF2"),
+      HashFormat::Scrypt => write!(f, "scrypt"),
+    }
+  }
+}
+
+#[derive(Debug)]
+pub enum VerifyError {
+  Argon2(argon2::Error),
+  // the prefix says which format it is, but the rest of the hash doesn't fit it
+  Malformed(HashFormat),
+  // the hash's parameters would take too long, or too much memory, to verify
+  TooCostly(HashFormat),
+  UnknownFormat,
+}
+
+impl std::fmt::Display for VerifyError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      VerifyError::Argon2(e) => write!(f, "argon2 error: {}", e),
+      VerifyError::Malformed(format) => write!(f, "malformed {} hash", format),
+      VerifyError::TooCostly(format) => write!(f, "{} hash is too costly to verify", format),
+      VerifyError::UnknownFormat => write!(f, "unknown password hash format"),
+    }
+  }
+}
+
+impl std::error::Error for VerifyError {}
+
+impl From<argon2::Error> for VerifyError {
+  fn from(e: argon2::Error) -> VerifyError {
+    VerifyError::Argon2(e)
+  }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Pbkdf2Digest {
+  Sha256,
+  Sha512,
+}
+
+// a hash in one of the formats imported from older systems
+enum LegacyHash<'a> {
+  Bcrypt {
+    hash: &'a str,
+    cost: u32,
+  },
+  Pbkdf2 {
+    digest: Pbkdf2Digest,
+    iterations: u32,
+    salt: Vec<u8>,
+    hash: Vec<u8>,
+  },
+  Scrypt {
+    log_n: u8,
+    r: u32,
+    p: u32,
+    salt: Vec<u8>,
+    hash: Vec<u8>,
+  },
+}
+
+// checks that a hash is in a format that can be verified, without needing the password
+pub fn check(password_hash: &str) -> Result<HashFormat, VerifyError> {
+  let format = HashFormat::of(password_hash).ok_or(VerifyError::UnknownFormat)?;
+  match format {
+    HashFormat::Argon2 => {
+      // the argon2 crate only parses a hash while verifying it
+      argon2::verify_encoded(password_hash, b"")?;
+    }
+    _ => {
+      let legacy_hash = parse_legacy(password_hash).ok_or(VerifyError::Malformed(format))?;
+      if !is_affordable(&legacy_hash) {
+        return Err(VerifyError::TooCostly(format));
+      }
+    }
+  }
+  Ok(format)
+}
+
+// verifies a password against a hash in any format but argon2, which utils::verify_password handles itself
+pub fn verify_legacy(password: &str, password_hash: &str) -> Result<bool, VerifyError> {
+  let format = HashFormat::of(password_hash).ok_or(VerifyError::UnknownFormat)?;
+  let legacy_hash = parse_legacy(password_hash).ok_or(VerifyError::Malformed(format))?;
+
+  // checked again here, in case the hash got into the database some other way than an import
+  if !is_affordable(&legacy_hash) {
+    return Err(VerifyError::TooCostly(format));
+  }
+
+  match legacy_hash {
+    LegacyHash::Bcrypt { hash, .. } => {
+      bcrypt::verify(password, hash).map_err(|_| VerifyError::Malformed(format))
+    }
+    LegacyHash::Pbkdf2 {
+      digest,
+      iterations,
+      salt,
+      hash,
+    } => {
+      let mut result = vec![0; hash.len()];
+      match digest {
+        Pbkdf2Digest::Sha256 => {
+          pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), &salt, iterations, &mut result)
+        }
+        Pbkdf2Digest::Sha512 => {
+          pbkdf2::pbkdf2_hmac::<Sha512>(password.as_bytes(), &salt, iterations, &mut result)
+        }
+      }
+      Ok(constant_time_eq(&result, &hash))
+    }
+    LegacyHash::Scrypt {
+      log_n,
+      r,
+      p,
+      salt,
+      hash,
+    } => {
+      let params =
+        scrypt::Params::new(log_n, r, p, hash.len()).map_err(|_| VerifyError::Malformed(format))?;
+      let mut result = vec![0; hash.len()];
+      scrypt::scrypt(password.as_bytes(), &salt, &params, &mut result)
+        .map_err(|_| VerifyError::Malformed(format))?;
+      Ok(constant_time_eq(&result, &hash))
+    }
+  }
+}
+
+fn parse_legacy(password_hash: &str) -> Option<LegacyHash<'_>> {
+  match HashFormat::of(password_hash)? {
+    HashFormat::Argon2 => None,
+    HashFormat::Bcrypt => {
+      // parsing checks the cost, salt and hash lengths
+      let parts = password_hash.parse::<bcrypt::HashParts>().ok()?;
+      Some(LegacyHash::Bcrypt {
+        hash: password_hash,
+        cost: parts.get_cost(),
+      })
+    }
+    HashFormat::Pbkdf2 => parse_pbkdf2(password_hash),
+    HashFormat::Scrypt => parse_scrypt(password_hash),
+  }
+}
+
+fn is_affordable(legacy_hash: &LegacyHash) -> bool {
+  match legacy_hash {
+    LegacyHash::Bcrypt { cost, .. } => *cost <= MAX_BCRYPT_COST,
+    LegacyHash::Pbkdf2 { iterations, .. } => *iterations <= MAX_PBKDF2_ITERATIONS,
+    LegacyHash::Scrypt { log_n, r, p, .. } => {
+      let memory = 128u64
+        .saturating_mul(*r as u64)
+        .saturating_mul(1u64.checked_shl(*log_n as u32).unwrap_or(u64::MAX));
+      memory <= MAX_SCRYPT_MEMORY && memory.saturating_mul(*p as u64) <= MAX_SCRYPT_WORK
+    }
+  }
+}
+
+fn parse_pbkdf2(password_hash: &str) -> Option<LegacyHash<'_>> {
+  // Django keeps the salt as text, and pads the hash
+  if let Some(rest) = password_hash.strip_prefix("pbkdf2_sha256$") {
+    let mut parts = rest.split('$');
+    let iterations = parts.next()?.parse().ok()?;
+    let salt = parts.next()?.as_bytes().to_vec();
+    let hash = decode_b64(parts.next()?)?;
+    return pbkdf2_hash(Pbkdf2Digest::Sha256, iterations, salt, hash, parts.next());
+  }
+
+  let mut parts = password_hash.strip_prefix('$')?.split('$');
+  let digest = match parts.next()? {
+    "pbkdf2-sha256" => Pbkdf2Digest::Sha256,
+    "pbkdf2-sha512" => Pbkdf2Digest::Sha512,
+    _ => return None,
+  };
+
+  // passlib writes just the iteration count, the PHC string format writes i=<iterations>,l=<length>
+  let params = parts.next()?;
+  let iterations = match params.parse() {
+    Ok(iterations) => iterations,
+    Err(_) => params
+      .split(',')
+      .find_map(|x| x.strip_prefix("i="))?
+      .parse()
+      .ok()?,
+  };
+
+  let salt = decode_b64(parts.next()?)?;
+  let hash = decode_b64(parts.next()?)?;
+  pbkdf2_hash(digest, iterations, salt, hash, parts.next())
+}
+
+fn pbkdf2_hash(
+  digest: Pbkdf2Digest,
+  iterations: u32,
+  salt: Vec<u8>,
+  hash: Vec<u8>,
+  extra: Option<&str>,
+) -> Option<LegacyHash<'static>> {
+  if extra.is_some() || iterations == 0 || salt.is_empty() || hash.is_empty() || hash.len() > 64 {
+    return None;
+  }
+  Some(LegacyHash::Pbkdf2 {
+    digest,
+    iterations,
+    salt,
+    hash,
+  })
+}
+
+fn parse_scrypt(password_hash: &str) -> Option<LegacyHash<'_>> {
+  let mut parts = password_hash.strip_prefix("$scrypt$")?.split('$');
+
+  let mut log_n = None;
+  let mut r = None;
+  let mut p = None;
+  for param in parts.next()?.split(',') {
+    match param.split_once('=')? {
+      ("ln", x) => log_n = Some(x.parse().ok()?),
+      ("r", x) => r = Some(x.parse().ok()?),
+      ("p", x) => p = Some(x.parse().ok()?),
+      _ => return None,
+    }
+  }
+
+  let (log_n, r, p) = (log_n?, r?, p?);
+  let salt = decode_b64(parts.next()?)?;
+  let hash = decode_b64(parts.next()?)?;
+  if parts.next().is_some() || salt.is_empty() || hash.is_empty() || hash.len() > 64 {
+    return None;
+  }
+
+  // rejects parameters scrypt can't run with
+  scrypt::Params::new(log_n, r, p, hash.len()).ok()?;
+
+  Some(LegacyHash::Scrypt {
+    log_n,
+    r,
+    p,
+    salt,
+    hash,
+  })
+}
+
+// PHC strings use standard base64 without padding, passlib uses . in place of +, and Django pads
+fn decode_b64(encoded: &str) -> Option<Vec<u8>> {
+  let encoded: String = encoded
+    .trim_end_matches('=')
+    .chars()
+    .map(|x| match x {
+      '+' | '.' => '-',
+      '/' => '_',
+      x => x,
+    })
+    .collect();
+  base64_url::decode(&encoded).ok()
+}
+
+// so the time taken doesn't say how much of the hash matched
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+  a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // each made by another implementation, for the password next to it
+  static KNOWN_ANSWERS: &[(&str, &str)] = &[
+    // OpenBSD's bcrypt test vectors
+    ("U*U", "$2a$05$CCCCCCCCCCCCCCCCCCCCC.E5YPO9kmyuRGyh0XouQYb4YMJKvyOeW"),
+    ("U*U", "$2b$05$CCCCCCCCCCCCCCCCCCCCC.E5YPO9kmyuRGyh0XouQYb4YMJKvyOeW"),
+    // passlib's pbkdf2_sha256.hash
+    (
+      "password",
+      "$pbkdf2-sha256$29000$N2YMIWQsBWBMae09x1jrPQ$lEjTD4tvq5SOB5ctjdSxvnbzU5PQEfMComvCIxNEqq8",
+    ),
+    // the rest from python's hashlib.pbkdf2_hmac and hashlib.scrypt, written out in each format
+    (
+      "correct horse",
+      "$pbkdf2-sha256$i=1000,l=32$c2FsdHlzYWx0eXNhbHQxNg$tVHYgLKarEClaRz7w0RMAtin5IJB7svUzVCB4M81cbM",
+    ),
+    (
+      "correct horse",
+      "$pbkdf2-sha512$1000$c2FsdHlzYWx0eXNhbHQxNg$UjSaNrJhmzWrWHfoM1kUUX1kGmXfKZ3FdbPOMviam9KOl3h0N.agdlVV5tc01daocZWH5J5Kiopil1PGNeoa.Q",
+    ),
+    (
+      "correct horse",
+      "pbkdf2_sha256$1000$djangosalt1234$UfDR5RgZl5jkin0YUDEyMNydGT0p4FMt6yZ3u0MKhHc=",
+    ),
+    (
+      "correct horse",
+      "$scrypt$ln=10,r=8,p=1$c2FsdHlzYWx0eXNhbHQxNg$bBI0d0kfuecuWBNXmgpJOh+0Za6lYEPiFOBNan4pwZ0",
+    ),
+  ];
+
+  #[test]
+  fn verifies_known_answers() {
+    for (password, password_hash) in KNOWN_ANSWERS {
+      assert!(
+        check(password_hash).is_ok(),
+        "{} failed the check",
+        password_hash
+      );
+      assert!(
+        verify_legacy(password, password_hash).unwrap(),
+        "{} didn't verify",
+        password_hash
+      );
+      assert!(
+        !verify_legacy("wrong password", password_hash).unwrap(),
+        "{} verified the wrong password",
+        password_hash
+      );
+    }
+  }
+
+  #[test]
+  fn tells_formats_apart() {
+    let formats: Vec<_> = KNOWN_ANSWERS
+      .iter()
+      .map(|(_, x)| HashFormat::of(x).unwrap())
+      .collect();
+    assert_eq!(
+      formats,
+      [
+        HashFormat::Bcrypt,
+        HashFormat::Bcrypt,
+        HashFormat::Pbkdf2,
+        HashFormat::Pbkdf2,
+        HashFormat::Pbkdf2,
+        HashFormat::Pbkdf2,
+        HashFormat::Scrypt,
+      ]
+    );
+    assert_eq!(
+      HashFormat::of("$argon2i$v=19$m=4096,t=3,p=1$c2FsdA$aGFzaA"),
+      Some(HashFormat::Argon2)
+    );
+    assert_eq!(HashFormat::of("$1$saltsalt$hash"), None);
+    assert_eq!(HashFormat::of("5f4dcc3b5aa765d61d8327deb882cf99"), None);
+  }
+
+  #[test]
+  fn rejects_malformed_hashes() {
+    for password_hash in [
+      // bcrypt hash cut short
+      "$2b$05$CCCCCCCCCCCCCCCCCCCCC.E5YPO9kmyuRGyh0XouQYb4YMJKvy",
+      // no iterations
+      "$pbkdf2-sha256$l=32$c2FsdA$aGFzaA",
+      "$pbkdf2-sha256$0$c2FsdA$aGFzaA",
+      // no hash, or something after it
+      "$pbkdf2-sha256$1000$c2FsdA",
+      "$pbkdf2-sha256$1000$c2FsdA$aGFzaA$extra",
+      "pbkdf2_sha256$1000$salt$!!!",
+      // scrypt with a missing or unusable parameter
+      "$scrypt$ln=10,r=8$c2FsdA$aGFzaA",
+      "$scrypt$ln=99,r=8,p=1$c2FsdA$aGFzaA",
+      "$scrypt$ln=10,r=8,p=1,x=1$c2FsdA$aGFzaA",
+    ] {
+      assert!(
+        matches!(check(password_hash), Err(VerifyError::Malformed(_))),
+        "{} passed the check",
+        password_hash
+      );
+    }
+
+    assert!(matches!(
+      check("plaintext"),
+      Err(VerifyError::UnknownFormat)
+    ));
+  }
+
+  #[test]
+  fn refuses_costly_hashes() {
+    for password_hash in [
+      "$2b$31$CCCCCCCCCCCCCCCCCCCCC.E5YPO9kmyuRGyh0XouQYb4YMJKvyOeW",
+      "$pbkdf2-sha256$1000000000$c2FsdA$aGFzaA",
+      "$pbkdf2-sha512$i=4000000,l=64$c2FsdA$aGFzaA",
+      // a gigabyte
+      "$scrypt$ln=20,r=8,p=1$c2FsdA$AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8",
+      // affordable memory, but too much work
+      "$scrypt$ln=17,r=8,p=4$c2FsdA$AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8",
+    ] {
+      assert!(
+        matches!(check(password_hash), Err(VerifyError::TooCostly(_))),
+        "{} passed the check",
+        password_hash
+      );
+      assert!(
+        matches!(
+          verify_legacy("password", password_hash),
+          Err(VerifyError::TooCostly(_))
+        ),
+        "{} was verified",
+        password_hash
+      );
+    }
+
+    // the usual parameters are fine
+    for password_hash in [
+      "$2b$12$CCCCCCCCCCCCCCCCCCCCC.E5YPO9kmyuRGyh0XouQYb4YMJKvyOeW",
+      "pbkdf2_sha256$1000000$salt$aGFzaA",
+      "$scrypt$ln=17,r=8,p=1$c2FsdA$AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8",
+    ] {
+      assert!(
+        check(password_hash).is_ok(),
+        "{} failed the check",
+        password_hash
+      );
+    }
+  }
+}
@@ -0,0 +1,509 @@
+use auth_service_api::response::PasswordPolicyViolation;
+use std::collections::HashMap;
+
+use super::breached_password_filter::BreachedPasswordFilter;
+
+// the most common passwords and password words, most common first
+// the rank is used as the number of guesses an attacker needs to reach the word
+static COMMON_PASSWORDS: [&str; 100] = [
+  "password",
+  "123456",
+  "qwerty",
+  "letmein",
+  "welcome",
+  "monkey",
+  "dragon",
+  "football",
+  "iloveyou",
+  "admin",
+  "baseball",
+  "master",
+  "sunshine",
+  "princess",
+  "shadow",
+  "superman",
+  "michael",
+  "abc123",
+  "trustno1",
+  "starwars",
+  "batman",
+  "login",
+  "passw0rd",
+  "hello",
+  "freedom",
+  "whatever",
+  "charlie",
+  "jordan",
+  "jennifer",
+  "hunter",
+  "buster",
+  "soccer",
+  "harley",
+  "ranger",
+  "thomas",
+  "robert",
+  "tigger",
+  "hockey",
+  "killer",
+  "george",
+  "andrew",
+  "michelle",
+  "jessica",
+  "pepper",
+  "daniel",
+  "access",
+  "joshua",
+  "maggie",
+  "ginger",
+  "summer",
+  "ashley",
+  "cookie",
+  "secret",
+  "mustang",
+  "flower",
+  "matrix",
+  "computer",
+  "internet",
+  "cheese",
+  "orange",
+  "banana",
+  "chocolate",
+  "purple",
+  "yellow",
+  "silver",
+  "google",
+  "samsung",
+  "pokemon",
+  "minecraft",
+  "naruto",
+  "school",
+  "student",
+  "teacher",
+  "family",
+  "friend",
+  "forever",
+  "lovely",
+  "angel",
+  "blessed",
+  "jesus",
+  "hannah",
+  "nicole",
+  "thunder",
+  "taylor",
+  "matthew",
+  "anthony",
+  "william",
+  "corvette",
+  "mercedes",
+  "diamond",
+  "london",
+  "america",
+  "canada",
+  "spring",
+  "winter",
+  "autumn",
+  "changeme",
+  "default",
+  "test",
+  "guest",
+];
+
+// rows of a qwerty keyboard, since walking along them is a common pattern
+static KEYBOARD_ROWS: [&str; 4] = ["1234567890", "qwertyuiop", "asdfghjkl", "zxcvbnm"];
+
+// each character not covered by a pattern is assumed to take this many guesses, as in zxcvbn
+static BRUTEFORCE_CARDINALITY: f64 = 10.0;
+
+// log10 guesses needed to reach each score above 0, the same thresholds zxcvbn uses
+static SCORE_THRESHOLDS: [f64; 4] = [3.0, 6.0, 8.0, 10.0];
+
+// what a password is checked against, set with the --password-* options
+#[derive(Clone, Debug)]
+pub struct PasswordPolicy {
+  // in characters, not bytes
+  pub min_length: usize,
+  pub max_length: usize,
+  // 0 (anything) to 4 (very hard to guess)
+  pub min_score: u8,
+  // passwords known from public breaches, set with --breached-password-filter
+  pub breached_passwords: Option<BreachedPasswordFilter>,
+  common_passwords: HashMap<&'static str, usize>,
+}
+
+// what we know about the user, which shouldn't appear in their password
+pub struct PersonalInfo<'a> {
+  pub username: &'a str,
+  pub realname: &'a str,
+  pub email: Option<&'a str>,
+}
+
+impl PasswordPolicy {
+  pub fn new(
+    min_length: usize,
+    max_length: usize,
+    min_score: u8,
+    breached_passwords: Option<BreachedPasswordFilter>,
+  ) -> PasswordPolicy {
+    PasswordPolicy {
+      min_length,
+      max_length,
+      min_score,
+      breached_passwords,
+      common_passwords: COMMON_PASSWORDS
+        .iter()
+        .enumerate()
+        .map(|(rank, x)| (*x, rank + 1))
+        .collect(),
+    }
+  }
+
+  // returns every reason the password isn't allowed, so they can all be fixed at once
+  pub fn check(
+    &self,
+    password: &str,
+    personal_info: &PersonalInfo,
+  ) -> Vec<PasswordPolicyViolation> {
+    let mut violations = vec![];
+
+    let len = password.chars().count();
+    if len < self.min_length {
+      violations.push(PasswordPolicyViolation::TooShort {
+        min_length: self.min_length as i64,
+      });
+    }
+    if len > self.max_length {
+      violations.push(PasswordPolicyViolation::TooLong {
+        max_length: self.max_length as i64,
+      });
+      // don't spend time estimating something that will be rejected anyway
+      return violations;
+    }
+
+    let lowercase = password.to_lowercase();
+
+    if contains_token(&lowercase, personal_info.username) {
+      violations.push(PasswordPolicyViolation::ContainsUsername);
+    }
+    if personal_info
+      .realname
+      .split_whitespace()
+      .any(|x| contains_token(&lowercase, x))
+    {
+      violations.push(PasswordPolicyViolation::ContainsRealname);
+    }
+    if let Some(email) = personal_info.email {
+      let local_part = email.split('@').next().unwrap_or(email);
+      if contains_token(&lowercase, local_part) {
+        violations.push(PasswordPolicyViolation::ContainsEmail);
+      }
+    }
+
+    if let Some(breached_passwords) = &self.breached_passwords {
+      if breached_passwords.contains(password) {
+        violations.push(PasswordPolicyViolation::Breached);
+      }
+    }
+
+    let score = self.score(password);
+    if score < self.min_score {
+      violations.push(PasswordPolicyViolation::TooGuessable {
+        score: score as i64,
+        min_score: self.min_score as i64,
+      });
+    }
+
+    violations
+  }
+
+  // 0 to 4, from the estimated number of guesses needed to crack the password
+  pub fn score(&self, password: &str) -> u8 {
+    let log10_guesses = self.estimate_log10_guesses(password);
+    SCORE_THRESHOLDS
+      .iter()
+      .filter(|x| log10_guesses >= **x)
+      .count() as u8
+  }
+
+  // Estimates guesses in the style of zxcvbn: find every substring matching a pattern an attacker would try
+  // (common passwords, sequences, repeats, keyboard walks and years), then take the cheapest way to cover
+  // the whole password with those matches and brute forced characters.
+  fn estimate_log10_guesses(&self, password: &str) -> f64 {
+    let chars: Vec<char> = password.chars().collect();
+    let n = chars.len();
+    if n == 0 {
+      return 0.0;
+    }
+
+    // matches[j] holds (start, log10 guesses) of each match ending just before j
+    let mut matches: Vec<Vec<(usize, f64)>> = vec![vec![]; n + 1];
+    for i in 0..n {
+      for j in (i + 3)..=n {
+        if let Some(guesses) = self.match_guesses(&chars[i..j]) {
+          matches[j].push((i, guesses.log10()));
+        }
+      }
+    }
+
+    // best[j] is the fewest log10 guesses to cover the first j characters
+    let mut best = vec![f64::INFINITY; n + 1];
+    best[0] = 0.0;
+    for j in 1..=n {
+      best[j] = best[j - 1] + BRUTEFORCE_CARDINALITY.log10();
+      for (i, log10_guesses) in &matches[j] {
+        // the attacker also has to guess how the patterns are combined
+        best[j] = best[j].min(best[*i] + log10_guesses + 1.0);
+      }
+    }
+
+    best[n]
+  }
+
+  // the guesses needed for the cheapest pattern the characters match, if any
+  fn match_guesses(&self, chars: &[char]) -> Option<f64> {
+    let token: String = chars.iter().collect();
+    let lowercase = token.to_lowercase();
+    let reversed: String = lowercase.chars().rev().collect();
+    let unleeted = unleet(&lowercase);
+
+    let mut candidates = vec![];
+
+    if let Some(rank) = self.common_passwords.get(lowercase.as_str()) {
+      candidates.push(*rank as f64 * uppercase_variations(chars));
+    }
+    if let Some(rank) = self.common_passwords.get(reversed.as_str()) {
+      candidates.push(*rank as f64 * uppercase_variations(chars) * 2.0);
+    }
+    if unleeted != lowercase {
+      if let Some(rank) = self.common_passwords.get(unleeted.as_str()) {
+        candidates.push(*rank as f64 * uppercase_variations(chars) * 2.0);
+      }
+    }
+
+    if let Some(guesses) = sequence_guesses(chars) {
+      candidates.push(guesses);
+    }
+    if let Some(guesses) = repeat_guesses(chars) {
+      candidates.push(guesses);
+    }
+    if chars.len() >= 4
+      && KEYBOARD_ROWS
+        .iter()
+        .any(|row| row.contains(lowercase.as_str()) || row.contains(reversed.as_str()))
+    {
+      // a starting key, a direction and a length
+      candidates.push(KEYBOARD_ROWS.len() as f64 * 10.0 * 2.0 * chars.len() as f64);
+    }
+    if is_year(&token) {
+      candidates.push(120.0);
+    }
+
+    candidates.into_iter().reduce(f64::min)
+  }
+}
+
+// tokens shorter than 3 characters match too many passwords by chance
+fn contains_token(lowercase_password: &str, token: &str) -> bool {
+  let token = token.trim().to_lowercase();
+  token.chars().count() >= 3 && lowercase_password.contains(&token)
+}
+
+// undoes the common letter to symbol swaps, eg p@ssw0rd
+fn unleet(lowercase: &str) -> String {
+  lowercase
+    .chars()
+    .map(|x| match x {
+      '4' | '@' => 'a',
+      '3' => 'e',
+      '1' | '!' => 'i',
+      '0' => 'o',
+      '5' | '$' => 's',
+      '7' => 't',
+      x => x,
+    })
+    .collect()
+}
+
+// capitalizing the first letter or the whole word barely helps, anything else doubles per uppercase letter
+fn uppercase_variations(chars: &[char]) -> f64 {
+  let num_upper = chars.iter().filter(|x| x.is_uppercase()).count();
+  let num_lower = chars.iter().filter(|x| x.is_lowercase()).count();
+  if num_upper == 0 {
+    1.0
+  } else if num_lower == 0 || (num_upper == 1 && chars[0].is_uppercase()) {
+    2.0
+  } else {
+    2f64.powi(num_upper.min(20) as i32)
+  }
+}
+
+// runs like abc, 9876 or mnop
+fn sequence_guesses(chars: &[char]) -> Option<f64> {
+  let delta = chars[1] as i64 - chars[0] as i64;
+  if delta.abs() != 1 || !chars.windows(2).all(|x| x[1] as i64 - x[0] as i64 == delta) {
+    return None;
+  }
+
+  let base = match chars[0] {
+    'a' | 'A' | 'z' | 'Z' | '0' | '1' | '9' => 4.0,
+    x if x.is_ascii_digit() => 10.0,
+    _ => 26.0,
+  };
+
+  let direction = if delta < 0 { 2.0 } else { 1.0 };
+
+  Some(base * chars.len() as f64 * direction)
+}
+
+// the same character over and over
+fn repeat_guesses(chars: &[char]) -> Option<f64> {
+  if !chars.iter().all(|x| *x == chars[0]) {
+    return None;
+  }
+
+  let cardinality = if chars[0].is_ascii_digit() {
+    10.0
+  } else if chars[0].is_alphabetic() {
+    26.0
+  } else {
+    33.0
+  };
+
+  Some(cardinality * chars.len() as f64)
+}
+
+// people often add a recent year to a password
+fn is_year(token: &str) -> bool {
+  token.len() == 4
+    && token
+      .parse::<u32>()
+      .map(|x| (1900..=2039).contains(&x))
+      .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn policy(min_score: u8) -> PasswordPolicy {
+    PasswordPolicy::new(8, 64, min_score, None)
+  }
+
+  fn nobody() -> PersonalInfo<'static> {
+    PersonalInfo {
+      username: "",
+      realname: "",
+      email: None,
+    }
+  }
+
+  #[test]
+  fn scores_common_patterns_low() {
+    let policy = policy(0);
+    for password in [
+      "password",
+      "Password",
+      "p@ssw0rd",
+      "drowssap",
+      "12345678",
+      "qwertyuiop",
+      "aaaaaaaaaa",
+      "abcdefgh",
+      "password2024",
+    ] {
+      assert!(policy.score(password) <= 2, "{} scored too high", password);
+    }
+  }
+
+  #[test]
+  fn scores_random_passwords_high() {
+    let policy = policy(0);
+    for password in [
+      "correct horse battery staple",
+      "x8#Kp2!vQz9@Lm4$",
+      "tR7vq2Lw9mZx",
+    ] {
+      assert_eq!(policy.score(password), 4, "{} scored too low", password);
+    }
+  }
+
+  #[test]
+  fn longer_is_never_weaker() {
+    let policy = policy(0);
+    let mut last = 0.0;
+    for len in 1..30 {
+      let guesses = policy.estimate_log10_guesses(&"kx7Rq2".repeat(5)[..len]);
+      assert!(guesses >= last);
+      last = guesses;
+    }
+  }
+
+  #[test]
+  fn reports_every_violation_at_once() {
+    let violations = policy(3).check(
+      "Jane1",
+      &PersonalInfo {
+        username: "jane1",
+        realname: "Jane Doe",
+        email: Some("jane@example.com"),
+      },
+    );
+    assert!(matches!(
+      violations[..],
+      [
+        PasswordPolicyViolation::TooShort { min_length: 8 },
+        PasswordPolicyViolation::ContainsUsername,
+        PasswordPolicyViolation::ContainsRealname,
+        PasswordPolicyViolation::ContainsEmail,
+        PasswordPolicyViolation::TooGuessable { min_score: 3, .. },
+      ]
+    ));
+  }
+
+  #[test]
+  fn too_long_passwords_are_not_estimated() {
+    let violations = policy(4).check(&"a".repeat(65), &nobody());
+    assert!(matches!(
+      violations[..],
+      [PasswordPolicyViolation::TooLong { max_length: 64 }]
+    ));
+  }
+
+  #[test]
+  fn counts_characters_not_bytes() {
+    assert!(policy(0).check("ñññññññññ", &nobody()).is_empty());
+  }
+
+  #[test]
+  fn ignores_short_personal_info() {
+    let violations = policy(0).check(
+      "jo-correct-horse",
+      &PersonalInfo {
+        username: "jo",
+        realname: "Jo Li",
+        email: Some("jo@example.com"),
+      },
+    );
+    assert!(violations.is_empty());
+  }
+
+  #[test]
+  fn accepts_a_strong_password() {
+    assert!(policy(3)
+      .check("correct horse battery staple", &nobody())
+      .is_empty());
+  }
+
+  #[test]
+  fn recognizes_patterns() {
+    assert_eq!(sequence_guesses(&['a', 'b', 'c']), Some(12.0));
+    assert_eq!(sequence_guesses(&['9', '8', '7']), Some(24.0));
+    assert_eq!(sequence_guesses(&['a', 'c', 'e']), None);
+    assert_eq!(repeat_guesses(&['z', 'z', 'z']), Some(78.0));
+    assert_eq!(repeat_guesses(&['z', 'z', 'y']), None);
+    assert!(is_year("1999"));
+    assert!(!is_year("2099"));
+    assert_eq!(unleet("p@$$w0rd"), "password");
+    assert_eq!(uppercase_variations(&['P', 'a', 's']), 2.0);
+    assert_eq!(uppercase_variations(&['p', 'A', 'S']), 4.0);
+  }
+}
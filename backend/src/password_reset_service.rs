@@ -1,6 +1,17 @@
 use super::db_types::PasswordReset;
 use tokio_postgres::GenericClient;
 
+impl From<tokio_postgres::row::Row> for PasswordReset {
+    // select * from password_reset order only, otherwise it will fail
+    fn from(row: tokio_postgres::row::Row) -> PasswordReset {
+        PasswordReset {
+            password_reset_key_hash: row.get("password_reset_key_hash"),
+            creation_time: row.get("creation_time"),
+            creator_user_id: row.get("creator_user_id"),
+        }
+    }
+}
+
 pub async fn add(
     con: &mut impl GenericClient,
     password_reset_key_hash: String,
@@ -36,11 +47,65 @@ pub async fn get_by_password_reset_key_hash(
             &[&password_reset_key_hash],
         )
         .await?
-        .map(|row| PasswordReset {
-            password_reset_key_hash: row.get("password_reset_key_hash"),
-            creation_time: row.get("creation_time"),
-            creator_user_id: row.get("creator_user_id"),
-        });
+        .map(|row| row.into());
 
     Ok(result)
 }
+
+// the hashes are every hash the key may have been stored under, see token_hasher.rs
+pub async fn get_by_password_reset_key_hashes(
+    con: &mut impl GenericClient,
+    password_reset_key_hashes: &[String],
+) -> Result<Option<PasswordReset>, tokio_postgres::Error> {
+    let result = con
+        .query_opt(
+            "SELECT * FROM password_reset_t WHERE password_reset_key_hash=ANY($1)",
+            &[&password_reset_key_hashes],
+        )
+        .await?
+        .map(|row| row.into());
+
+    Ok(result)
+}
+
+// gets every password reset requested for the user
+pub async fn get_all_by_user_id(
+    con: &mut impl GenericClient,
+    user_id: i64,
+) -> Result<Vec<PasswordReset>, tokio_postgres::Error> {
+    let results = con
+        .query(
+            "SELECT pr.* FROM password_reset_t pr
+             WHERE pr.creator_user_id = $1
+             ORDER BY pr.creation_time
+            ",
+            &[&user_id],
+        )
+        .await?
+        .into_iter()
+        .map(|x| x.into())
+        .collect();
+
+    Ok(results)
+}
+
+pub async fn get_num_by_user_id_between(
+    con: &mut impl GenericClient,
+    user_id: i64,
+    min_creation_time: i64,
+    max_creation_time: i64,
+) -> Result<i64, tokio_postgres::Error> {
+    let count: i64 = con
+        .query_one(
+            "SELECT count(*) FROM password_reset_t
+             WHERE creator_user_id = $1
+             AND creation_time >= $2
+             AND creation_time <= $3
+            ",
+            &[&user_id, &min_creation_time, &max_creation_time],
+        )
+        .await?
+        .get(0);
+
+    Ok(count)
+}
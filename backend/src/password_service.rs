@@ -64,6 +64,46 @@ pub async fn get_by_user_id(
   Ok(result)
 }
 
+// replaces the hash of a password with a new hash of the same password, eg under a new pepper
+// the password itself hasn't changed, so this doesn't add a row
+pub async fn update_password_hash(
+  con: &mut impl GenericClient,
+  password_id: i64,
+  password_hash: &str,
+) -> Result<(), tokio_postgres::Error> {
+  con
+    .execute(
+      "UPDATE password_t SET password_hash=$1 WHERE password_id=$2",
+      &[&password_hash, &password_id],
+    )
+    .await?;
+
+  Ok(())
+}
+
+// gets the user's most recent passwords, newest first
+pub async fn get_recent_by_user_id(
+  con: &mut impl GenericClient,
+  user_id: i64,
+  limit: i64,
+) -> Result<Vec<Password>, tokio_postgres::Error> {
+  let results = con
+    .query(
+      "SELECT p.* FROM password_t p
+       WHERE p.creator_user_id = $1
+       ORDER BY p.password_id DESC
+       LIMIT $2
+      ",
+      &[&user_id, &limit],
+    )
+    .await?
+    .into_iter()
+    .map(|x| x.into())
+    .collect();
+
+  Ok(results)
+}
+
 #[allow(unused)]
 pub async fn get_by_password_id(
   con: &mut impl GenericClient,
@@ -91,6 +131,27 @@ pub async fn exists_by_password_reset_key_hash(
   Ok(count != 0)
 }
 
+// gets every password the user has ever had
+pub async fn get_all_by_user_id(
+  con: &mut impl GenericClient,
+  user_id: i64,
+) -> Result<Vec<Password>, tokio_postgres::Error> {
+  let results = con
+    .query(
+      "SELECT p.* FROM password_t p
+       WHERE p.creator_user_id = $1
+       ORDER BY p.password_id
+      ",
+      &[&user_id],
+    )
+    .await?
+    .into_iter()
+    .map(|x| x.into())
+    .collect();
+
+  Ok(results)
+}
+
 pub async fn query(
   con: &mut impl GenericClient,
   props: auth_service_api::request::PasswordViewProps,
@@ -0,0 +1,304 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+
+use super::password_hash_format::{HashFormat, VerifyError};
+use super::utils;
+
+// marks a hash made with a pepper, followed by the key id and the argon2 encoded hash, eg
+// $pepper$2025-01$argon2i$v=19$m=4096,t=3,p=1$...
+static PEPPER_PREFIX: &str = "$pepper$";
+
+// too short a secret would be easy to brute force along with the password
+static MIN_SECRET_LEN: usize = 16;
+
+#[derive(Debug)]
+pub enum PepperError {
+  Argon2(argon2::Error),
+  Verify(VerifyError),
+  // the hash was made with a key that is no longer in the pepper file
+  UnknownKeyId(String),
+}
+
+impl std::fmt::Display for PepperError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      PepperError::Argon2(e) => write!(f, "argon2 error: {}", e),
+      PepperError::Verify(e) => write!(f, "{}", e),
+      PepperError::UnknownKeyId(key_id) => write!(f, "no pepper with key id {}", key_id),
+    }
+  }
+}
+
+impl std::error::Error for PepperError {}
+
+impl From<argon2::Error> for PepperError {
+  fn from(e: argon2::Error) -> PepperError {
+    PepperError::Argon2(e)
+  }
+}
+
+impl From<VerifyError> for PepperError {
+  fn from(e: VerifyError) -> PepperError {
+    PepperError::Verify(e)
+  }
+}
+
+// the contents of the file given by --pepper-file, eg:
+// {
+//   "currentKeyId": "2025-01",
+//   "keys": { "2024-06": "<base64url secret>", "2025-01": "<base64url secret>" }
+// }
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PepperFile {
+  current_key_id: String,
+  keys: HashMap<String, String>,
+}
+
+// Secrets mixed into every password hash (as the argon2 secret parameter) but kept out of the database,
+// so that a dump of password_t alone isn't enough to crack the passwords offline.
+// New hashes use the current key. Older keys are kept so hashes made with them can still be checked,
+// and each is rewrapped with the current key the next time its user logs in.
+// With no pepper file, hashes are plain argon2 like before.
+// Hashes imported from older systems (see password_hash_format.rs) have no pepper, and are rehashed the same way.
+#[derive(Clone, Default)]
+pub struct Peppers {
+  current_key_id: Option<String>,
+  keys: HashMap<String, Vec<u8>>,
+}
+
+impl Peppers {
+  pub fn load(path: &Path) -> Result<Peppers, Box<dyn std::error::Error + 'static>> {
+    let contents = std::fs::read_to_string(path)?;
+    let file: PepperFile = serde_json::from_str(&contents)?;
+
+    let mut keys = HashMap::new();
+    for (key_id, secret) in file.keys {
+      // the key id is stored in the hash, between $ signs
+      if key_id.is_empty()
+        || !key_id
+          .chars()
+          .all(|x| x.is_ascii_alphanumeric() || x == '-' || x == '_')
+      {
+        return Err(
+          format!(
+            "pepper key id {:?} may only have letters, digits, - and _",
+            key_id
+          )
+          .into(),
+        );
+      }
+
+      let secret = base64_url::decode(&secret)?;
+      if secret.len() < MIN_SECRET_LEN {
+        return Err(
+          format!(
+            "pepper {} must be at least {} bytes",
+            key_id, MIN_SECRET_LEN
+          )
+          .into(),
+        );
+      }
+
+      keys.insert(key_id, secret);
+    }
+
+    if !keys.contains_key(&file.current_key_id) {
+      return Err(format!("current pepper {} is not in the file", file.current_key_id).into());
+    }
+
+    Ok(Peppers {
+      current_key_id: Some(file.current_key_id),
+      keys,
+    })
+  }
+
+  pub fn hash_password(&self, password: &str) -> Result<String, PepperError> {
+    match &self.current_key_id {
+      Some(key_id) => Ok(format!(
+        "{}{}{}",
+        PEPPER_PREFIX,
+        key_id,
+        utils::hash_password(password, &self.keys[key_id])?
+      )),
+      None => Ok(utils::hash_password(password, &[])?),
+    }
+  }
+
+  pub fn verify_password(&self, password: &str, password_hash: &str) -> Result<bool, PepperError> {
+    match split_key_id(password_hash) {
+      Some((key_id, encoded)) => {
+        let secret = self
+          .keys
+          .get(key_id)
+          .ok_or_else(|| PepperError::UnknownKeyId(key_id.to_owned()))?;
+        Ok(utils::verify_password(password, encoded, secret)?)
+      }
+      None => Ok(utils::verify_password(password, password_hash, &[])?),
+    }
+  }
+
+  // true if the hash wasn't made with the current key, or isn't argon2 at all,
+  // so should be rewrapped once the password is known
+  pub fn needs_rehash(&self, password_hash: &str) -> bool {
+    match split_key_id(password_hash) {
+      Some((key_id, _)) => Some(key_id) != self.current_key_id.as_deref(),
+      None => {
+        self.current_key_id.is_some() || HashFormat::of(password_hash) != Some(HashFormat::Argon2)
+      }
+    }
+  }
+}
+
+// the key id and the argon2 encoded hash, if the hash was made with a pepper
+fn split_key_id(password_hash: &str) -> Option<(&str, &str)> {
+  let rest = password_hash.strip_prefix(PEPPER_PREFIX)?;
+  // the encoded hash starts with its own $
+  let index = rest.find('$')?;
+  Some((&rest[..index], &rest[index..]))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn peppers(current_key_id: &str, key_ids: &[&str]) -> Peppers {
+    Peppers {
+      current_key_id: Some(current_key_id.to_owned()),
+      keys: key_ids
+        .iter()
+        .map(|x| (x.to_string(), format!("secret for {}", x).into_bytes()))
+        .collect(),
+    }
+  }
+
+  // a scratch file per test, since they run in parallel
+  fn load_str(name: &str, contents: &str) -> Result<Peppers, Box<dyn std::error::Error + 'static>> {
+    let path = std::env::temp_dir().join(format!("pepper-{}-{}.json", name, std::process::id()));
+    std::fs::write(&path, contents).unwrap();
+    let peppers = Peppers::load(&path);
+    std::fs::remove_file(&path).unwrap();
+    peppers
+  }
+
+  #[test]
+  fn hashes_with_the_current_key() {
+    let peppers = peppers("2025-01", &["2024-06", "2025-01"]);
+    let hash = peppers.hash_password("hunter22").unwrap();
+
+    assert!(hash.starts_with("$pepper$2025-01$argon2"));
+    assert!(peppers.verify_password("hunter22", &hash).unwrap());
+    assert!(!peppers.verify_password("hunter23", &hash).unwrap());
+    assert!(!peppers.needs_rehash(&hash));
+  }
+
+  #[test]
+  fn the_secret_is_part_of_the_hash() {
+    let hash = peppers("2025-01", &["2025-01"])
+      .hash_password("hunter22")
+      .unwrap();
+    // the same key id with a different secret
+    let other = Peppers {
+      current_key_id: Some("2025-01".to_owned()),
+      keys: HashMap::from([("2025-01".to_owned(), b"some other secret".to_vec())]),
+    };
+    assert!(!other.verify_password("hunter22", &hash).unwrap());
+
+    // nor does the hash check out without any secret
+    let (_, encoded) = split_key_id(&hash).unwrap();
+    assert!(!Peppers::default()
+      .verify_password("hunter22", encoded)
+      .unwrap());
+  }
+
+  #[test]
+  fn rotates_to_the_current_key() {
+    let old_hash = peppers("2024-06", &["2024-06"])
+      .hash_password("hunter22")
+      .unwrap();
+
+    let rotated = peppers("2025-01", &["2024-06", "2025-01"]);
+    assert!(rotated.verify_password("hunter22", &old_hash).unwrap());
+    assert!(rotated.needs_rehash(&old_hash));
+
+    // once the old key is removed its hashes can't be checked
+    let retired = peppers("2025-01", &["2025-01"]);
+    assert!(matches!(
+      retired.verify_password("hunter22", &old_hash),
+      Err(PepperError::UnknownKeyId(x)) if x == "2024-06"
+    ));
+  }
+
+  #[test]
+  fn keeps_working_without_a_pepper() {
+    let plain = Peppers::default().hash_password("hunter22").unwrap();
+    assert!(plain.starts_with("$argon2"));
+    assert!(!Peppers::default().needs_rehash(&plain));
+
+    // hashes from before there was a pepper file still verify, and get rewrapped
+    let peppers = peppers("2025-01", &["2025-01"]);
+    assert!(peppers.verify_password("hunter22", &plain).unwrap());
+    assert!(peppers.needs_rehash(&plain));
+  }
+
+  #[test]
+  fn splits_off_the_key_id() {
+    assert_eq!(
+      split_key_id("$pepper$2025-01$argon2i$v=19$m=4096,t=3,p=1$c2FsdA$aGFzaA"),
+      Some(("2025-01", "$argon2i$v=19$m=4096,t=3,p=1$c2FsdA$aGFzaA"))
+    );
+    assert_eq!(
+      split_key_id("$argon2i$v=19$m=4096,t=3,p=1$c2FsdA$aGFzaA"),
+      None
+    );
+    assert_eq!(split_key_id("$pepper$2025-01"), None);
+  }
+
+  #[test]
+  fn loads_a_pepper_file() {
+    let secret = base64_url::encode(&[7u8; 32]);
+    let peppers = load_str(
+      "valid",
+      &format!(
+        r#"{{ "currentKeyId": "2025-01", "keys": {{ "2024-06": "{0}", "2025-01": "{0}" }} }}"#,
+        secret
+      ),
+    )
+    .unwrap();
+    assert_eq!(peppers.current_key_id.as_deref(), Some("2025-01"));
+    assert_eq!(peppers.keys["2024-06"], vec![7u8; 32]);
+  }
+
+  #[test]
+  fn rejects_bad_pepper_files() {
+    let secret = base64_url::encode(&[7u8; 32]);
+    let short_secret = base64_url::encode(&[7u8; 8]);
+    for (name, contents) in [
+      (
+        "missing-current",
+        format!(
+          r#"{{ "currentKeyId": "2025-01", "keys": {{ "2024-06": "{}" }} }}"#,
+          secret
+        ),
+      ),
+      (
+        "bad-key-id",
+        format!(
+          r#"{{ "currentKeyId": "a$b", "keys": {{ "a$b": "{}" }} }}"#,
+          secret
+        ),
+      ),
+      (
+        "short-secret",
+        format!(
+          r#"{{ "currentKeyId": "2025-01", "keys": {{ "2025-01": "{}" }} }}"#,
+          short_secret
+        ),
+      ),
+      ("not-json", "currentKeyId = 2025-01".to_owned()),
+    ] {
+      assert!(load_str(name, &contents).is_err(), "{} was accepted", name);
+    }
+  }
+}
@@ -0,0 +1,211 @@
+use super::db_types::*;
+use std::convert::TryInto;
+use tokio_postgres::GenericClient;
+
+impl From<tokio_postgres::row::Row> for PhoneChallenge {
+  // select * from phone_challenge order only, otherwise it will fail
+  fn from(row: tokio_postgres::row::Row) -> PhoneChallenge {
+    PhoneChallenge {
+      phone_challenge_id: row.get("phone_challenge_id"),
+      creation_time: row.get("creation_time"),
+      creator_user_id: row.get("creator_user_id"),
+      phone: row.get("phone"),
+      // means that there's a mismatch between the values of the enum and the value stored in the column
+      phone_challenge_kind: (row.get::<&str, i64>("phone_challenge_kind") as u8)
+        .try_into()
+        .unwrap(),
+      phone_code_hash: row.get("phone_code_hash"),
+    }
+  }
+}
+
+pub async fn add(
+  con: &mut impl GenericClient,
+  creator_user_id: i64,
+  phone: String,
+  phone_challenge_kind: PhoneChallengeKind,
+  phone_code_hash: String,
+) -> Result<PhoneChallenge, tokio_postgres::Error> {
+  let row = con
+    .query_one(
+      "INSERT INTO
+       phone_challenge_t(
+         creator_user_id,
+         phone,
+         phone_challenge_kind,
+         phone_code_hash
+       )
+       VALUES ($1, $2, $3, $4)
+       RETURNING phone_challenge_id, creation_time
+      ",
+      &[
+        &creator_user_id,
+        &phone,
+        &(phone_challenge_kind as i64),
+        &phone_code_hash,
+      ],
+    )
+    .await?;
+
+  Ok(PhoneChallenge {
+    phone_challenge_id: row.get(0),
+    creation_time: row.get(1),
+    creator_user_id,
+    phone,
+    phone_challenge_kind,
+    phone_code_hash,
+  })
+}
+
+pub async fn get_by_phone_challenge_id(
+  con: &mut impl GenericClient,
+  phone_challenge_id: i64,
+) -> Result<Option<PhoneChallenge>, tokio_postgres::Error> {
+  let result = con
+    .query_opt(
+      "SELECT * FROM phone_challenge_t WHERE phone_challenge_id=$1",
+      &[&phone_challenge_id],
+    )
+    .await?
+    .map(|row| row.into());
+
+  Ok(result)
+}
+
+// asking for a new code replaces the old one
+pub async fn get_latest_by_user_id_and_kind(
+  con: &mut impl GenericClient,
+  user_id: i64,
+  phone_challenge_kind: PhoneChallengeKind,
+) -> Result<Option<PhoneChallenge>, tokio_postgres::Error> {
+  let result = con
+    .query_opt(
+      "SELECT * FROM phone_challenge_t
+       WHERE creator_user_id=$1
+       AND phone_challenge_kind=$2
+       ORDER BY phone_challenge_id DESC
+       LIMIT 1
+      ",
+      &[&user_id, &(phone_challenge_kind as i64)],
+    )
+    .await?
+    .map(|row| row.into());
+
+  Ok(result)
+}
+
+pub async fn get_num_by_user_id_between(
+  con: &mut impl GenericClient,
+  user_id: i64,
+  min_creation_time: i64,
+  max_creation_time: i64,
+) -> Result<i64, tokio_postgres::Error> {
+  let count: i64 = con
+    .query_one(
+      "SELECT count(*) FROM phone_challenge_t
+       WHERE creator_user_id=$1
+       AND creation_time >= $2
+       AND creation_time <= $3
+      ",
+      &[&user_id, &min_creation_time, &max_creation_time],
+    )
+    .await?
+    .get(0);
+
+  Ok(count)
+}
+
+// counts every text sent to the number, whoever asked for it
+pub async fn get_num_by_phone_between(
+  con: &mut impl GenericClient,
+  phone: &str,
+  min_creation_time: i64,
+  max_creation_time: i64,
+) -> Result<i64, tokio_postgres::Error> {
+  let count: i64 = con
+    .query_one(
+      "SELECT count(*) FROM phone_challenge_t
+       WHERE phone=$1
+       AND creation_time >= $2
+       AND creation_time <= $3
+      ",
+      &[&phone, &min_creation_time, &max_creation_time],
+    )
+    .await?
+    .get(0);
+
+  Ok(count)
+}
+
+pub async fn add_attempt(
+  con: &mut impl GenericClient,
+  phone_challenge_id: i64,
+  success: bool,
+) -> Result<(), tokio_postgres::Error> {
+  con
+    .execute(
+      "INSERT INTO phone_challenge_attempt_t(phone_challenge_id, success) VALUES ($1, $2)",
+      &[&phone_challenge_id, &success],
+    )
+    .await?;
+
+  Ok(())
+}
+
+pub async fn get_num_failed_attempts(
+  con: &mut impl GenericClient,
+  phone_challenge_id: i64,
+) -> Result<i64, tokio_postgres::Error> {
+  let count: i64 = con
+    .query_one(
+      "SELECT count(*) FROM phone_challenge_attempt_t
+       WHERE phone_challenge_id=$1
+       AND success=false
+      ",
+      &[&phone_challenge_id],
+    )
+    .await?
+    .get(0);
+
+  Ok(count)
+}
+
+// a code can only be used once
+pub async fn exists_successful_attempt(
+  con: &mut impl GenericClient,
+  phone_challenge_id: i64,
+) -> Result<bool, tokio_postgres::Error> {
+  let count: i64 = con
+    .query_one(
+      "SELECT count(*) FROM phone_challenge_attempt_t
+       WHERE phone_challenge_id=$1
+       AND success=true
+      ",
+      &[&phone_challenge_id],
+    )
+    .await?
+    .get(0);
+
+  Ok(count != 0)
+}
+
+// gets every code texted for the user
+pub async fn get_all_by_user_id(
+  con: &mut impl GenericClient,
+  user_id: i64,
+) -> Result<Vec<PhoneChallenge>, tokio_postgres::Error> {
+  let results = con
+    .query(
+      "SELECT pc.* FROM phone_challenge_t pc
+       WHERE pc.creator_user_id = $1
+       ORDER BY pc.phone_challenge_id
+      ",
+      &[&user_id],
+    )
+    .await?
+    .into_iter()
+    .map(|x| x.into())
+    .collect();
+
+  Ok(results)
+}
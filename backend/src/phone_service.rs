@@ -0,0 +1,101 @@
+use super::db_types::*;
+use tokio_postgres::GenericClient;
+
+impl From<tokio_postgres::row::Row> for Phone {
+  // select * from phone order only, otherwise it will fail
+  fn from(row: tokio_postgres::row::Row) -> Phone {
+    Phone {
+      phone_id: row.get("phone_id"),
+      creation_time: row.get("creation_time"),
+      creator_user_id: row.get("creator_user_id"),
+      phone_challenge_id: row.get("phone_challenge_id"),
+    }
+  }
+}
+
+// phone_challenge_id is None to remove the user's phone
+pub async fn add(
+  con: &mut impl GenericClient,
+  creator_user_id: i64,
+  phone_challenge_id: Option<i64>,
+) -> Result<Phone, tokio_postgres::Error> {
+  let row = con
+    .query_one(
+      "INSERT INTO
+       phone_t(
+         creator_user_id,
+         phone_challenge_id
+       )
+       VALUES ($1, $2)
+       RETURNING phone_id, creation_time
+      ",
+      &[&creator_user_id, &phone_challenge_id],
+    )
+    .await?;
+
+  Ok(Phone {
+    phone_id: row.get(0),
+    creation_time: row.get(1),
+    creator_user_id,
+    phone_challenge_id,
+  })
+}
+
+// gets the user's phone, if they have one
+pub async fn get_by_user_id(
+  con: &mut impl GenericClient,
+  user_id: i64,
+) -> Result<Option<Phone>, tokio_postgres::Error> {
+  let result = con
+    .query_opt(
+      "SELECT * FROM recent_phone_v
+       WHERE creator_user_id=$1
+       AND phone_challenge_id IS NOT NULL
+      ",
+      &[&user_id],
+    )
+    .await?
+    .map(|row| row.into());
+
+  Ok(result)
+}
+
+// gets whoever currently holds the number
+pub async fn get_by_phone(
+  con: &mut impl GenericClient,
+  phone: &str,
+) -> Result<Option<Phone>, tokio_postgres::Error> {
+  let result = con
+    .query_opt(
+      "SELECT p.* FROM recent_phone_v p
+       INNER JOIN phone_challenge_t pc ON pc.phone_challenge_id = p.phone_challenge_id
+       WHERE pc.phone=$1
+      ",
+      &[&phone],
+    )
+    .await?
+    .map(|row| row.into());
+
+  Ok(result)
+}
+
+// gets every phone the user has held, including removals
+pub async fn get_all_by_user_id(
+  con: &mut impl GenericClient,
+  user_id: i64,
+) -> Result<Vec<Phone>, tokio_postgres::Error> {
+  let results = con
+    .query(
+      "SELECT p.* FROM phone_t p
+       WHERE p.creator_user_id = $1
+       ORDER BY p.phone_id
+      ",
+      &[&user_id],
+    )
+    .await?
+    .into_iter()
+    .map(|x| x.into())
+    .collect();
+
+  Ok(results)
+}
@@ -0,0 +1,91 @@
+use super::db_types::*;
+use tokio_postgres::GenericClient;
+
+impl From<tokio_postgres::row::Row> for Reauthentication {
+  // select * from reauthentication order only, otherwise it will fail
+  fn from(row: tokio_postgres::row::Row) -> Reauthentication {
+    Reauthentication {
+      reauthentication_id: row.get("reauthentication_id"),
+      creation_time: row.get("creation_time"),
+      creator_user_id: row.get("creator_user_id"),
+      api_key_hash: row.get("api_key_hash"),
+      reauthentication_kind: (row.get::<&str, i64>("reauthentication_kind") as u8)
+        .try_into()
+        .unwrap(),
+    }
+  }
+}
+
+pub async fn add(
+  con: &mut impl GenericClient,
+  creator_user_id: i64,
+  api_key_hash: String,
+  reauthentication_kind: ReauthenticationKind,
+) -> Result<Reauthentication, tokio_postgres::Error> {
+  let row = con
+    .query_one(
+      "INSERT INTO
+       reauthentication_t(
+         creator_user_id,
+         api_key_hash,
+         reauthentication_kind
+       )
+       VALUES ($1, $2, $3)
+       RETURNING reauthentication_id, creation_time
+      ",
+      &[
+        &creator_user_id,
+        &api_key_hash,
+        &(reauthentication_kind as i64),
+      ],
+    )
+    .await?;
+
+  Ok(Reauthentication {
+    reauthentication_id: row.get(0),
+    creation_time: row.get(1),
+    creator_user_id,
+    api_key_hash,
+    reauthentication_kind,
+  })
+}
+
+// gets the last time the holder of this api key proved who they are
+pub async fn get_latest_by_api_key_hash(
+  con: &mut impl GenericClient,
+  api_key_hash: &str,
+) -> Result<Option<Reauthentication>, tokio_postgres::Error> {
+  let result = con
+    .query_opt(
+      "SELECT * FROM reauthentication_t
+       WHERE api_key_hash=$1
+       ORDER BY reauthentication_id DESC
+       LIMIT 1
+      ",
+      &[&api_key_hash],
+    )
+    .await?
+    .map(|x| x.into());
+
+  Ok(result)
+}
+
+pub async fn get_all_by_user_id(
+  con: &mut impl GenericClient,
+  user_id: i64,
+) -> Result<Vec<Reauthentication>, tokio_postgres::Error> {
+  let results = con
+    .query(
+      "SELECT * FROM reauthentication_t
+       WHERE creator_user_id=$1
+       ORDER BY reauthentication_id
+      ",
+      &[&user_id],
+    )
+    .await?
+    .into_iter()
+    .map(|x| x.into())
+    .collect();
+
+  Ok(results)
+}
@@ -0,0 +1,77 @@
+use super::db_types::*;
+use tokio_postgres::GenericClient;
+
+impl From<tokio_postgres::row::Row> for SecondFactor {
+  // select * from second_factor order only, otherwise it will fail
+  fn from(row: tokio_postgres::row::Row) -> SecondFactor {
+    SecondFactor {
+      second_factor_id: row.get("second_factor_id"),
+      creation_time: row.get("creation_time"),
+      creator_user_id: row.get("creator_user_id"),
+      enabled: row.get("enabled"),
+    }
+  }
+}
+
+pub async fn add(
+  con: &mut impl GenericClient,
+  creator_user_id: i64,
+  enabled: bool,
+) -> Result<SecondFactor, tokio_postgres::Error> {
+  let row = con
+    .query_one(
+      "INSERT INTO
+       second_factor_t(
+         creator_user_id,
+         enabled
+       )
+       VALUES ($1, $2)
+       RETURNING second_factor_id, creation_time
+      ",
+      &[&creator_user_id, &enabled],
+    )
+    .await?;
+
+  Ok(SecondFactor {
+    second_factor_id: row.get(0),
+    creation_time: row.get(1),
+    creator_user_id,
+    enabled,
+  })
+}
+
+pub async fn get_by_user_id(
+  con: &mut impl GenericClient,
+  user_id: i64,
+) -> Result<Option<SecondFactor>, tokio_postgres::Error> {
+  let result = con
+    .query_opt(
+      "SELECT * FROM recent_second_factor_v WHERE creator_user_id=$1",
+      &[&user_id],
+    )
+    .await?
+    .map(|row| row.into());
+
+  Ok(result)
+}
+
+// gets every change the user made to their second factor
+pub async fn get_all_by_user_id(
+  con: &mut impl GenericClient,
+  user_id: i64,
+) -> Result<Vec<SecondFactor>, tokio_postgres::Error> {
+  let results = con
+    .query(
+      "SELECT sf.* FROM second_factor_t sf
+       WHERE sf.creator_user_id = $1
+       ORDER BY sf.second_factor_id
+      ",
+      &[&user_id],
+    )
+    .await?
+    .into_iter()
+    .map(|x| x.into())
+    .collect();
+
+  Ok(results)
+}
@@ -0,0 +1,93 @@
+use super::db_types::*;
+use std::convert::TryInto;
+use tokio_postgres::GenericClient;
+
+impl From<tokio_postgres::row::Row> for SecurityNotification {
+  // select * from security_notification order only, otherwise it will fail
+  fn from(row: tokio_postgres::row::Row) -> SecurityNotification {
+    SecurityNotification {
+      security_notification_key_hash: row.get("security_notification_key_hash"),
+      creation_time: row.get("creation_time"),
+      creator_user_id: row.get("creator_user_id"),
+      // means that there's a mismatch between the values of the enum and the value stored in the column
+      security_notification_kind: (row.get::<&str, i64>("security_notification_kind") as u8)
+        .try_into()
+        .unwrap(),
+      email: row.get("email"),
+    }
+  }
+}
+
+pub async fn add(
+  con: &mut impl GenericClient,
+  security_notification_key_hash: String,
+  creator_user_id: i64,
+  security_notification_kind: SecurityNotificationKind,
+  email: String,
+) -> Result<SecurityNotification, tokio_postgres::Error> {
+  let row = con
+    .query_one(
+      "INSERT INTO
+       security_notification_t(
+         security_notification_key_hash,
+         creator_user_id,
+         security_notification_kind,
+         email
+       )
+       VALUES ($1, $2, $3, $4)
+       RETURNING creation_time
+      ",
+      &[
+        &security_notification_key_hash,
+        &creator_user_id,
+        &(security_notification_kind as i64),
+        &email,
+      ],
+    )
+    .await?;
+
+  Ok(SecurityNotification {
+    security_notification_key_hash,
+    creation_time: row.get(0),
+    creator_user_id,
+    security_notification_kind,
+    email,
+  })
+}
+
+// the hashes are every hash the key may have been stored under, see token_hasher.rs
+pub async fn get_by_security_notification_key_hashes(
+  con: &mut impl GenericClient,
+  security_notification_key_hashes: &[String],
+) -> Result<Option<SecurityNotification>, tokio_postgres::Error> {
+  let result = con
+    .query_opt(
+      "SELECT * FROM security_notification_t WHERE security_notification_key_hash=ANY($1)",
+      &[&security_notification_key_hashes],
+    )
+    .await?
+    .map(|row| row.into());
+
+  Ok(result)
+}
+
+// gets every security notification sent to the user
+pub async fn get_all_by_user_id(
+  con: &mut impl GenericClient,
+  user_id: i64,
+) -> Result<Vec<SecurityNotification>, tokio_postgres::Error> {
+  let results = con
+    .query(
+      "SELECT sn.* FROM security_notification_t sn
+       WHERE sn.creator_user_id = $1
+       ORDER BY sn.creation_time
+      ",
+      &[&user_id],
+    )
+    .await?
+    .into_iter()
+    .map(|x| x.into())
+    .collect();
+
+  Ok(results)
+}
@@ -0,0 +1,112 @@
+use async_trait::async_trait;
+use serde::Serialize;
+use std::path::PathBuf;
+use tokio::io::AsyncWriteExt;
+
+use super::utils;
+
+#[derive(Clone, Debug)]
+pub struct SmsError(pub String);
+
+impl std::fmt::Display for SmsError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "sms error: {}", self.0)
+  }
+}
+
+impl std::error::Error for SmsError {}
+
+// something that can deliver a text message, chosen with --sms-sender
+#[async_trait]
+pub trait SmsSender: Send + Sync {
+  // the phone number is in E.164 form, eg +15555550123
+  async fn send(&self, phone: &str, message: &str) -> Result<(), SmsError>;
+}
+
+// sends through the Twilio messages api
+pub struct TwilioSmsSender {
+  client: reqwest::Client,
+  account_sid: String,
+  auth_token: String,
+  from: String,
+}
+
+impl TwilioSmsSender {
+  pub fn new(account_sid: String, auth_token: String, from: String) -> TwilioSmsSender {
+    TwilioSmsSender {
+      client: reqwest::Client::new(),
+      account_sid,
+      auth_token,
+      from,
+    }
+  }
+}
+
+#[async_trait]
+impl SmsSender for TwilioSmsSender {
+  async fn send(&self, phone: &str, message: &str) -> Result<(), SmsError> {
+    let response = self
+      .client
+      .post(format!(
+        "https://api.twilio.com/2010-04-01/Accounts/{}/Messages.json",
+        self.account_sid
+      ))
+      .basic_auth(&self.account_sid, Some(&self.auth_token))
+      .form(&[("To", phone), ("From", &self.from), ("Body", message)])
+      .send()
+      .await
+      .map_err(|e| SmsError(e.to_string()))?;
+
+    if !response.status().is_success() {
+      let status = response.status();
+      let body = response.text().await.unwrap_or_default();
+      return Err(SmsError(format!("{}: {}", status, body)));
+    }
+
+    Ok(())
+  }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct FileSms<'a> {
+  creation_time: i64,
+  phone: &'a str,
+  message: &'a str,
+}
+
+// appends each message as a line of JSON to a file, for development and testing
+pub struct FileSmsSender {
+  path: PathBuf,
+}
+
+impl FileSmsSender {
+  pub fn new(path: PathBuf) -> FileSmsSender {
+    FileSmsSender { path }
+  }
+}
+
+#[async_trait]
+impl SmsSender for FileSmsSender {
+  async fn send(&self, phone: &str, message: &str) -> Result<(), SmsError> {
+    let mut line = serde_json::to_string(&FileSms {
+      creation_time: utils::current_time_millis(),
+      phone,
+      message,
+    })
+    .map_err(|e| SmsError(e.to_string()))?;
+    line.push('\n');
+
+    let mut file = tokio::fs::OpenOptions::new()
+      .create(true)
+      .append(true)
+      .open(&self.path)
+      .await
+      .map_err(|e| SmsError(e.to_string()))?;
+
+    file
+      .write_all(line.as_bytes())
+      .await
+      .map_err(|e| SmsError(e.to_string()))
+  }
+}
@@ -0,0 +1,481 @@
+use aes_gcm::aead::Aead;
+use aes_gcm::{Aes256Gcm, Nonce};
+use hmac::{Hmac, Mac};
+use rand::{thread_rng, Rng};
+use serde::Deserialize;
+use sha2::Sha256;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use super::utils;
+
+// too short a secret would let someone with the database guess it and then check tokens against it
+static MIN_SECRET_LEN: usize = 32;
+
+// marks text sealed with a key file, followed by the key version and the nonce and ciphertext, eg
+// $sealed$v2$<base64url nonce and ciphertext>
+static SEALED_PREFIX: &str = "$sealed$v";
+
+// the encryption key is derived from each version's secret, so it is never the same as the hmac key
+static SEAL_KEY_LABEL: &[u8] = b"innexgo authenticator seal key";
+
+#[derive(Debug)]
+pub enum UnsealError {
+  // the text was sealed with a version that is no longer in the key file, or with no key file at all
+  UnknownVersion,
+  Corrupt,
+}
+
+impl std::fmt::Display for UnsealError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      UnsealError::UnknownVersion => {
+        write!(f, "sealed with a token hash key that is not in the file")
+      }
+      UnsealError::Corrupt => write!(f, "sealed text is corrupt"),
+    }
+  }
+}
+
+impl std::error::Error for UnsealError {}
+
+// what a token is for, which decides the prefix it is given
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TokenKind {
+  ApiKey,
+  VerificationChallenge,
+  PasswordReset,
+  ParentAccess,
+  EmailChange,
+  SecurityNotification,
+}
+
+impl TokenKind {
+  // distinctive enough for secret scanning tools to recognize a leaked token, and say what kind it is
+  fn prefix(self) -> &'static str {
+    match self {
+      TokenKind::ApiKey => "inxak_",
+      TokenKind::VerificationChallenge => "inxvc_",
+      TokenKind::PasswordReset => "inxpr_",
+      TokenKind::ParentAccess => "inxpa_",
+      TokenKind::EmailChange => "inxec_",
+      TokenKind::SecurityNotification => "inxsn_",
+    }
+  }
+}
+
+// the contents of the file given by --token-hash-key-file, eg:
+// {
+//   "currentVersion": 2,
+//   "keys": { "1": "<base64url secret>", "2": "<base64url secret>" },
+//   "acceptUnkeyed": false
+// }
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TokenHashKeyFile {
+  current_version: u32,
+  keys: BTreeMap<u32, String>,
+  // whether tokens hashed before there was a key file are still accepted
+  #[serde(default = "default_accept_unkeyed")]
+  accept_unkeyed: bool,
+}
+
+fn default_accept_unkeyed() -> bool {
+  true
+}
+
+// Api keys, and the keys sent in links and codes, are only stored as hashes so that they can be looked up.
+// With a key file the hashes are HMAC-SHA256 under a server secret, written as v<version>$<hmac>,
+// so that a copy of the database can't be used to check guessed tokens.
+// New tokens are hashed with the current version. Tokens are looked up under every version in the file
+// (and the plain SHA-256 used before there was a key file, unless acceptUnkeyed is false),
+// so a new version can be made current while tokens hashed under the old one are still in use.
+// Once the longest lived token hashed under an old version has expired, it can be removed from the file.
+// Without a key file, the hashes are plain SHA-256 like before.
+// The same secrets are used to seal queued emails, whose contents hold raw tokens.
+#[derive(Clone)]
+pub struct TokenHasher {
+  current_version: Option<u32>,
+  keys: BTreeMap<u32, Vec<u8>>,
+  accept_unkeyed: bool,
+}
+
+impl Default for TokenHasher {
+  fn default() -> TokenHasher {
+    TokenHasher {
+      current_version: None,
+      keys: BTreeMap::new(),
+      accept_unkeyed: true,
+    }
+  }
+}
+
+impl TokenHasher {
+  pub fn load(path: &Path) -> Result<TokenHasher, Box<dyn std::error::Error + 'static>> {
+    let contents = std::fs::read_to_string(path)?;
+    let file: TokenHashKeyFile = serde_json::from_str(&contents)?;
+
+    let mut keys = BTreeMap::new();
+    for (version, secret) in file.keys {
+      let secret = base64_url::decode(&secret)?;
+      if secret.len() < MIN_SECRET_LEN {
+        return Err(
+          format!(
+            "token hash key {} must be at least {} bytes",
+            version, MIN_SECRET_LEN
+          )
+          .into(),
+        );
+      }
+      keys.insert(version, secret);
+    }
+
+    if !keys.contains_key(&file.current_version) {
+      return Err(
+        format!(
+          "current token hash key {} is not in the file",
+          file.current_version
+        )
+        .into(),
+      );
+    }
+
+    Ok(TokenHasher {
+      current_version: Some(file.current_version),
+      keys,
+      accept_unkeyed: file.accept_unkeyed,
+    })
+  }
+
+  // a new random token, to be given out once and stored only as its hash
+  pub fn gen_token(&self, token_kind: TokenKind) -> String {
+    format!("{}{}", token_kind.prefix(), utils::gen_random_string())
+  }
+
+  // the hash to store a new token under
+  pub fn hash(&self, token: &str) -> String {
+    match self.current_version {
+      Some(version) => self.hash_with_version(token, version),
+      None => utils::hash_str(token),
+    }
+  }
+
+  // every hash a token may have been stored under, the current version first
+  pub fn candidate_hashes(&self, token: &str) -> Vec<String> {
+    let mut hashes = vec![];
+
+    if let Some(current_version) = self.current_version {
+      hashes.push(self.hash_with_version(token, current_version));
+    }
+
+    for version in self.keys.keys().rev() {
+      if Some(*version) != self.current_version {
+        hashes.push(self.hash_with_version(token, *version));
+      }
+    }
+
+    if self.accept_unkeyed || self.current_version.is_none() {
+      hashes.push(utils::hash_str(token));
+    }
+
+    hashes
+  }
+
+  // true if the token matches the stored hash, eg for codes that are compared rather than looked up
+  pub fn verify(&self, token: &str, token_hash: &str) -> bool {
+    self.candidate_hashes(token).iter().any(|x| x == token_hash)
+  }
+
+  // Encrypts text holding raw tokens that has to be kept for a while, ie queued emails,
+  // so that a copy of the database doesn't hand out working links.
+  // Without a key file there is no secret to encrypt with, so the text is kept as is.
+  pub fn seal(&self, plaintext: &str) -> String {
+    let version = match self.current_version {
+      Some(version) => version,
+      None => return plaintext.to_owned(),
+    };
+
+    // a random nonce is fine, far fewer than 2^32 texts are sealed under one key
+    let nonce = thread_rng().gen::<[u8; 12]>();
+    let ciphertext = self
+      .seal_cipher(version)
+      .encrypt(Nonce::from_slice(&nonce), plaintext.as_bytes())
+      .expect("encrypting to a vec can't fail");
+
+    format!(
+      "{}{}${}",
+      SEALED_PREFIX,
+      version,
+      base64_url::encode(&[&nonce[..], &ciphertext].concat())
+    )
+  }
+
+  // decrypts text from seal, under any version in the file
+  // text that was never sealed is returned as is
+  pub fn unseal(&self, text: &str) -> Result<String, UnsealError> {
+    let rest = match text.strip_prefix(SEALED_PREFIX) {
+      Some(rest) => rest,
+      None => return Ok(text.to_owned()),
+    };
+
+    let (version, encoded) = rest.split_once('$').ok_or(UnsealError::Corrupt)?;
+    let version: u32 = version.parse().map_err(|_| UnsealError::Corrupt)?;
+    if !self.keys.contains_key(&version) {
+      return Err(UnsealError::UnknownVersion);
+    }
+
+    let bytes = base64_url::decode(encoded).map_err(|_| UnsealError::Corrupt)?;
+    if bytes.len() < 12 {
+      return Err(UnsealError::Corrupt);
+    }
+    let (nonce, ciphertext) = bytes.split_at(12);
+
+    let plaintext = self
+      .seal_cipher(version)
+      .decrypt(Nonce::from_slice(nonce), ciphertext)
+      .map_err(|_| UnsealError::Corrupt)?;
+
+    String::from_utf8(plaintext).map_err(|_| UnsealError::Corrupt)
+  }
+
+  fn seal_cipher(&self, version: u32) -> Aes256Gcm {
+    let mut mac = Hmac::<Sha256>::new_from_slice(&self.keys[&version])
+      .expect("hmac accepts keys of any length");
+    mac.update(SEAL_KEY_LABEL);
+    // named in full, hmac has a new_from_slice of its own
+    <Aes256Gcm as aes_gcm::KeyInit>::new(&mac.finalize().into_bytes())
+  }
+
+  fn hash_with_version(&self, token: &str, version: u32) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(&self.keys[&version])
+      .expect("hmac accepts keys of any length");
+    mac.update(token.as_bytes());
+    format!(
+      "v{}${}",
+      version,
+      base64_url::encode(&mac.finalize().into_bytes())
+    )
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn hasher(current_version: u32, versions: &[u32], accept_unkeyed: bool) -> TokenHasher {
+    TokenHasher {
+      current_version: Some(current_version),
+      keys: versions.iter().map(|x| (*x, vec![*x as u8; 32])).collect(),
+      accept_unkeyed,
+    }
+  }
+
+  // a scratch file per test, since they run in parallel
+  fn load_str(
+    name: &str,
+    contents: &str,
+  ) -> Result<TokenHasher, Box<dyn std::error::Error + 'static>> {
+    let path = std::env::temp_dir().join(format!(
+      "token-hash-key-{}-{}.json",
+      name,
+      std::process::id()
+    ));
+    std::fs::write(&path, contents).unwrap();
+    let hasher = TokenHasher::load(&path);
+    std::fs::remove_file(&path).unwrap();
+    hasher
+  }
+
+  #[test]
+  fn prefixes_tokens_by_kind() {
+    let token = TokenHasher::default().gen_token(TokenKind::PasswordReset);
+    assert!(token.starts_with("inxpr_"));
+    assert!(token.len() > 40);
+    assert_ne!(
+      token,
+      TokenHasher::default().gen_token(TokenKind::PasswordReset)
+    );
+  }
+
+  #[test]
+  fn hashes_with_sha256_without_a_key_file() {
+    let hasher = TokenHasher::default();
+    // sha256("abc")
+    assert_eq!(
+      hasher.hash("abc"),
+      base64_url::encode(&hex_bytes(
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+      ))
+    );
+    assert_eq!(hasher.candidate_hashes("abc"), vec![hasher.hash("abc")]);
+  }
+
+  #[test]
+  fn hashes_with_hmac_under_the_current_version() {
+    let hasher = TokenHasher {
+      current_version: Some(1),
+      keys: BTreeMap::from([(1, vec![0x0b; 20])]),
+      accept_unkeyed: false,
+    };
+    // RFC 4231 test case 1
+    assert_eq!(
+      hasher.hash("Hi There"),
+      format!(
+        "v1${}",
+        base64_url::encode(&hex_bytes(
+          "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7"
+        ))
+      )
+    );
+  }
+
+  #[test]
+  fn looks_up_under_every_version() {
+    let old = hasher(1, &[1], true);
+    let unkeyed_hash = TokenHasher::default().hash("token");
+    let old_hash = old.hash("token");
+
+    let rotated = hasher(2, &[1, 2], true);
+    let new_hash = rotated.hash("token");
+    assert!(new_hash.starts_with("v2$"));
+    assert_eq!(
+      rotated.candidate_hashes("token"),
+      vec![new_hash.clone(), old_hash.clone(), unkeyed_hash.clone()]
+    );
+    assert!(rotated.verify("token", &old_hash));
+    assert!(rotated.verify("token", &unkeyed_hash));
+    assert!(!rotated.verify("other token", &new_hash));
+
+    let strict = hasher(2, &[2], false);
+    assert!(strict.verify("token", &new_hash));
+    assert!(!strict.verify("token", &old_hash));
+    assert!(!strict.verify("token", &unkeyed_hash));
+  }
+
+  #[test]
+  fn seals_and_unseals() {
+    let hasher = hasher(2, &[1, 2], true);
+    let sealed = hasher.seal("<a href=\"/reset?key=inxpr_secret\">reset</a>");
+    assert!(sealed.starts_with("$sealed$v2$"));
+    assert!(!sealed.contains("inxpr_secret"));
+    // a fresh nonce each time
+    assert_ne!(
+      sealed,
+      hasher.seal("<a href=\"/reset?key=inxpr_secret\">reset</a>")
+    );
+    assert_eq!(
+      hasher.unseal(&sealed).unwrap(),
+      "<a href=\"/reset?key=inxpr_secret\">reset</a>"
+    );
+
+    // still readable once a new version is current, but not once its version is removed
+    let old_sealed = self::hasher(1, &[1], true).seal("hello");
+    assert_eq!(hasher.unseal(&old_sealed).unwrap(), "hello");
+    assert!(matches!(
+      self::hasher(2, &[2], true).unseal(&old_sealed),
+      Err(UnsealError::UnknownVersion)
+    ));
+    assert!(matches!(
+      TokenHasher::default().unseal(&old_sealed),
+      Err(UnsealError::UnknownVersion)
+    ));
+  }
+
+  #[test]
+  fn leaves_text_alone_without_a_key_file() {
+    let hasher = TokenHasher::default();
+    assert_eq!(hasher.seal("hello"), "hello");
+    // and emails queued before there was a key file can still be sent
+    assert_eq!(
+      self::hasher(1, &[1], true).unseal("hello").unwrap(),
+      "hello"
+    );
+  }
+
+  #[test]
+  fn refuses_tampered_sealed_text() {
+    let hasher = hasher(1, &[1], true);
+    let sealed = hasher.seal("hello");
+    let mut bytes = base64_url::decode(&sealed["$sealed$v1$".len()..]).unwrap();
+    *bytes.last_mut().unwrap() ^= 1;
+    let tampered = format!("$sealed$v1${}", base64_url::encode(&bytes));
+
+    for text in [
+      tampered.as_str(),
+      "$sealed$v1$",
+      "$sealed$vx$AAAA",
+      "$sealed$v1",
+    ] {
+      assert!(
+        matches!(hasher.unseal(text), Err(UnsealError::Corrupt)),
+        "{} was accepted",
+        text
+      );
+    }
+  }
+
+  #[test]
+  fn loads_a_key_file() {
+    let secret = base64_url::encode(&[7u8; 32]);
+    let hasher = load_str(
+      "valid",
+      &format!(
+        r#"{{ "currentVersion": 2, "keys": {{ "1": "{0}", "2": "{0}" }}, "acceptUnkeyed": false }}"#,
+        secret
+      ),
+    )
+    .unwrap();
+    assert_eq!(hasher.current_version, Some(2));
+    assert_eq!(hasher.keys.len(), 2);
+    assert!(!hasher.accept_unkeyed);
+
+    // unkeyed hashes are accepted unless turned off
+    let hasher = load_str(
+      "default-unkeyed",
+      &format!(
+        r#"{{ "currentVersion": 1, "keys": {{ "1": "{}" }} }}"#,
+        secret
+      ),
+    )
+    .unwrap();
+    assert!(hasher.accept_unkeyed);
+  }
+
+  #[test]
+  fn rejects_bad_key_files() {
+    let secret = base64_url::encode(&[7u8; 32]);
+    let short_secret = base64_url::encode(&[7u8; 16]);
+    for (name, contents) in [
+      (
+        "missing-current",
+        format!(
+          r#"{{ "currentVersion": 2, "keys": {{ "1": "{}" }} }}"#,
+          secret
+        ),
+      ),
+      (
+        "short-secret",
+        format!(
+          r#"{{ "currentVersion": 1, "keys": {{ "1": "{}" }} }}"#,
+          short_secret
+        ),
+      ),
+      (
+        "bad-version",
+        format!(
+          r#"{{ "currentVersion": 1, "keys": {{ "one": "{}" }} }}"#,
+          secret
+        ),
+      ),
+    ] {
+      assert!(load_str(name, &contents).is_err(), "{} was accepted", name);
+    }
+  }
+
+  fn hex_bytes(hex: &str) -> Vec<u8> {
+    (0..hex.len())
+      .step_by(2)
+      .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+      .collect()
+  }
+}
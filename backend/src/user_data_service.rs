@@ -11,6 +11,7 @@ impl From<tokio_postgres::row::Row> for UserData {
       dateofbirth: row.get("dateofbirth"),
       username: row.get("username"),
       realname: row.get("realname"),
+      country: row.get("country"),
     }
   }
 }
@@ -21,6 +22,7 @@ pub async fn add(
   dateofbirth: i64,
   username: String,
   realname: String,
+  country: Option<String>,
 ) -> Result<UserData, tokio_postgres::Error> {
   let row = con
     .query_one(
@@ -29,9 +31,10 @@ pub async fn add(
         creator_user_id,
         dateofbirth,
         username,
-        realname
+        realname,
+        country
        )
-       VALUES($1, $2, $3, $4)
+       VALUES($1, $2, $3, $4, $5)
        RETURNING user_data_id, creation_time
       ",
       &[
@@ -39,6 +42,7 @@ pub async fn add(
         &dateofbirth,
         &username,
         &realname,
+        &country,
       ],
     )
     .await?;
@@ -51,6 +55,7 @@ pub async fn add(
     dateofbirth,
     username,
     realname,
+    country,
   })
 }
 
@@ -87,6 +92,27 @@ pub async fn get_by_username(
   Ok(result)
 }
 
+// gets every version of the user data by user_id
+pub async fn get_all_by_user_id(
+  con: &mut impl GenericClient,
+  user_id: i64,
+) -> Result<Vec<UserData>, tokio_postgres::Error> {
+  let results = con
+    .query(
+      "SELECT ud.* FROM user_data_t ud
+       WHERE ud.creator_user_id = $1
+       ORDER BY ud.user_data_id
+      ",
+      &[&user_id],
+    )
+    .await?
+    .into_iter()
+    .map(|x| x.into())
+    .collect();
+
+  Ok(results)
+}
+
 pub async fn query(
   con: &mut impl GenericClient,
   props: auth_service_api::request::UserDataViewProps,
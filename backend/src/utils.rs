@@ -1,6 +1,9 @@
 use rand::{thread_rng, Rng};
 use sha2::{Digest, Sha256};
 use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::password_hash_format::{self, HashFormat, VerifyError};
+
 pub fn current_time_millis() -> i64 {
   let since_the_epoch = SystemTime::now()
     .duration_since(UNIX_EPOCH)
@@ -14,6 +17,11 @@ pub fn gen_random_string() -> String {
   base64_url::encode(&thread_rng().gen::<[u8; 32]>())
 }
 
+// a six digit code, short enough to type in by hand
+pub fn gen_verification_code() -> String {
+  format!("{:06}", thread_rng().gen_range(0..1_000_000))
+}
+
 pub fn hash_str(key: &str) -> String {
   let mut hasher = Sha256::new();
   hasher.update(key);
@@ -21,14 +29,6 @@ pub fn hash_str(key: &str) -> String {
   base64_url::encode(&result)
 }
 
-pub fn is_secure_password(password: &str) -> bool {
-  let len = password.len();
-
-  let numdigits = password.matches(char::is_numeric).count();
-
-  len >= 8 && numdigits > 0
-}
-
 pub fn is_username_valid(username: &str) -> bool {
   // length must be 0..=20
   if username.len() == 0 || username.len() > 20 {
@@ -49,17 +49,114 @@ pub fn is_realname_valid(realname: &str) -> bool {
   return !realname.is_empty();
 }
 
-pub fn verify_password(password: &str, password_hash: &str) -> Result<bool, argon2::Error> {
-  argon2::verify_encoded(password_hash, password.as_bytes())
+// drops the spaces, dashes, dots and brackets people write phone numbers with
+pub fn normalize_phone(phone: &str) -> String {
+  phone
+    .chars()
+    .filter(|x| !matches!(x, ' ' | '-' | '.' | '(' | ')'))
+    .collect()
+}
+
+// an E.164 number: a + then up to 15 digits, the first of which isn't 0
+pub fn is_phone_valid(phone: &str) -> bool {
+  match phone.strip_prefix('+') {
+    Some(digits) => {
+      (8..=15).contains(&digits.len())
+        && digits.chars().all(|x| x.is_ascii_digit())
+        && !digits.starts_with('0')
+    }
+    None => false,
+  }
 }
 
-pub fn hash_password(password: &str) -> Result<String, argon2::Error> {
+// ISO 3166-1 alpha-2 country code, optionally followed by an ISO 3166-2 subdivision (eg "ES-CT")
+pub fn is_country_valid(country: &str) -> bool {
+  let mut parts = country.splitn(2, '-');
+
+  let country_code = parts.next().unwrap_or("");
+  if country_code.len() != 2 || !country_code.chars().all(|x| x.is_ascii_uppercase()) {
+    return false;
+  }
+
+  match parts.next() {
+    Some(subdivision) => {
+      !subdivision.is_empty()
+        && subdivision.len() <= 3
+        && subdivision
+          .chars()
+          .all(|x| x.is_ascii_uppercase() || x.is_ascii_digit())
+    }
+    None => true,
+  }
+}
+
+// an ISO 639-1 language code, optionally followed by an ISO 3166-1 region (eg "es-MX")
+pub fn is_locale_valid(locale: &str) -> bool {
+  let mut parts = locale.splitn(2, '-');
+
+  let language = parts.next().unwrap_or("");
+  if language.len() != 2 || !language.chars().all(|x| x.is_ascii_lowercase()) {
+    return false;
+  }
+
+  match parts.next() {
+    Some(region) => region.len() == 2 && region.chars().all(|x| x.is_ascii_uppercase()),
+    None => true,
+  }
+}
+
+// the secret is the pepper, empty if there is none, see pepper.rs
+// hashes imported from older systems are never peppered, see password_hash_format.rs
+pub fn verify_password(
+  password: &str,
+  password_hash: &str,
+  secret: &[u8],
+) -> Result<bool, VerifyError> {
+  match HashFormat::of(password_hash) {
+    Some(HashFormat::Argon2) => Ok(argon2::verify_encoded_ext(
+      password_hash,
+      password.as_bytes(),
+      secret,
+      &[],
+    )?),
+    Some(_) => password_hash_format::verify_legacy(password, password_hash),
+    None => Err(VerifyError::UnknownFormat),
+  }
+}
+
+pub fn hash_password(password: &str, secret: &[u8]) -> Result<String, argon2::Error> {
   argon2::hash_encoded(
     // password
     password.as_bytes(),
     // salt
     &thread_rng().gen::<[u8; 32]>(),
     //config
-    &argon2::Config::default(),
+    &argon2::Config {
+      secret,
+      ..argon2::Config::default()
+    },
   )
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn normalizes_written_phone_numbers() {
+    assert_eq!(normalize_phone("+1 (555) 555-0123"), "+15555550123");
+    assert_eq!(normalize_phone("+44 20.7946.0018"), "+442079460018");
+  }
+
+  #[test]
+  fn accepts_only_e164_phone_numbers() {
+    assert!(is_phone_valid("+15555550123"));
+    assert!(is_phone_valid("+123456789012345"));
+    assert!(!is_phone_valid("15555550123"));
+    assert!(!is_phone_valid("+1234567"));
+    assert!(!is_phone_valid("+1234567890123456"));
+    assert!(!is_phone_valid("+05555550123"));
+    assert!(!is_phone_valid("+1555555O123"));
+    assert!(!is_phone_valid("+1 555 555 0123"));
+  }
+}
@@ -67,6 +67,43 @@ pub async fn get_by_verification_challenge_key_hash(
   Ok(result)
 }
 
+// the hashes are every hash the key may have been stored under, see token_hasher.rs
+pub async fn get_by_verification_challenge_key_hashes(
+  con: &mut impl GenericClient,
+  verification_challenge_key_hashes: &[String],
+) -> Result<Option<VerificationChallenge>, tokio_postgres::Error> {
+  let result = con
+    .query_opt(
+      "SELECT * FROM verification_challenge_t WHERE verification_challenge_key_hash=ANY($1)",
+      &[&verification_challenge_key_hashes],
+    )
+    .await?
+    .map(|row| row.into());
+
+  Ok(result)
+}
+
+// gets every verification challenge made by the user
+pub async fn get_all_by_user_id(
+  con: &mut impl GenericClient,
+  user_id: i64,
+) -> Result<Vec<VerificationChallenge>, tokio_postgres::Error> {
+  let results = con
+    .query(
+      "SELECT vc.* FROM verification_challenge_t vc
+       WHERE vc.creator_user_id = $1
+       ORDER BY vc.creation_time
+      ",
+      &[&user_id],
+    )
+    .await?
+    .into_iter()
+    .map(|x| x.into())
+    .collect();
+
+  Ok(results)
+}
+
 pub async fn get_num_challenges_by_creator_between(
   con: &mut impl GenericClient,
   creator_user_id: i64,
@@ -90,3 +127,67 @@ pub async fn get_num_challenges_by_creator_between(
 
   Ok(time)
 }
+
+// gets the parent permission requests sent to this email that haven't been answered yet
+pub async fn get_pending_parent_by_email(
+  con: &mut impl GenericClient,
+  email: &str,
+  min_time: i64,
+) -> Result<Vec<VerificationChallenge>, tokio_postgres::Error> {
+  let results = con
+    .query(
+      "SELECT vc.* FROM verification_challenge_t vc
+       WHERE vc.to_parent = true
+       AND lower(vc.email) = lower($1)
+       AND vc.creation_time >= $2
+       AND NOT EXISTS (
+         SELECT 1 FROM email_t e
+         WHERE e.verification_challenge_key_hash = vc.verification_challenge_key_hash
+       )
+       ORDER BY vc.creation_time
+      ",
+      &[&email, &min_time],
+    )
+    .await?
+    .into_iter()
+    .map(|row| row.into())
+    .collect();
+
+  Ok(results)
+}
+
+// gets the challenges behind every own email that is still held by some user
+pub async fn get_all_held_own(
+  con: &mut impl GenericClient,
+) -> Result<Vec<VerificationChallenge>, tokio_postgres::Error> {
+  let results = con
+    .query(
+      "SELECT vc.* FROM verification_challenge_t vc
+       JOIN own_email_v e USING(verification_challenge_key_hash)
+       ORDER BY vc.creator_user_id, vc.creation_time
+      ",
+      &[],
+    )
+    .await?
+    .into_iter()
+    .map(|row| row.into())
+    .collect();
+
+  Ok(results)
+}
+
+// only used when migrating addresses to their normalized form, challenges are otherwise never changed
+pub async fn update_email(
+  con: &mut impl GenericClient,
+  verification_challenge_key_hash: &str,
+  email: &str,
+) -> Result<(), tokio_postgres::Error> {
+  con
+    .execute(
+      "UPDATE verification_challenge_t SET email=$2 WHERE verification_challenge_key_hash=$1",
+      &[&verification_challenge_key_hash, &email],
+    )
+    .await?;
+
+  Ok(())
+}
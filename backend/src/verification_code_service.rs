@@ -0,0 +1,153 @@
+use super::db_types::*;
+use tokio_postgres::GenericClient;
+
+impl From<tokio_postgres::row::Row> for VerificationCode {
+  // select * from verification_code order only, otherwise it will fail
+  fn from(row: tokio_postgres::row::Row) -> VerificationCode {
+    VerificationCode {
+      verification_challenge_key_hash: row.get("verification_challenge_key_hash"),
+      creation_time: row.get("creation_time"),
+      api_key_hash: row.get("api_key_hash"),
+      verification_code_hash: row.get("verification_code_hash"),
+    }
+  }
+}
+
+pub async fn add(
+  con: &mut impl GenericClient,
+  verification_challenge_key_hash: String,
+  api_key_hash: String,
+  verification_code_hash: String,
+) -> Result<VerificationCode, tokio_postgres::Error> {
+  let row = con
+    .query_one(
+      "INSERT INTO
+       verification_code_t(
+         verification_challenge_key_hash,
+         api_key_hash,
+         verification_code_hash
+       )
+       VALUES ($1, $2, $3)
+       RETURNING creation_time
+      ",
+      &[
+        &verification_challenge_key_hash,
+        &api_key_hash,
+        &verification_code_hash,
+      ],
+    )
+    .await?;
+
+  Ok(VerificationCode {
+    verification_challenge_key_hash,
+    creation_time: row.get(0),
+    api_key_hash,
+    verification_code_hash,
+  })
+}
+
+// gets the code most recently sent for this api key, asking for a new code replaces the old one
+pub async fn get_latest_by_api_key_hash(
+  con: &mut impl GenericClient,
+  api_key_hash: &str,
+) -> Result<Option<VerificationCode>, tokio_postgres::Error> {
+  let result = con
+    .query_opt(
+      "SELECT * FROM verification_code_t
+       WHERE api_key_hash=$1
+       ORDER BY creation_time DESC
+       LIMIT 1
+      ",
+      &[&api_key_hash],
+    )
+    .await?
+    .map(|row| row.into());
+
+  Ok(result)
+}
+
+pub async fn add_attempt(
+  con: &mut impl GenericClient,
+  verification_challenge_key_hash: &str,
+  success: bool,
+) -> Result<(), tokio_postgres::Error> {
+  con
+    .execute(
+      "INSERT INTO
+       verification_code_attempt_t(
+         verification_challenge_key_hash,
+         success
+       )
+       VALUES ($1, $2)
+      ",
+      &[&verification_challenge_key_hash, &success],
+    )
+    .await?;
+
+  Ok(())
+}
+
+pub async fn get_num_failed_attempts(
+  con: &mut impl GenericClient,
+  verification_challenge_key_hash: &str,
+) -> Result<i64, tokio_postgres::Error> {
+  let count: i64 = con
+    .query_one(
+      "SELECT count(*) FROM verification_code_attempt_t
+       WHERE verification_challenge_key_hash=$1
+       AND success=false
+      ",
+      &[&verification_challenge_key_hash],
+    )
+    .await?
+    .get(0);
+
+  Ok(count)
+}
+
+// the number of codes the user asked for in the time range
+pub async fn get_num_by_creator_between(
+  con: &mut impl GenericClient,
+  creator_user_id: i64,
+  min_creation_time: i64,
+  max_creation_time: i64,
+) -> Result<i64, tokio_postgres::Error> {
+  let count: i64 = con
+    .query_one(
+      "SELECT count(*) FROM verification_code_t vco
+       JOIN verification_challenge_t vc USING(verification_challenge_key_hash)
+       WHERE vc.creator_user_id=$1
+       AND vco.creation_time >= $2
+       AND vco.creation_time <= $3
+      ",
+      &[&creator_user_id, &min_creation_time, &max_creation_time],
+    )
+    .await?
+    .get(0);
+
+  Ok(count)
+}
+
+// the number of wrong guesses at any of the user's codes in the time range
+pub async fn get_num_failed_attempts_by_creator_between(
+  con: &mut impl GenericClient,
+  creator_user_id: i64,
+  min_creation_time: i64,
+  max_creation_time: i64,
+) -> Result<i64, tokio_postgres::Error> {
+  let count: i64 = con
+    .query_one(
+      "SELECT count(*) FROM verification_code_attempt_t vca
+       JOIN verification_challenge_t vc USING(verification_challenge_key_hash)
+       WHERE vc.creator_user_id=$1
+       AND vca.success=false
+       AND vca.creation_time >= $2
+       AND vca.creation_time <= $3
+      ",
+      &[&creator_user_id, &min_creation_time, &max_creation_time],
+    )
+    .await?
+    .get(0);
+
+  Ok(count)
+}